@@ -0,0 +1,49 @@
+use criterion::{Criterion, Throughput, black_box, criterion_group, criterion_main};
+use std::time::Duration;
+
+use routbox::config::Config;
+use routbox::key_processor::KeyMappingProcessor;
+
+// Presses and releases of the buttons, scrolls and knob turns of the default config,
+// roughly the mix a session of editing sends
+const STREAM: &[u8] = &[
+    0x02, 0x82, 0x00, 0x80, 0x44, 0x44, 0x44, 0x04, 0x04, 0x22, 0xa2, 0x49, 0x49, 0x09, 0x0a, 0x44,
+    0x8a, 0x03, 0x49, 0x83, 0x2a, 0xaa, 0x37, 0x04, 0xb7, 0x23, 0xa3, 0x55,
+];
+
+fn pipeline(c: &mut Criterion) {
+    let cfg = Config::embedded_default();
+    let dial_keys = cfg.key_map.stateless.values().cloned().collect();
+    let mut processor = KeyMappingProcessor::from_config(&cfg.mappings)
+        .with_profiles(&cfg.profiles)
+        .with_shift_key(cfg.shift_key.clone())
+        .with_pass_through(&cfg.pass_through)
+        .with_dial_dead_zone(
+            dial_keys,
+            cfg.dial_dead_zone,
+            Duration::from_millis(cfg.dial_idle_ms),
+        );
+
+    let mut group = c.benchmark_group("pipeline");
+    group.throughput(Throughput::Elements(STREAM.len() as u64));
+    group.bench_function("decode", |b| {
+        b.iter(|| {
+            for code in STREAM {
+                black_box(cfg.key_map.decode(black_box(*code)));
+            }
+        })
+    });
+    group.bench_function("decode_and_process", |b| {
+        b.iter(|| {
+            for code in STREAM {
+                if let Some(ev) = cfg.key_map.decode(black_box(*code)) {
+                    black_box(processor.process(ev));
+                }
+            }
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, pipeline);
+criterion_main!(benches);
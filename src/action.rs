@@ -0,0 +1,182 @@
+use serde::{Serialize, Serializer};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TourAction {
+    KeyPress(String),
+    KeyClick(String),
+    KeyRelease(String),
+    // types the text as is, independent of the keyboard layout
+    TypeText(String),
+    UiAction(String),
+    OsCommand(OsCommand),
+    // replaces all active profiles with this one
+    SwitchProfile(String),
+    // activates a profile on top of the current one, until `PopProfile`
+    PushProfile(String),
+    PopProfile,
+    // shows a desktop notification
+    Notify { title: String, body: String },
+    // a TourBox key now counts as held (or not) for modifier matching, without output
+    ModifierLock { key: String, locked: bool },
+    // moves or resizes the foreground window
+    WindowAction(WindowOp),
+    // a state variable for `when` conditions changed, without output
+    SetState { name: String, value: i64 },
+    // puts the mouse cursor at this pixel of the main display
+    MouseMoveAbs { x: i32, y: i32 },
+    // closes the device connection and opens it again, re-sending the init command
+    ReinitDevice,
+    // puts the text on the clipboard
+    SetClipboard(String),
+    // sends the paste shortcut, after putting the text on the clipboard if there is one
+    PasteClipboard(Option<String>),
+    // the `feedback` of a mapping that fired: a sound file, or a short beep for `None`
+    PlaySound(Option<String>),
+}
+
+// Common system actions from `os:<name>` mappings, run by the platform's own tools
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OsCommand {
+    LockScreen,
+    Screenshot,
+    SleepDisplay,
+}
+
+impl OsCommand {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "lock_screen" => Some(OsCommand::LockScreen),
+            "screenshot" => Some(OsCommand::Screenshot),
+            "sleep_display" => Some(OsCommand::SleepDisplay),
+            _ => None,
+        }
+    }
+}
+
+// Window management from `win:<name>` mappings, applied to the foreground window
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WindowOp {
+    SnapLeft,
+    SnapRight,
+    Maximize,
+    Minimize,
+    NextMonitor,
+}
+
+impl WindowOp {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "snap_left" => Some(WindowOp::SnapLeft),
+            "snap_right" => Some(WindowOp::SnapRight),
+            "maximize" => Some(WindowOp::Maximize),
+            "minimize" => Some(WindowOp::Minimize),
+            "next_monitor" => Some(WindowOp::NextMonitor),
+            _ => None,
+        }
+    }
+}
+
+// The serialized form of `TourAction`, `{"type": "KeyClick", "key": "CTRL_L+C"}`. Kept
+// apart so changes to the enum don't change what other programs read.
+#[derive(Serialize)]
+#[serde(tag = "type")]
+enum TaggedAction<'a> {
+    KeyPress { key: &'a str },
+    KeyClick { key: &'a str },
+    KeyRelease { key: &'a str },
+    TypeText { text: &'a str },
+    UiAction { name: &'a str },
+    OsCommand { command: OsCommand },
+    SwitchProfile { profile: &'a str },
+    PushProfile { profile: &'a str },
+    PopProfile,
+    Notify { title: &'a str, body: &'a str },
+    ModifierLock { key: &'a str, locked: bool },
+    WindowAction { op: WindowOp },
+    SetState { name: &'a str, value: i64 },
+    MouseMoveAbs { x: i32, y: i32 },
+    ReinitDevice,
+    SetClipboard { text: &'a str },
+    PasteClipboard { text: Option<&'a str> },
+    PlaySound { sound: Option<&'a str> },
+}
+
+impl Serialize for TourAction {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let tagged = match self {
+            TourAction::KeyPress(key) => TaggedAction::KeyPress { key },
+            TourAction::KeyClick(key) => TaggedAction::KeyClick { key },
+            TourAction::KeyRelease(key) => TaggedAction::KeyRelease { key },
+            TourAction::TypeText(text) => TaggedAction::TypeText { text },
+            TourAction::UiAction(name) => TaggedAction::UiAction { name },
+            TourAction::OsCommand(command) => TaggedAction::OsCommand { command: *command },
+            TourAction::SwitchProfile(profile) => TaggedAction::SwitchProfile { profile },
+            TourAction::PushProfile(profile) => TaggedAction::PushProfile { profile },
+            TourAction::PopProfile => TaggedAction::PopProfile,
+            TourAction::Notify { title, body } => TaggedAction::Notify { title, body },
+            TourAction::ModifierLock { key, locked } => TaggedAction::ModifierLock {
+                key,
+                locked: *locked,
+            },
+            TourAction::WindowAction(op) => TaggedAction::WindowAction { op: *op },
+            TourAction::SetState { name, value } => TaggedAction::SetState {
+                name,
+                value: *value,
+            },
+            TourAction::MouseMoveAbs { x, y } => TaggedAction::MouseMoveAbs { x: *x, y: *y },
+            TourAction::ReinitDevice => TaggedAction::ReinitDevice,
+            TourAction::SetClipboard(text) => TaggedAction::SetClipboard { text },
+            TourAction::PasteClipboard(text) => TaggedAction::PasteClipboard {
+                text: text.as_deref(),
+            },
+            TourAction::PlaySound(sound) => TaggedAction::PlaySound {
+                sound: sound.as_deref(),
+            },
+        };
+        tagged.serialize(serializer)
+    }
+}
+
+// Where the actions produced by the processor end up, e.g. the OS keyboard
+pub trait ActionSink {
+    type Error;
+
+    fn send(&mut self, action: &TourAction) -> Result<(), Self::Error>;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn actions_serialize_with_a_type_tag() {
+        let json = |action: TourAction| serde_json::to_value(action).unwrap();
+
+        assert_eq!(
+            json(TourAction::KeyClick("CTRL_L+C".into())),
+            json!({ "type": "KeyClick", "key": "CTRL_L+C" })
+        );
+        assert_eq!(
+            json(TourAction::OsCommand(OsCommand::LockScreen)),
+            json!({ "type": "OsCommand", "command": "lock_screen" })
+        );
+        assert_eq!(
+            json(TourAction::ModifierLock {
+                key: "SIDE".into(),
+                locked: true
+            }),
+            json!({ "type": "ModifierLock", "key": "SIDE", "locked": true })
+        );
+        assert_eq!(
+            json(TourAction::PopProfile),
+            json!({ "type": "PopProfile" })
+        );
+        assert_eq!(
+            json(TourAction::PlaySound(None)),
+            json!({ "type": "PlaySound", "sound": null })
+        );
+    }
+}
@@ -1,55 +1,438 @@
 use eframe::egui;
-use log::error;
+use log::warn;
+use std::collections::BTreeSet;
+use std::path::PathBuf;
 use std::sync::mpsc::{self, Receiver};
+use std::time::{Duration, Instant};
 
-use crate::key_sender::TourAction;
+use routbox::action::TourAction;
+use routbox::config::{Config, UiConfig};
+use routbox::event::InputEvent;
+use routbox::key_processor::{DEFAULT_PROFILE, MappingSwitches, ResetRequest};
+
+use crate::os_actions;
+use crate::stats::{SharedStats, StatsOrder};
+use crate::status::SharedStatus;
+
+// What the key thread tells the window about
+pub enum UiEvent {
+    Action(TourAction),
+    // a device event as it arrived, before it was turned into actions
+    Input(InputEvent),
+    // an action that could not be carried out, e.g. an unknown key
+    Failed(String),
+}
+
+pub enum LogEntry {
+    Action(TourAction),
+    // why the action before it failed
+    Failed(String),
+    // marks where a profile change happened, with the profile active from there on
+    Profile(String),
+}
+
+// What the key thread reported so far, shown by the window and the terminal UI alike
+#[derive(Default)]
+pub struct View {
+    pub active_keys: Vec<LogEntry>,
+    // follows the processor's profile stack to know which profile is active
+    profile_stack: Vec<String>,
+    // TourBox keys locked on as modifiers by `lock:` mappings
+    pub locked_keys: BTreeSet<String>,
+    // TourBox keys physically held right now
+    pub pressed_keys: BTreeSet<String>,
+    // `None` until the device connected or failed for the first time
+    pub connected: Option<bool>,
+    // the last status reports in percent, `None` for devices that never send them
+    pub battery: Option<u8>,
+    pub link_quality: Option<u8>,
+    // `ui` of the config, for the names keys are shown with
+    pub ui: UiConfig,
+}
+
+// The mappings of the config as the window lists them, each next to its switch
+pub struct MappingTable {
+    rows: Vec<MappingRow>,
+    switches: MappingSwitches,
+}
+
+impl MappingTable {
+    pub fn new(cfg: &Config, switches: MappingSwitches) -> Self {
+        Self {
+            rows: MappingRow::list(cfg),
+            switches,
+        }
+    }
+}
+
+struct MappingRow {
+    profile: Option<String>,
+    index: usize,
+    label: String,
+}
+
+impl MappingRow {
+    // The top level mappings first, then those of every profile by name
+    fn list(cfg: &Config) -> Vec<Self> {
+        let mut profiles: Vec<_> = cfg.profiles.iter().collect();
+        profiles.sort_by_key(|(name, _)| *name);
+        let lists = std::iter::once((None, &cfg.mappings))
+            .chain(profiles.into_iter().map(|(name, ms)| (Some(name), ms)));
+        lists
+            .flat_map(|(profile, mappings)| {
+                mappings.iter().enumerate().map(move |(index, m)| Self {
+                    profile: profile.cloned(),
+                    index,
+                    label: format!(
+                        "{} → {} ({:?})",
+                        cfg.ui.label_keys(&m.keys),
+                        m.action.join(", "),
+                        m.trigger
+                    ),
+                })
+            })
+            .collect()
+    }
+}
+
+// What the window shows of the config
+pub struct ConfigView {
+    // the config file, `None` when it came from stdin or is the built-in default
+    pub path: Option<PathBuf>,
+    pub mappings: MappingTable,
+    pub ui: UiConfig,
+}
+
+// the window redraws at most this often for new events, about 60 times a second
+const MIN_REPAINT_INTERVAL: Duration = Duration::from_millis(16);
 
 pub struct TourApp {
-    active_keys: Vec<TourAction>,
-    receiver: Receiver<TourAction>,
+    view: View,
+    receiver: Receiver<UiEvent>,
+    status: SharedStatus,
+    stats: SharedStats,
+    stats_order: StatsOrder,
+    // the config file, `None` when it came from stdin or is the built-in default
+    config_path: Option<PathBuf>,
+    // switched on and off until the next start, the config file stays as it is
+    mappings: MappingTable,
+    reset: ResetRequest,
 }
 
 impl TourApp {
-    pub fn new(app_receiver: Receiver<TourAction>, ctx: egui::Context) -> Self {
+    pub fn new(
+        app_receiver: Receiver<UiEvent>,
+        status: SharedStatus,
+        stats: SharedStats,
+        config: ConfigView,
+        reset: ResetRequest,
+        ctx: egui::Context,
+    ) -> Self {
         let (sender, receiver) = mpsc::channel();
         std::thread::spawn(move || {
-            loop {
-                let a = app_receiver.recv();
-                match a {
-                    Ok(k) => {
-                        sender.send(k).expect("Channel from app to ui is broken");
-                        ctx.request_repaint();
+            let mut last_repaint: Option<Instant> = None;
+            // ends once either side is gone, which is how the app exits
+            while let Ok(k) = app_receiver.recv() {
+                if sender.send(k).is_err() {
+                    break;
+                }
+                // a fast dial spin is drawn at most once per interval, the frame
+                // takes everything that queued up since the last one
+                let now = Instant::now();
+                match last_repaint.map(|at| now.duration_since(at)) {
+                    Some(since) if since < MIN_REPAINT_INTERVAL => {
+                        ctx.request_repaint_after(MIN_REPAINT_INTERVAL - since);
                     }
-                    Err(e) => {
-                        error!("{e}");
-                        panic!();
+                    _ => {
+                        last_repaint = Some(now);
+                        ctx.request_repaint();
                     }
                 }
             }
         });
         Self {
             receiver,
-            active_keys: Vec::new(),
+            view: View::with_ui(config.ui),
+            status,
+            stats,
+            stats_order: StatsOrder::ByCount,
+            config_path: config.path,
+            mappings: config.mappings,
+            reset,
         }
     }
 }
 
+impl View {
+    pub fn with_ui(ui: UiConfig) -> Self {
+        Self {
+            ui,
+            ..Default::default()
+        }
+    }
+
+    pub fn apply(&mut self, event: UiEvent) {
+        match event {
+            UiEvent::Action(action) => self.log(action),
+            UiEvent::Input(ev) => self.track(ev),
+            UiEvent::Failed(message) => self.active_keys.push(LogEntry::Failed(message)),
+        }
+    }
+
+    // Battery and link quality, if the device reported any
+    pub fn wireless(&self) -> Option<String> {
+        let parts: Vec<String> = [("Battery", self.battery), ("Link", self.link_quality)]
+            .into_iter()
+            .filter_map(|(name, value)| Some(format!("{}: {}%", name, value?)))
+            .collect();
+        (!parts.is_empty()).then(|| parts.join("   "))
+    }
+
+    pub fn profile(&self) -> &str {
+        self.profile_stack
+            .last()
+            .map_or(DEFAULT_PROFILE, |p| p.as_str())
+    }
+
+    fn track(&mut self, ev: InputEvent) {
+        match ev {
+            InputEvent::KeyPressed(k) => {
+                self.pressed_keys.insert(k);
+            }
+            InputEvent::KeyReleased(k) => {
+                self.pressed_keys.remove(&k);
+            }
+            // nothing is held on a device that just (re)connected
+            InputEvent::Connected | InputEvent::Disconnected => {
+                self.connected = Some(ev == InputEvent::Connected);
+                self.pressed_keys.clear();
+            }
+            InputEvent::Unknown(_) | InputEvent::ResetRequested => {}
+            InputEvent::Battery(level) => self.battery = Some(level),
+            InputEvent::LinkQuality(quality) => self.link_quality = Some(quality),
+        }
+    }
+
+    fn log(&mut self, action: TourAction) {
+        let profile_changed = match &action {
+            TourAction::SwitchProfile(name) => {
+                self.profile_stack.clear();
+                if name != DEFAULT_PROFILE {
+                    self.profile_stack.push(name.clone());
+                }
+                true
+            }
+            TourAction::PushProfile(name) => {
+                self.profile_stack.push(name.clone());
+                true
+            }
+            TourAction::PopProfile => {
+                self.profile_stack.pop();
+                true
+            }
+            TourAction::ModifierLock { key, locked } => {
+                if *locked {
+                    self.locked_keys.insert(key.clone());
+                } else {
+                    self.locked_keys.remove(key);
+                }
+                false
+            }
+            _ => false,
+        };
+        self.active_keys.push(LogEntry::Action(action));
+        if profile_changed {
+            let profile = self.profile().to_string();
+            self.active_keys.push(LogEntry::Profile(profile));
+        }
+    }
+}
+
+// Icon and short text for an entry of the action log, with the TourBox keys and
+// state variables named by their `ui.labels`
+pub fn describe(action: &TourAction, ui: &UiConfig) -> (&'static str, String) {
+    match action {
+        TourAction::KeyClick(keys) if keys.to_uppercase().starts_with("WHEEL_") => {
+            ("🖱", keys.clone())
+        }
+        TourAction::KeyClick(keys) => ("⌨", keys.clone()),
+        TourAction::KeyPress(key) => ("⬇", key.clone()),
+        TourAction::KeyRelease(key) => ("⬆", key.clone()),
+        TourAction::TypeText(text) => ("✏", format!("\"{}\"", text)),
+        TourAction::UiAction(name) => ("🔘", name.clone()),
+        TourAction::OsCommand(cmd) => ("⚙", format!("{:?}", cmd)),
+        TourAction::WindowAction(op) => ("🗔", format!("{:?}", op)),
+        TourAction::Notify { title, .. } => ("💬", title.clone()),
+        TourAction::SwitchProfile(name) => ("📂", name.clone()),
+        TourAction::PushProfile(name) => ("📂", format!("+ {}", name)),
+        TourAction::PopProfile => ("📂", "back".to_string()),
+        TourAction::ModifierLock { key, locked: true } => ("🔒", ui.label(key).to_string()),
+        TourAction::ModifierLock { key, locked: false } => ("🔓", ui.label(key).to_string()),
+        TourAction::SetState { name, value } => ("🔀", format!("{} = {}", ui.label(name), value)),
+        TourAction::MouseMoveAbs { x, y } => ("🖱", format!("to {}, {}", x, y)),
+        TourAction::ReinitDevice => ("🔌", "reinitialize device".to_string()),
+        TourAction::SetClipboard(text) => ("📋", format!("copy \"{}\"", text)),
+        TourAction::PasteClipboard(None) => ("📋", "paste".to_string()),
+        TourAction::PasteClipboard(Some(text)) => ("📋", format!("paste \"{}\"", text)),
+        TourAction::PlaySound(None) => ("🔔", "beep".to_string()),
+        TourAction::PlaySound(Some(path)) => ("🔔", path.clone()),
+    }
+}
+
 impl eframe::App for TourApp {
     fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
-        if let Ok(k) = self.receiver.try_recv() {
-            self.active_keys.push(k);
+        // only called when the relay thread or the user asks for a repaint, so take
+        // everything that arrived since the last frame instead of one action per frame
+        while let Ok(event) = self.receiver.try_recv() {
+            self.view.apply(event);
         }
 
         egui::CentralPanel::default().show(ctx, |ui| {
-            ui.heading("TourBox Command Receiver");
+            ui.horizontal(|ui| {
+                // switches between dark and light until the next start
+                egui::widgets::global_dark_light_mode_switch(ui);
+                ui.heading("TourBox Command Receiver");
+                let edit = ui
+                    .add_enabled(self.config_path.is_some(), egui::Button::new("Edit Config"))
+                    .on_disabled_hover_text("No config file was loaded");
+                if edit.clicked()
+                    && let Some(path) = &self.config_path
+                    && let Err(e) = os_actions::open_path(path)
+                {
+                    warn!("Could not open '{}': {}", path.display(), e);
+                }
+                let reset =
+                    egui::Button::new(egui::RichText::new("Reset").color(egui::Color32::WHITE))
+                        .fill(egui::Color32::DARK_RED);
+                if ui
+                    .add(reset)
+                    .on_hover_text("Release all keys, unlock locked keys and clear state variables")
+                    .clicked()
+                {
+                    self.reset.request();
+                }
+            });
+            let status = self.status.lock().unwrap();
+            for (thread, error) in &status.failed_threads {
+                ui.colored_label(
+                    egui::Color32::RED,
+                    format!("⚠ The {} thread failed to start: {}", thread, error),
+                );
+            }
+            for (thread, count) in &status.panics {
+                ui.colored_label(
+                    egui::Color32::RED,
+                    format!(
+                        "⚠ The {} thread crashed {} time(s) and was restarted, see the log",
+                        thread, count
+                    ),
+                );
+            }
+            drop(status);
+            ui.horizontal_wrapped(|ui| {
+                ui.label("🖐 Held:");
+                if self.view.pressed_keys.is_empty() {
+                    ui.weak("nothing");
+                }
+                for key in &self.view.pressed_keys {
+                    ui.label(
+                        egui::RichText::new(self.view.ui.label(key))
+                            .strong()
+                            .monospace(),
+                    );
+                }
+            });
+            if !self.view.locked_keys.is_empty() {
+                let locked: Vec<&str> = self
+                    .view
+                    .locked_keys
+                    .iter()
+                    .map(|k| self.view.ui.label(k))
+                    .collect();
+                ui.label(format!("🔒 Locked: {}", locked.join(", ")));
+            }
+            if let Some(wireless) = self.view.wireless() {
+                ui.label(format!("🔋 {}", wireless));
+            }
+            ui.separator();
+            egui::CollapsingHeader::new("Diagnostics").show(ui, |ui| {
+                let report = self.status.lock().unwrap().diagnostics();
+                if ui.button("Copy").clicked() {
+                    ui.output_mut(|o| o.copied_text = report.clone());
+                }
+                ui.monospace(report);
+            });
+            egui::CollapsingHeader::new("Usage").show(ui, |ui| {
+                let mut stats = self.stats.lock().unwrap();
+                if ui.button("Reset").clicked() {
+                    stats.reset();
+                }
+                egui::Grid::new("usage").striped(true).show(ui, |ui| {
+                    // clicking a header sorts by that column
+                    for (header, order) in
+                        [("Key", StatsOrder::ByKey), ("Count", StatsOrder::ByCount)]
+                    {
+                        if ui
+                            .selectable_label(self.stats_order == order, header)
+                            .clicked()
+                        {
+                            self.stats_order = order;
+                        }
+                    }
+                    ui.end_row();
+                    for (key, count) in stats.sorted(self.stats_order) {
+                        ui.label(self.view.ui.label(key));
+                        ui.label(count.to_string());
+                        ui.end_row();
+                    }
+                });
+            });
+            egui::CollapsingHeader::new("Mappings").show(ui, |ui| {
+                let mut profile = None;
+                for row in &self.mappings.rows {
+                    if let Some(name) = &row.profile
+                        && profile != Some(name)
+                    {
+                        ui.strong(format!("Profile: {}", name));
+                    }
+                    profile = row.profile.as_ref();
+                    let mut enabled = self
+                        .mappings
+                        .switches
+                        .is_enabled(profile.map(String::as_str), row.index);
+                    if ui.checkbox(&mut enabled, &row.label).changed() {
+                        self.mappings.switches.set_enabled(
+                            profile.map(String::as_str),
+                            row.index,
+                            enabled,
+                        );
+                    }
+                }
+            });
             ui.separator();
             ui.label("Active Keys:");
             egui::ScrollArea::vertical()
                 .stick_to_bottom(true)
                 .auto_shrink(false)
                 .show(ui, |ui| {
-                    for a in self.active_keys.iter() {
-                        ui.label(format!("{:?}", a));
+                    for entry in self.view.active_keys.iter() {
+                        match entry {
+                            LogEntry::Action(a) => {
+                                let (icon, text) = describe(a, &self.view.ui);
+                                ui.label(format!("{} {}", icon, text))
+                                    .on_hover_text(format!("{:?}", a));
+                            }
+                            LogEntry::Failed(message) => {
+                                ui.colored_label(
+                                    egui::Color32::RED,
+                                    format!("⚠ failed: {}", message),
+                                );
+                            }
+                            LogEntry::Profile(name) => {
+                                ui.separator();
+                                ui.strong(format!("Profile: {}", name));
+                            }
+                        }
                     }
                 });
         });
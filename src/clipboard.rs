@@ -0,0 +1,26 @@
+use std::fmt;
+
+// The system clipboard, opened on first use. It stays open as on Linux the text is only
+// on the clipboard while the program that put it there is running.
+#[derive(Default)]
+pub struct Clipboard {
+    board: Option<arboard::Clipboard>,
+}
+
+impl Clipboard {
+    pub fn set(&mut self, text: &str) -> Result<(), arboard::Error> {
+        let board = match &mut self.board {
+            Some(board) => board,
+            None => self.board.insert(arboard::Clipboard::new()?),
+        };
+        board.set_text(text)
+    }
+}
+
+impl fmt::Debug for Clipboard {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Clipboard")
+            .field("open", &self.board.is_some())
+            .finish()
+    }
+}
@@ -1,83 +1,1859 @@
-use serde::Deserialize;
-use std::collections::HashMap;
-use std::fs::File;
-use std::io;
+use crate::event::InputEvent;
+use crate::key_processor::{DEFAULT_PROFILE, HOST_MODIFIERS, split_chord, split_group};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde_json::error::Category;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::mem;
 use std::path::Path;
+use thiserror::Error;
 
-#[derive(Deserialize, Clone, Debug)]
+#[derive(Deserialize, Serialize, Clone, Debug)]
 pub struct KeyMap {
+    #[serde(deserialize_with = "codes", serialize_with = "sorted")]
     pub stateful: HashMap<String, String>,
+    #[serde(deserialize_with = "codes", serialize_with = "sorted")]
     pub stateless: HashMap<String, String>,
+    // what the device adds to a stateful key's code to signal its release, 0 if it never does
+    #[serde(
+        default = "default_release_offset",
+        deserialize_with = "number::release_offset",
+        serialize_with = "number::hex"
+    )]
+    pub release_offset: u8,
+    // what happens with codes that are in neither map
+    #[serde(default)]
+    pub unknown_code_policy: UnknownCodePolicy,
+    // codes that announce a status report instead of a key, the value is the next byte
+    #[serde(
+        default,
+        deserialize_with = "codes",
+        skip_serializing_if = "HashMap::is_empty",
+        serialize_with = "sorted"
+    )]
+    pub status: HashMap<String, StatusKind>,
 }
 
-#[derive(Deserialize, Clone, Debug, Copy)]
+// What a status code of `KeyMap::status` reports
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum StatusKind {
+    // the charge in percent
+    Battery,
+    // the wireless signal strength in percent
+    LinkQuality,
+}
+
+impl StatusKind {
+    pub fn event(self, value: u8) -> InputEvent {
+        match self {
+            StatusKind::Battery => InputEvent::Battery(value),
+            StatusKind::LinkQuality => InputEvent::LinkQuality(value),
+        }
+    }
+}
+
+fn default_release_offset() -> u8 {
+    0x80
+}
+
+// Written as `warn`, `ignore`, `capture:<file>` or `passthrough`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum UnknownCodePolicy {
+    // logs a warning for every unknown code
+    #[default]
+    Warn,
+    Ignore,
+    // appends every unknown code to the file, one per line
+    Capture(String),
+    // sends the code as a press of a key named after it, like `0x55`
+    Passthrough,
+}
+
+impl Serialize for UnknownCodePolicy {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        match self {
+            UnknownCodePolicy::Warn => serializer.serialize_str("warn"),
+            UnknownCodePolicy::Ignore => serializer.serialize_str("ignore"),
+            UnknownCodePolicy::Capture(path) => {
+                serializer.serialize_str(&format!("capture:{}", path))
+            }
+            UnknownCodePolicy::Passthrough => serializer.serialize_str("passthrough"),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for UnknownCodePolicy {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        match s.as_str() {
+            "warn" => Ok(UnknownCodePolicy::Warn),
+            "ignore" => Ok(UnknownCodePolicy::Ignore),
+            "passthrough" => Ok(UnknownCodePolicy::Passthrough),
+            _ => match s.strip_prefix("capture:") {
+                Some(path) if !path.is_empty() => Ok(UnknownCodePolicy::Capture(path.to_owned())),
+                _ => Err(serde::de::Error::custom(format!(
+                    "invalid `unknown_code_policy` \"{}\", expected `warn`, `ignore`, \
+                     `capture:<file>` or `passthrough`",
+                    s
+                ))),
+            },
+        }
+    }
+}
+
+pub fn code_name(code: u8) -> String {
+    format!("0x{:02x}", code)
+}
+
+// `code_name` on the stack, `decode` looks up every code the device sends
+struct CodeName([u8; 4]);
+
+impl CodeName {
+    fn new(code: u8) -> Self {
+        const HEX: &[u8; 16] = b"0123456789abcdef";
+        Self([
+            b'0',
+            b'x',
+            HEX[(code >> 4) as usize],
+            HEX[(code & 0xf) as usize],
+        ])
+    }
+
+    fn as_str(&self) -> &str {
+        std::str::from_utf8(&self.0).expect("hex digits are ASCII")
+    }
+}
+
+// A code written in hex like `0x1a` or in decimal like `26`
+fn parse_code(name: &str) -> Option<u8> {
+    match name.strip_prefix("0x").or_else(|| name.strip_prefix("0X")) {
+        Some(hex) => u8::from_str_radix(hex, 16).ok(),
+        None => name.parse().ok(),
+    }
+}
+
+// The code as `code_name` writes it, which `decode` looks up. Anything else is kept.
+fn normalized_code(name: &str) -> String {
+    parse_code(name).map_or_else(|| name.to_string(), code_name)
+}
+
+// A map of key map codes, each written the way `normalized_code` gives it
+fn codes<'de, D, V>(deserializer: D) -> Result<HashMap<String, V>, D::Error>
+where
+    D: Deserializer<'de>,
+    V: Deserialize<'de>,
+{
+    let codes = HashMap::<String, V>::deserialize(deserializer)?;
+    Ok(codes
+        .into_iter()
+        .map(|(code, value)| (normalized_code(&code), value))
+        .collect())
+}
+
+impl KeyMap {
+    // The press code whose release `code` would be
+    fn released_code(&self, code: u8) -> Option<u8> {
+        match self.release_offset {
+            0 => None,
+            offset => code.checked_sub(offset),
+        }
+    }
+
+    // Turns a raw code into an event. When maps overlap, a stateful press wins over
+    // a stateful release, which wins over a stateless key, so a held key always gets
+    // its release. `Config::validate` reports such overlaps.
+    pub fn decode(&self, code: u8) -> Option<InputEvent> {
+        let name = CodeName::new(code);
+        if let Some(key) = self.stateful.get(name.as_str()) {
+            Some(InputEvent::KeyPressed(key.clone()))
+        } else if let Some(key) = self
+            .released_code(code)
+            .and_then(|c| self.stateful.get(CodeName::new(c).as_str()))
+        {
+            Some(InputEvent::KeyReleased(key.clone()))
+        } else {
+            self.stateless
+                .get(name.as_str())
+                .map(|key| InputEvent::KeyPressed(key.clone()))
+        }
+    }
+
+    // What `code` announces, if it is a status code
+    pub fn status_kind(&self, code: u8) -> Option<StatusKind> {
+        self.status.get(CodeName::new(code).as_str()).copied()
+    }
+
+    // The code the device sends for a key, its press code for a stateful one
+    pub fn code(&self, key: &str) -> Option<u8> {
+        self.stateful
+            .iter()
+            .chain(&self.stateless)
+            .find(|(_, k)| *k == key)
+            .and_then(|(code, _)| parse_code(code))
+    }
+
+    // Describes every stateless code that `decode` reads as a stateful key instead
+    fn conflicts(&self) -> Vec<String> {
+        let stateful: HashMap<u8, &String> = self
+            .stateful
+            .iter()
+            .filter_map(|(code, key)| Some((parse_code(code)?, key)))
+            .collect();
+        let mut conflicts: Vec<(u8, String)> = self
+            .stateless
+            .iter()
+            .filter_map(|(code, key)| {
+                let code = parse_code(code)?;
+                let msg = if let Some(other) = stateful.get(&code) {
+                    format!(
+                        "code {} is mapped to stateless '{}' and stateful '{}', it is read as a press of '{}'",
+                        code_name(code),
+                        key,
+                        other,
+                        other
+                    )
+                } else {
+                    let other = stateful.get(&self.released_code(code)?)?;
+                    format!(
+                        "code {} is mapped to stateless '{}' but is also the release code of stateful '{}', it is read as a release of '{}'",
+                        code_name(code),
+                        key,
+                        other,
+                        other
+                    )
+                };
+                Some((code, msg))
+            })
+            .collect();
+        conflicts.sort();
+        conflicts.into_iter().map(|(_, msg)| msg).collect()
+    }
+}
+
+#[derive(Clone, Debug, Copy, PartialEq, Eq)]
 pub enum KeyTriggerTiming {
-    #[serde(rename = "on_press")]
     OnPress,
-    #[serde(rename = "on_hold")]
     OnHold,
-    #[serde(rename = "on_release")]
     OnRelease,
+    // fires once the key was pressed `count` times in a row
+    OnMultiTap,
+}
+
+impl KeyTriggerTiming {
+    const VARIANTS: &'static [&'static str] =
+        &["on_press", "on_hold", "on_release", "on_multi_tap"];
+}
+
+impl Serialize for KeyTriggerTiming {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(Self::VARIANTS[*self as usize])
+    }
 }
 
-#[derive(Deserialize, Clone, Debug)]
+// Accepts `on_press`, `press`, `OnPress`, `ON_PRESS`, ... (case and `_` are ignored)
+impl<'de> Deserialize<'de> for KeyTriggerTiming {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        let normalized = s.to_lowercase().replace('_', "");
+        match normalized.strip_prefix("on").unwrap_or(&normalized) {
+            "press" => Ok(KeyTriggerTiming::OnPress),
+            "hold" => Ok(KeyTriggerTiming::OnHold),
+            "release" => Ok(KeyTriggerTiming::OnRelease),
+            "multitap" => Ok(KeyTriggerTiming::OnMultiTap),
+            _ => Err(serde::de::Error::unknown_variant(&s, Self::VARIANTS)),
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
 pub struct KeyMappingConfig {
     pub keys: String,
-    pub action: String,
+    // fired in order, written as one string or a list of them
+    #[serde(deserialize_with = "one_or_many")]
+    pub action: Vec<String>,
     pub trigger: KeyTriggerTiming,
+    // only fires while the `shift_key` is held
+    #[serde(default)]
+    pub shifted: bool,
+    // how many presses an on_multi_tap mapping waits for
+    #[serde(default = "default_tap_count")]
+    pub count: u32,
+    // an on_release mapping only fires if the key was released within this time (in ms)
+    #[serde(default)]
+    pub max_hold_ms: Option<u64>,
+    // only fires while a state variable matches, like `mode==1`
+    #[serde(default)]
+    pub when: Option<String>,
+    // an on_press mapping of a dial key fires several times per tick on fast turns
+    #[serde(default)]
+    pub accelerate: bool,
+    // keys of the computer's own keyboard that have to be held too, like `ALT`
+    #[serde(default)]
+    pub host_modifiers: Vec<String>,
+    // only fires when no other mapping of the key would
+    #[serde(default)]
+    pub default: bool,
+    // a disabled mapping stays in the config but never fires
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+    // played after the actions fired, `beep` or `sound:<file.wav>`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub feedback: Option<String>,
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+fn default_tap_count() -> u32 {
+    2
 }
 
-// In src/config.rs
+// Activates a profile or sets a state variable to 1 while the local time is between
+// `from` and `to`, written like "22:00"; a range past midnight wraps around
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct ScheduleConfig {
+    pub from: String,
+    pub to: String,
+    #[serde(default)]
+    pub profile: Option<String>,
+    #[serde(default)]
+    pub variable: Option<String>,
+}
+
+// Fires its actions every `every_ms` while its keys and `when` allow it, without any input
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct TimerConfig {
+    pub every_ms: u64,
+    #[serde(deserialize_with = "one_or_many")]
+    pub action: Vec<String>,
+    // TourBox keys that have to be held or locked, `+` separated like modifiers
+    #[serde(default)]
+    pub keys: Option<String>,
+    // only fires while a state variable matches, like the `when` of a mapping
+    #[serde(default)]
+    pub when: Option<String>,
+}
+
+// Maps are written sorted by key, so dumped configs don't change from run to run
+fn sorted<S, V>(map: &HashMap<String, V>, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+    V: Serialize,
+{
+    map.iter().collect::<BTreeMap<_, _>>().serialize(serializer)
+}
 
-// Add this module. It can go near the top with the `use` statements.
-mod hex_serde {
-    use serde::{self, Deserialize, Deserializer};
+fn one_or_many<'de, D>(deserializer: D) -> Result<Vec<String>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged, expecting = "a string or a list of strings")]
+    enum Raw {
+        One(String),
+        Many(Vec<String>),
+    }
+
+    Ok(match Raw::deserialize(deserializer)? {
+        Raw::One(s) => vec![s],
+        Raw::Many(v) => v,
+    })
+}
 
-    // The custom deserialization function
-    pub fn deserialize<'de, D>(deserializer: D) -> Result<u16, D::Error>
+// Numbers like USB ids, written as hex with a `0x` prefix (`"0xCAFE"`) or as a decimal
+// number (`"51966"` or `51966`)
+mod number {
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Raw {
+        Number(u64),
+        Text(String),
+    }
+
+    fn parse(field: &str, raw: Raw, max: u64) -> Result<u64, String> {
+        let (value, parsed) = match raw {
+            Raw::Number(n) => (n.to_string(), Some(n)),
+            Raw::Text(s) => {
+                let parsed = match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+                    Some(hex) => u64::from_str_radix(hex, 16).ok(),
+                    None => s.parse().ok(),
+                };
+                (format!("\"{}\"", s), parsed)
+            }
+        };
+        parsed.filter(|n| *n <= max).ok_or_else(|| {
+            format!(
+                "invalid `{}` {}, expected a hex number starting with `0x` like \"0x{:X}\" or a decimal number up to {}",
+                field, value, max, max
+            )
+        })
+    }
+
+    fn deserialize<'de, D>(deserializer: D, field: &str, max: u64) -> Result<u64, D::Error>
     where
         D: Deserializer<'de>,
     {
-        // Deserialize the field as a string first
-        let s = String::deserialize(deserializer)?;
-        // Ensure the string starts with "0x" and then parse the rest as a hex number
-        if s.starts_with("0x") {
-            u16::from_str_radix(&s[2..], 16).map_err(serde::de::Error::custom)
+        parse(field, Raw::deserialize(deserializer)?, max).map_err(serde::de::Error::custom)
+    }
+
+    pub fn vid<'de, D>(deserializer: D) -> Result<u16, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserialize(deserializer, "vid", u16::MAX.into()).map(|n| n as u16)
+    }
+
+    pub fn pid<'de, D>(deserializer: D) -> Result<u16, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserialize(deserializer, "pid", u16::MAX.into()).map(|n| n as u16)
+    }
+
+    pub fn release_offset<'de, D>(deserializer: D) -> Result<u8, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserialize(deserializer, "release_offset", u8::MAX.into()).map(|n| n as u8)
+    }
+
+    pub fn hex<S, N>(n: &N, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+        N: Copy + Into<u64>,
+    {
+        serializer.serialize_str(&format!("0x{:X}", (*n).into()))
+    }
+}
+
+// Hand-written configs may contain `//` and `/* */` comments and trailing commas.
+mod relaxed_json {
+    // Blanks out comments and trailing commas so the result is strict JSON,
+    // with every remaining token at its original line and column.
+    pub fn to_strict(src: &str) -> String {
+        strip_trailing_commas(&strip_comments(src))
+    }
+
+    fn blank(out: &mut String, c: char) {
+        if c == '\n' {
+            out.push('\n');
         } else {
-            // If it doesn't start with "0x", return an error
-            Err(serde::de::Error::custom(
-                "expected a hex string starting with '0x'",
-            ))
+            out.extend(std::iter::repeat_n(' ', c.len_utf8()));
+        }
+    }
+
+    fn strip_comments(src: &str) -> String {
+        let mut out = String::with_capacity(src.len());
+        let mut chars = src.chars().peekable();
+        let mut in_string = false;
+        while let Some(c) = chars.next() {
+            if in_string {
+                out.push(c);
+                match c {
+                    '\\' => out.extend(chars.next()),
+                    '"' => in_string = false,
+                    _ => {}
+                }
+                continue;
+            }
+            match (c, chars.peek()) {
+                ('/', Some('/')) => {
+                    blank(&mut out, c);
+                    while let Some(n) = chars.next_if(|n| *n != '\n') {
+                        blank(&mut out, n);
+                    }
+                }
+                ('/', Some('*')) => {
+                    blank(&mut out, c);
+                    let mut prev = chars.next().unwrap();
+                    blank(&mut out, prev);
+                    for n in chars.by_ref() {
+                        blank(&mut out, n);
+                        if prev == '*' && n == '/' {
+                            break;
+                        }
+                        prev = n;
+                    }
+                }
+                _ => {
+                    in_string = c == '"';
+                    out.push(c);
+                }
+            }
         }
+        out
     }
+
+    fn strip_trailing_commas(src: &str) -> String {
+        let mut out = String::with_capacity(src.len());
+        let mut in_string = false;
+        let mut escaped = false;
+        for (i, c) in src.char_indices() {
+            if in_string {
+                if escaped {
+                    escaped = false;
+                } else if c == '\\' {
+                    escaped = true;
+                } else if c == '"' {
+                    in_string = false;
+                }
+            } else if c == '"' {
+                in_string = true;
+            } else if c == ','
+                && matches!(src[i + 1..].trim_start().chars().next(), Some('}' | ']'))
+            {
+                out.push(' ');
+                continue;
+            }
+            out.push(c);
+        }
+        out
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct WinUsbConfig {
+    #[serde(deserialize_with = "number::vid", serialize_with = "number::hex")]
+    pub vid: u16,
+    #[serde(deserialize_with = "number::pid", serialize_with = "number::hex")]
+    pub pid: u16,
+    // interface holding the bulk endpoints
+    #[serde(default = "default_winusb_interface")]
+    pub interface: u8,
+    // configuration descriptor to look for the interface in
+    #[serde(default)]
+    pub config_index: u8,
+    // how long a single bulk read blocks, shorter reacts faster to shutdown at a bit more CPU
+    #[serde(default = "default_winusb_read_timeout_ms")]
+    pub read_timeout_ms: u64,
+    // how often the init command is sent again when the device doesn't accept it
+    #[serde(default = "default_winusb_init_retries")]
+    pub init_retries: u32,
+    // pause (in ms) before sending the init command again
+    #[serde(default = "default_winusb_init_retry_delay_ms")]
+    pub init_retry_delay_ms: u64,
+    // for a device some other software already initialized, only read from it
+    #[serde(default)]
+    pub skip_init: bool,
+    // sent instead of the init command, for firmware that needs more than one command
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub handshake: Vec<HandshakeStep>,
+    // how long (in ms) an `expect` or `read` step of the handshake waits for its bytes
+    #[serde(default = "default_handshake_timeout_ms")]
+    pub handshake_timeout_ms: u64,
+    // lets mappings name the keys of this device like `left.SIDE`, see `Config::strip_key_prefix`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prefix: Option<String>,
 }
 
-#[derive(Debug, Deserialize, Clone)]
+fn default_handshake_timeout_ms() -> u64 {
+    1000
+}
+
+// One step of a device handshake, bytes are written in hex like "B5 00 07"
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum HandshakeStep {
+    // sends the bytes
+    Write(#[serde(with = "hex_bytes")] Vec<u8>),
+    // reads as many bytes as the pattern has and fails unless they match, `??` matches any
+    Expect(#[serde(with = "hex_bytes::pattern")] Vec<Option<u8>>),
+    // reads this many bytes, whatever they are
+    Read(usize),
+    // pauses for this long (in ms)
+    DelayMs(u64),
+}
+
+mod hex_bytes {
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    fn parse_byte(byte: &str) -> Result<u8, String> {
+        let hex = byte
+            .strip_prefix("0x")
+            .or_else(|| byte.strip_prefix("0X"))
+            .unwrap_or(byte);
+        u8::from_str_radix(hex, 16)
+            .map_err(|_| format!("invalid byte `{}`, expected hex like `B5` or `0xB5`", byte))
+    }
+
+    pub fn serialize<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        let hex: Vec<String> = bytes.iter().map(|b| format!("{:02X}", b)).collect();
+        serializer.serialize_str(&hex.join(" "))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        String::deserialize(deserializer)?
+            .split_whitespace()
+            .map(parse_byte)
+            .collect::<Result<_, _>>()
+            .map_err(serde::de::Error::custom)
+    }
+
+    pub mod pattern {
+        use serde::{Deserialize, Deserializer, Serializer};
+
+        pub fn serialize<S: Serializer>(
+            pattern: &[Option<u8>],
+            serializer: S,
+        ) -> Result<S::Ok, S::Error> {
+            let hex: Vec<String> = pattern
+                .iter()
+                .map(|b| b.map_or("??".to_string(), |b| format!("{:02X}", b)))
+                .collect();
+            serializer.serialize_str(&hex.join(" "))
+        }
+
+        pub fn deserialize<'de, D: Deserializer<'de>>(
+            deserializer: D,
+        ) -> Result<Vec<Option<u8>>, D::Error> {
+            String::deserialize(deserializer)?
+                .split_whitespace()
+                .map(|b| match b {
+                    "??" => Ok(None),
+                    b => super::parse_byte(b).map(Some),
+                })
+                .collect::<Result<_, _>>()
+                .map_err(serde::de::Error::custom)
+        }
+    }
+}
+
+fn default_winusb_interface() -> u8 {
+    1
+}
+
+fn default_winusb_read_timeout_ms() -> u64 {
+    100
+}
+
+fn default_winusb_init_retries() -> u32 {
+    2
+}
+
+fn default_winusb_init_retry_delay_ms() -> u64 {
+    200
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct SerialConfig {
+    pub serial_port: String,
+    pub baud_rate: u32,
+    // like the WinUSB one, the port is only opened and read
+    #[serde(default)]
+    pub skip_init: bool,
+    // like the WinUSB ones
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub handshake: Vec<HandshakeStep>,
+    #[serde(default = "default_handshake_timeout_ms")]
+    pub handshake_timeout_ms: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prefix: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
 pub enum TourBoxDevice {
     #[serde(rename = "winusb")]
-    WinUsb {
-        #[serde(with = "hex_serde")]
-        vid: u16,
-        #[serde(with = "hex_serde")]
-        pid: u16,
-    },
+    WinUsb(WinUsbConfig),
     #[serde(rename = "serial")]
-    Serial { serial_port: String, baud_rate: u32 },
+    Serial(SerialConfig),
+    #[serde(rename = "stdin")]
+    Stdin {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        prefix: Option<String>,
+    },
+}
+
+impl TourBoxDevice {
+    pub fn prefix(&self) -> Option<&str> {
+        match self {
+            TourBoxDevice::WinUsb(cfg) => cfg.prefix.as_deref(),
+            TourBoxDevice::Serial(cfg) => cfg.prefix.as_deref(),
+            TourBoxDevice::Stdin { prefix } => prefix.as_deref(),
+        }
+    }
+}
+
+// Every key name of a mapping's `keys` passed through `f`, keeping the `+`, `&`, `(`, `|`
+// and `)` around them
+fn map_key_names(keys: &str, f: impl Fn(&str) -> String) -> String {
+    let mut mapped = String::new();
+    let mut key = String::new();
+    let mut chars = keys.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '+' | '&' | '(' | ')' | '|' => {
+                mapped.push_str(&f(&key));
+                mapped.push(c);
+                key.clear();
+            }
+            // an escaped `+` is part of the key
+            '\\' => key.extend([Some(c), chars.next()].into_iter().flatten()),
+            c => key.push(c),
+        }
+    }
+    mapped.push_str(&f(&key));
+    mapped
+}
+
+// Look of the window
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+pub struct UiConfig {
+    #[serde(default)]
+    pub theme: Theme,
+    // names the window and the terminal UI show for TourBox keys, e.g. `{ "SIDE": "Left Side Button" }`
+    #[serde(default, serialize_with = "sorted")]
+    pub labels: HashMap<String, String>,
+}
+
+impl UiConfig {
+    // The label of a TourBox key, its own name without one
+    pub fn label<'a>(&'a self, key: &'a str) -> &'a str {
+        self.labels.get(key).map_or(key, String::as_str)
+    }
+
+    // The keys of a mapping with every key that has a label replaced by it, e.g. `SIDE+TOP`
+    pub fn label_keys(&self, keys: &str) -> String {
+        map_key_names(keys, |k| self.label(k).to_owned())
+    }
 }
 
-#[derive(Debug, Deserialize, Clone)]
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Theme {
+    Dark,
+    Light,
+    // follows the OS setting where it can be detected, dark otherwise
+    #[default]
+    System,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct Config {
     pub device: TourBoxDevice,
     pub key_map: KeyMap,
+    #[serde(default)]
+    pub mappings: Vec<KeyMappingConfig>,
+    // named sets of mappings that take precedence over `mappings` while active
+    #[serde(default, serialize_with = "sorted")]
+    pub profiles: HashMap<String, Vec<KeyMappingConfig>>,
+    // a key that never fires mappings itself but selects the `shifted` ones while held
+    #[serde(default)]
+    pub shift_key: Option<String>,
+    // TourBox keys that never fire mappings themselves, but add their host keys to the
+    // keys other mappings click while held, e.g. `{ "SIDE": "CTRL_L" }`
+    #[serde(default, serialize_with = "sorted")]
+    pub pass_through: HashMap<String, String>,
+    // TourBox keys that, once tapped, count as held for the next press of another key
+    #[serde(default)]
+    pub sticky_keys: Vec<String>,
+    // how long (in ms) a tapped sticky key waits for that press
+    #[serde(default = "default_sticky_timeout_ms")]
+    pub sticky_timeout_ms: u64,
+    // names for raw key codes, usable in `action` like the built-in key names
+    #[serde(default, serialize_with = "sorted")]
+    pub key_aliases: HashMap<String, u32>,
+    // actions fired when the device connects or disconnects
+    #[serde(default, deserialize_with = "one_or_many")]
+    pub on_connect: Vec<String>,
+    #[serde(default, deserialize_with = "one_or_many")]
+    pub on_disconnect: Vec<String>,
+    // actions fired once the battery level drops below `low_battery_percent`
+    #[serde(default, deserialize_with = "one_or_many")]
+    pub on_low_battery: Vec<String>,
+    #[serde(default = "default_low_battery_percent")]
+    pub low_battery_percent: u8,
+    #[serde(default)]
+    pub timers: Vec<TimerConfig>,
+    #[serde(default)]
+    pub schedule: Vec<ScheduleConfig>,
+    // if set, actions are only sent while one of these apps is in the foreground
+    #[serde(default)]
+    pub active_apps: Vec<String>,
+    // actions are never sent while one of these apps is in the foreground
+    #[serde(default)]
+    pub inactive_apps: Vec<String>,
+    // how close together (in ms) both keys of a co-press mapping (`A&B`) have to be pressed
+    #[serde(default = "default_co_press_window_ms")]
+    pub co_press_window_ms: u64,
+    // how long (in ms) a press waits for a modifier that would pick a more specific mapping
+    #[serde(default)]
+    pub combo_grace_ms: u64,
+    // how long (in ms) after a press the next one still counts for an on_multi_tap mapping
+    #[serde(default = "default_multi_tap_window_ms")]
+    pub multi_tap_window_ms: u64,
+    // ticks of the dial, knob or scroll wheel swallowed at the start of every turn
+    #[serde(default)]
+    pub dial_dead_zone: u32,
+    // a pause (in ms) after which a turn starts with the dead zone again
+    #[serde(default = "default_dial_idle_ms")]
+    pub dial_idle_ms: u64,
+    // ticks closer together (in ms) fire `accelerate` mappings more than once
+    #[serde(default = "default_dial_accel_ms")]
+    pub dial_accel_ms: u64,
+    // how often one tick fires an `accelerate` mapping at most
+    #[serde(default = "default_dial_accel_max")]
+    pub dial_accel_max: u32,
+    // how long (in ms) a clicked key is held down before it is released
+    #[serde(default)]
+    pub key_press_ms: u64,
+    // pause (in ms) between pressing or releasing the keys of a clicked chord
+    #[serde(default)]
+    pub inter_key_delay_ms: u64,
+    // lets go of the keyboard's modifiers while a key is clicked, instead of sending them along
+    #[serde(default)]
+    pub release_host_modifiers: bool,
+    #[serde(default)]
+    pub ui: UiConfig,
+}
+
+fn default_sticky_timeout_ms() -> u64 {
+    2000
+}
+
+fn default_low_battery_percent() -> u8 {
+    20
+}
+
+fn default_co_press_window_ms() -> u64 {
+    50
+}
+
+fn default_multi_tap_window_ms() -> u64 {
+    300
+}
+
+fn default_dial_idle_ms() -> u64 {
+    250
+}
+
+fn default_dial_accel_ms() -> u64 {
+    100
+}
+
+fn default_dial_accel_max() -> u32 {
+    4
+}
+
+// Used when there is no config file, the stock TourBox over WinUSB
+const EMBEDDED_DEFAULT: &str = include_str!("../config_usb.json");
+
+#[derive(Error, Debug)]
+pub enum ConfigError {
+    #[error("{path}: {source}")]
+    Io {
+        path: String,
+        #[source]
+        source: io::Error,
+    },
+    // not valid JSON, even with comments and trailing commas
+    #[error("{path}:{line}:{column}: {message}")]
+    Parse {
+        path: String,
+        line: usize,
+        column: usize,
+        message: String,
+    },
+    // valid JSON that doesn't describe a config, e.g. a missing field or an unknown trigger
+    #[error("{path}:{line}:{column}: {message}")]
+    Validation {
+        path: String,
+        line: usize,
+        column: usize,
+        message: String,
+    },
+    // files that load on their own but don't describe a config once merged, serde_json
+    // can't tell which file the problem came from
+    #[error("{paths}: {message}")]
+    Merged { paths: String, message: String },
+}
+
+impl ConfigError {
+    fn from_json(path: &str, e: serde_json::Error) -> Self {
+        // serde_json appends " at line L column C" to the message, it is part of the error instead
+        let msg = e.to_string();
+        let location = format!(" at line {} column {}", e.line(), e.column());
+        let message = msg.strip_suffix(&location).unwrap_or(&msg).to_string();
+        let path = path.to_string();
+        let (line, column) = (e.line(), e.column());
+        match e.classify() {
+            Category::Data => ConfigError::Validation {
+                path,
+                line,
+                column,
+                message,
+            },
+            Category::Io => ConfigError::Io {
+                path,
+                source: e.into(),
+            },
+            Category::Syntax | Category::Eof => ConfigError::Parse {
+                path,
+                line,
+                column,
+                message,
+            },
+        }
+    }
+}
+
+// Replaces a top level `{ "$file": "keymap.json" }` with what that file holds, found
+// next to the config at `path`. Returns the files read.
+fn include_files(value: &mut serde_json::Value, path: &str) -> Result<Vec<String>, ConfigError> {
+    let Some(sections) = value.as_object_mut() else {
+        return Ok(Vec::new());
+    };
+    let dir = Path::new(path).parent().unwrap_or(Path::new(""));
+    let mut included = Vec::new();
+    for section in sections.values_mut() {
+        let Some(file) = section
+            .as_object()
+            .filter(|o| o.len() == 1)
+            .and_then(|o| o.get("$file"))
+            .and_then(|f| f.as_str())
+        else {
+            continue;
+        };
+        let file = dir.join(file).display().to_string();
+        let source = fs::read_to_string(&file).map_err(|source| ConfigError::Io {
+            path: file.clone(),
+            source,
+        })?;
+        *section = serde_json::from_str(&relaxed_json::to_strict(&source))
+            .map_err(|e| ConfigError::from_json(&file, e))?;
+        included.push(file);
+    }
+    Ok(included)
+}
+
+// Sections of a config `merge_config` replaces as a whole, the variant of a tagged enum
+// like `device` can't be mixed with the fields of another one
+const REPLACED_SECTIONS: &[&str] = &["device"];
+
+// Merges one config file over the ones before it. Its key map codes are normalized
+// first, so `26` replaces what an earlier file has at `0x1a`.
+fn merge_config(base: &mut serde_json::Value, mut overlay: serde_json::Value) {
+    for section in ["stateful", "stateless", "status"] {
+        if let Some(serde_json::Value::Object(codes)) =
+            overlay.pointer_mut(&format!("/key_map/{}", section))
+        {
+            *codes = mem::take(codes)
+                .into_iter()
+                .map(|(code, value)| (normalized_code(&code), value))
+                .collect();
+        }
+    }
+    if let (Some(base), Some(overlay)) = (base.as_object_mut(), overlay.as_object_mut()) {
+        for section in REPLACED_SECTIONS {
+            if let Some(value) = overlay.remove(*section) {
+                base.insert(section.to_string(), value);
+            }
+        }
+    }
+    merge(base, overlay);
+}
+
+// Objects are merged key by key, anything else in `overlay`, arrays included, replaces
+// what `base` has there
+fn merge(base: &mut serde_json::Value, overlay: serde_json::Value) {
+    match (base, overlay) {
+        (serde_json::Value::Object(base), serde_json::Value::Object(overlay)) => {
+            for (key, value) in overlay {
+                match base.get_mut(&key) {
+                    Some(existing) => merge(existing, value),
+                    None => {
+                        base.insert(key, value);
+                    }
+                }
+            }
+        }
+        (base, overlay) => *base = overlay,
+    }
+}
+
+// The mappings of a config with what they depend on, written by `--export` and read by
+// `--import`. It is a config file itself, to be merged over another one with `--config`.
+#[derive(Deserialize, Serialize, Default, Debug)]
+pub struct Layout {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key_map: Option<KeyMap>,
+    #[serde(default)]
     pub mappings: Vec<KeyMappingConfig>,
+    #[serde(default, serialize_with = "sorted")]
+    pub profiles: HashMap<String, Vec<KeyMappingConfig>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub shift_key: Option<String>,
+    #[serde(
+        default,
+        skip_serializing_if = "HashMap::is_empty",
+        serialize_with = "sorted"
+    )]
+    pub pass_through: HashMap<String, String>,
+    #[serde(
+        default,
+        skip_serializing_if = "HashMap::is_empty",
+        serialize_with = "sorted"
+    )]
+    pub key_aliases: HashMap<String, u32>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub sticky_keys: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub timers: Vec<TimerConfig>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub schedule: Vec<ScheduleConfig>,
+    #[serde(default, skip_serializing_if = "LayoutUi::is_empty")]
+    pub ui: LayoutUi,
+}
+
+// The part of `ui` that belongs to a layout, the theme stays with the config
+#[derive(Deserialize, Serialize, Default, Debug)]
+pub struct LayoutUi {
+    #[serde(default, serialize_with = "sorted")]
+    pub labels: HashMap<String, String>,
+}
+
+impl LayoutUi {
+    fn is_empty(&self) -> bool {
+        self.labels.is_empty()
+    }
+}
+
+impl Layout {
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, ConfigError> {
+        let path = path.as_ref().display().to_string();
+        let source = fs::read_to_string(&path).map_err(|source| ConfigError::Io {
+            path: path.clone(),
+            source,
+        })?;
+        serde_json::from_str(&relaxed_json::to_strict(&source))
+            .map_err(|e| ConfigError::from_json(&path, e))
+    }
+}
+
+// The mappings of `layout` replace every one with the same `keys`, the others stay
+fn merge_mappings(mappings: &mut Vec<KeyMappingConfig>, layout: Vec<KeyMappingConfig>) {
+    let keys: HashSet<&str> = layout.iter().map(|m| m.keys.as_str()).collect();
+    mappings.retain(|m| !keys.contains(m.keys.as_str()));
+    mappings.extend(layout);
 }
 
 impl Config {
-    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, io::Error> {
-        let file = File::open(path)?;
-        let reader = io::BufReader::new(file);
-        let config = serde_json::from_reader(reader)
-            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
-        Ok(config)
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, ConfigError> {
+        let path = path.as_ref().display().to_string();
+        let file = File::open(&path).map_err(|source| ConfigError::Io {
+            path: path.clone(),
+            source,
+        })?;
+        Self::from_reader(file, &path)
+    }
+
+    // Deep merges the files in order, see `merge_config`. A single file loads like `from_file`.
+    pub fn from_files<P: AsRef<Path>>(paths: &[P]) -> Result<Self, ConfigError> {
+        if let [path] = paths {
+            return Self::from_file(path);
+        }
+        let mut merged = serde_json::Value::Object(Default::default());
+        for path in paths {
+            let path = path.as_ref().display().to_string();
+            let source = fs::read_to_string(&path).map_err(|source| ConfigError::Io {
+                path: path.clone(),
+                source,
+            })?;
+            let mut value = serde_json::from_str(&relaxed_json::to_strict(&source))
+                .map_err(|e| ConfigError::from_json(&path, e))?;
+            include_files(&mut value, &path)?;
+            merge_config(&mut merged, value);
+        }
+        serde_json::from_value(merged).map_err(|e| ConfigError::Merged {
+            paths: Self::files_name(paths),
+            message: e.to_string(),
+        })
+    }
+
+    // How `from_files` names the merged config in messages
+    pub fn files_name<P: AsRef<Path>>(paths: &[P]) -> String {
+        paths
+            .iter()
+            .map(|p| p.as_ref().display().to_string())
+            .collect::<Vec<_>>()
+            .join(" + ")
+    }
+
+    // `name` is where the config came from, for error messages
+    pub fn from_reader<R: Read>(mut reader: R, name: &str) -> Result<Self, ConfigError> {
+        let mut source = String::new();
+        reader
+            .read_to_string(&mut source)
+            .map_err(|source| ConfigError::Io {
+                path: name.to_string(),
+                source,
+            })?;
+        Self::parse(&source, name)
+    }
+
+    pub fn embedded_default() -> Self {
+        Self::parse(EMBEDDED_DEFAULT, "embedded default config")
+            .expect("the embedded default config is valid")
+    }
+
+    fn parse(source: &str, name: &str) -> Result<Self, ConfigError> {
+        let strict = relaxed_json::to_strict(source);
+        let mut value =
+            serde_json::from_str(&strict).map_err(|e| ConfigError::from_json(name, e))?;
+        let included = include_files(&mut value, name)?;
+        if included.is_empty() {
+            // straight from the text, so mistakes point at their line
+            return serde_json::from_str(&strict).map_err(|e| ConfigError::from_json(name, e));
+        }
+        serde_json::from_value(value).map_err(|e| ConfigError::Merged {
+            paths: Self::files_name(&[&[name.to_string()], &included[..]].concat()),
+            message: e.to_string(),
+        })
+    }
+
+    // Every setting in effect, defaults included, as a config file that loads the same
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("configs always serialize")
+    }
+
+    // The mappings, with what they depend on, see `Layout`. `with_key_map` adds the key
+    // names, for a different device.
+    pub fn export_layout(&self, with_key_map: bool) -> String {
+        let layout = Layout {
+            key_map: with_key_map.then(|| self.key_map.clone()),
+            mappings: self.mappings.clone(),
+            profiles: self.profiles.clone(),
+            shift_key: self.shift_key.clone(),
+            pass_through: self.pass_through.clone(),
+            key_aliases: self.key_aliases.clone(),
+            sticky_keys: self.sticky_keys.clone(),
+            timers: self.timers.clone(),
+            schedule: self.schedule.clone(),
+            ui: LayoutUi {
+                labels: self.ui.labels.clone(),
+            },
+        };
+        serde_json::to_string_pretty(&layout).expect("layouts always serialize")
+    }
+
+    // Merges an exported layout in: its mappings replace the ones with the same `keys`, in
+    // the top level and in every profile, the others stay. Its `key_map` and `shift_key`
+    // replace the config's, the entries of its maps are added over the config's, and its
+    // sticky keys, timers and schedule entries are added unless the config has them already.
+    pub fn import_layout(&mut self, layout: Layout) {
+        if let Some(key_map) = layout.key_map {
+            self.key_map = key_map;
+        }
+        merge_mappings(&mut self.mappings, layout.mappings);
+        for (name, mappings) in layout.profiles {
+            merge_mappings(self.profiles.entry(name).or_default(), mappings);
+        }
+        if layout.shift_key.is_some() {
+            self.shift_key = layout.shift_key;
+        }
+        self.pass_through.extend(layout.pass_through);
+        self.key_aliases.extend(layout.key_aliases);
+        self.ui.labels.extend(layout.ui.labels);
+        for key in layout.sticky_keys {
+            if !self.sticky_keys.contains(&key) {
+                self.sticky_keys.push(key);
+            }
+        }
+        for timer in layout.timers {
+            if !self.timers.contains(&timer) {
+                self.timers.push(timer);
+            }
+        }
+        for entry in layout.schedule {
+            if !self.schedule.contains(&entry) {
+                self.schedule.push(entry);
+            }
+        }
+    }
+
+    // With the device's `prefix`, e.g. `left.`, the config may name its keys `left.SIDE` as
+    // well as `SIDE`. The device sends the bare names, so the prefix is dropped wherever a
+    // TourBox key is named: the keys of mappings and timers, `lock:` actions, `shift_key`,
+    // `pass_through`, `sticky_keys` and `ui.labels`. A key with another prefix is kept as it
+    // is and never pressed.
+    pub fn strip_key_prefix(&mut self) {
+        let Some(prefix) = self.device.prefix().map(str::to_owned) else {
+            return;
+        };
+        let strip = |k: &str| k.strip_prefix(prefix.as_str()).unwrap_or(k).to_owned();
+        let bare = |keys: &str| map_key_names(keys, strip);
+        // the other actions name keys of the computer, not of the TourBox
+        let bare_actions = |actions: &mut Vec<String>| {
+            for action in actions {
+                if let Some(key) = action.strip_prefix("lock:") {
+                    *action = format!("lock:{}", strip(key));
+                }
+            }
+        };
+        let mappings = self
+            .mappings
+            .iter_mut()
+            .chain(self.profiles.values_mut().flatten());
+        for m in mappings {
+            m.keys = bare(&m.keys);
+            bare_actions(&mut m.action);
+        }
+        for timer in &mut self.timers {
+            timer.keys = timer.keys.as_deref().map(bare);
+            bare_actions(&mut timer.action);
+        }
+        for hook in [
+            &mut self.on_connect,
+            &mut self.on_disconnect,
+            &mut self.on_low_battery,
+        ] {
+            bare_actions(hook);
+        }
+        self.shift_key = self.shift_key.as_deref().map(strip);
+        self.pass_through = self
+            .pass_through
+            .drain()
+            .map(|(key, host_key)| (strip(&key), host_key))
+            .collect();
+        for key in &mut self.sticky_keys {
+            *key = strip(key);
+        }
+        self.ui.labels = self
+            .ui
+            .labels
+            .drain()
+            .map(|(key, label)| (strip(&key), label))
+            .collect();
+    }
+
+    // Problems that don't stop the config from loading but will likely surprise at runtime
+    pub fn validate(&self) -> Vec<String> {
+        let mut problems = self.errors();
+        problems.extend(self.warnings());
+        problems
+    }
+
+    // The problems of `validate` that make keys or mappings useless, `--validate` counts
+    // them as errors
+    pub fn errors(&self) -> Vec<String> {
+        let mut problems = vec![];
+        if self.key_map.release_offset == 0 && !self.key_map.stateful.is_empty() {
+            let mut keys: Vec<&String> = self.key_map.stateful.values().collect();
+            keys.sort();
+            problems.push(format!(
+                "`release_offset` is 0, so the `stateful` keys {} are pressed but never released, \
+                 put them in `stateless`",
+                keys.iter()
+                    .map(|k| format!("`{}`", k))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ));
+        }
+        problems.extend(self.unknown_keys());
+        problems
+    }
+
+    // The problems of `validate` that don't make a mapping useless, see `errors`
+    pub fn warnings(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        if self.mappings.is_empty() && self.profiles.values().all(Vec::is_empty) {
+            problems.push(
+                "no mappings are configured, key presses will not do anything \
+                 (see config_usb.json or config_serial.json for an example `mappings` section)"
+                    .to_string(),
+            );
+        }
+        problems.extend(self.key_map.conflicts());
+        problems.extend(self.shadowed_mappings());
+        problems
+    }
+
+    // Every mapping with the profile it belongs to, `None` for the top level ones
+    pub fn all_mappings(&self) -> Vec<(Option<&str>, &KeyMappingConfig)> {
+        let mut profiles: Vec<_> = self.profiles.iter().collect();
+        profiles.sort_by_key(|(name, _)| *name);
+        self.mappings
+            .iter()
+            .map(|m| (None, m))
+            .chain(
+                profiles
+                    .into_iter()
+                    .flat_map(|(name, ms)| ms.iter().map(move |m| (Some(name.as_str()), m))),
+            )
+            .collect()
+    }
+
+    // State variables set by `set:` and `toggle:` actions or by the `schedule`
+    fn state_names(&self) -> HashSet<&str> {
+        let actions = self
+            .all_mappings()
+            .into_iter()
+            .flat_map(|(_, m)| &m.action)
+            .chain(self.timers.iter().flat_map(|t| &t.action));
+        let mut names: HashSet<&str> = actions
+            .filter_map(|a| {
+                a.strip_prefix("set:")
+                    .map(|s| s.split_once('=').map_or(s, |(name, _)| name))
+                    .or_else(|| a.strip_prefix("toggle:"))
+            })
+            .collect();
+        names.extend(self.schedule.iter().filter_map(|s| s.variable.as_deref()));
+        names
+    }
+
+    // TourBox keys used by mappings but never produced by the `key_map`, the mappings can
+    // never fire so `--validate` counts them as errors
+    pub fn unknown_keys(&self) -> Vec<String> {
+        let known: HashSet<&String> = self
+            .key_map
+            .stateful
+            .values()
+            .chain(self.key_map.stateless.values())
+            .collect();
+        // stdin is fed key names directly, there is nothing to check them against
+        if known.is_empty() {
+            return vec![];
+        }
+        let mut problems = vec![];
+        if let Some(shift_key) = &self.shift_key
+            && !known.contains(shift_key)
+        {
+            problems.push(format!(
+                "`shift_key` `{}` is not a key in `key_map`",
+                shift_key
+            ));
+        }
+        let mut pass_through: Vec<&String> = self.pass_through.keys().collect();
+        pass_through.sort();
+        for key in pass_through {
+            if !known.contains(key) {
+                problems.push(format!(
+                    "`pass_through` key `{}` is not a key in `key_map`",
+                    key
+                ));
+            }
+        }
+        // the action log shows state variables by their labels too
+        let states = self.state_names();
+        let mut labels: Vec<&String> = self.ui.labels.keys().collect();
+        labels.sort();
+        for key in labels {
+            if !known.contains(key) && !states.contains(key.as_str()) {
+                problems.push(format!(
+                    "`ui.labels` key `{}` is not a key in `key_map` or a state variable",
+                    key
+                ));
+            }
+        }
+        for key in &self.sticky_keys {
+            if !known.contains(key) {
+                problems.push(format!(
+                    "`sticky_keys` key `{}` is not a key in `key_map`",
+                    key
+                ));
+            }
+        }
+        for (_, m) in self.all_mappings() {
+            for host_key in &m.host_modifiers {
+                if !HOST_MODIFIERS.contains(&host_key.to_uppercase().as_str()) {
+                    problems.push(format!(
+                        "mapping `{}`: `{}` is not a host modifier, use one of {}",
+                        m.keys,
+                        host_key,
+                        HOST_MODIFIERS.join(", ")
+                    ));
+                }
+            }
+            for chord_key in split_chord(&m.keys) {
+                for key in chord_key.split('&').flat_map(split_group) {
+                    if !known.contains(&key) {
+                        problems.push(format!(
+                            "mapping `{}`: `{}` is not a key in `key_map`",
+                            m.keys, key
+                        ));
+                    }
+                }
+            }
+        }
+        problems
+    }
+
+    // Mappings that never fire because a later one has the same keys and trigger
+    fn shadowed_mappings(&self) -> Vec<String> {
+        let mut problems = vec![];
+        let mappings = self.all_mappings();
+        for (i, (profile, m)) in mappings.iter().enumerate() {
+            let shadowed = mappings[i + 1..].iter().any(|(later_profile, later)| {
+                later_profile == profile
+                    && later.keys == m.keys
+                    && later.trigger == m.trigger
+                    && later.shifted == m.shifted
+                    && later.when == m.when
+                    && later.host_modifiers == m.host_modifiers
+                    && (m.trigger != KeyTriggerTiming::OnMultiTap || later.count == m.count)
+            });
+            if shadowed {
+                problems.push(format!(
+                    "mapping `{}` ({:?}) in `{}` never fires, a later mapping has the same keys and trigger",
+                    m.keys,
+                    m.trigger,
+                    profile.unwrap_or(DEFAULT_PROFILE)
+                ));
+            }
+        }
+        problems
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key_map(release_offset: u8) -> KeyMap {
+        KeyMap {
+            stateful: HashMap::from([("0x02".to_string(), "TOP".to_string())]),
+            stateless: HashMap::from([("0x44".to_string(), "KNOB_CW".to_string())]),
+            release_offset,
+            unknown_code_policy: UnknownCodePolicy::Warn,
+            status: HashMap::new(),
+        }
+    }
+
+    fn pressed(k: &str) -> Option<InputEvent> {
+        Some(InputEvent::KeyPressed(k.to_owned()))
+    }
+
+    fn released(k: &str) -> Option<InputEvent> {
+        Some(InputEvent::KeyReleased(k.to_owned()))
+    }
+
+    #[test]
+    fn labels_replace_the_keys_they_name() {
+        let ui: UiConfig = serde_json::from_str(
+            r#"{"labels": {"SIDE": "Left Side Button", "TOP": "Top Button"}}"#,
+        )
+        .unwrap();
+        assert_eq!(ui.label("SIDE"), "Left Side Button");
+        assert_eq!(ui.label("TALL"), "TALL");
+        assert_eq!(ui.label_keys("SIDE+TOP"), "Left Side Button+Top Button");
+        assert_eq!(
+            ui.label_keys("(SIDE|TALL)&TOP"),
+            "(Left Side Button|TALL)&Top Button"
+        );
+        assert_eq!(ui.label_keys("SIDE+\\+"), "Left Side Button+\\+");
+    }
+
+    #[test]
+    fn release_offset_defaults_to_0x80() {
+        let map: KeyMap = serde_json::from_str(r#"{"stateful": {}, "stateless": {}}"#).unwrap();
+        assert_eq!(map.release_offset, 0x80);
+
+        let map = key_map(0x80);
+        assert_eq!(map.decode(0x02), pressed("TOP"));
+        assert_eq!(map.decode(0x82), released("TOP"));
+        assert_eq!(map.decode(0x44), pressed("KNOB_CW"));
+        assert_eq!(map.code("TOP"), Some(0x02));
+        assert_eq!(map.code("KNOB_CW"), Some(0x44));
+        assert_eq!(map.code("SIDE"), None);
+    }
+
+    #[test]
+    fn decoded_code_names_match_the_config_spelling() {
+        for code in 0..=u8::MAX {
+            assert_eq!(CodeName::new(code).as_str(), code_name(code));
+        }
+    }
+
+    #[test]
+    fn custom_release_offset() {
+        let map: KeyMap =
+            serde_json::from_str(r#"{"stateful": {}, "stateless": {}, "release_offset": "0x40"}"#)
+                .unwrap();
+        assert_eq!(map.release_offset, 0x40);
+
+        let map = key_map(0x40);
+        assert_eq!(map.decode(0x42), released("TOP"));
+        assert_eq!(map.decode(0x82), None);
+        assert_eq!(map.decode(0x44), pressed("KNOB_CW"));
+    }
+
+    #[test]
+    fn zero_release_offset_never_releases() {
+        let map = key_map(0);
+        assert_eq!(map.decode(0x02), pressed("TOP"));
+        assert_eq!(map.decode(0x82), None);
+
+        let config = Config::from_reader(
+            r#"{
+                "device": { "stdin": {} },
+                "key_map": { "stateful": { "0x02": "TOP", "0x03": "C1" }, "stateless": {}, "release_offset": 0 },
+                "mappings": [{ "keys": "TOP", "action": "A", "trigger": "on_press" }]
+            }"#
+            .as_bytes(),
+            "test.json",
+        )
+        .unwrap();
+        assert_eq!(
+            config.errors(),
+            vec![
+                "`release_offset` is 0, so the `stateful` keys `C1`, `TOP` are pressed but never released, put them in `stateless`"
+            ]
+        );
+    }
+
+    #[test]
+    fn config_errors_tell_syntax_from_shape() {
+        let broken = Config::from_reader("{ \"device\": ".as_bytes(), "broken.json");
+        assert!(matches!(broken, Err(ConfigError::Parse { .. })));
+
+        let wrong = Config::from_reader("{ \"key_map\": 1 }".as_bytes(), "wrong.json");
+        match wrong {
+            Err(ConfigError::Validation { path, line, .. }) => {
+                assert_eq!(path, "wrong.json");
+                assert_eq!(line, 1);
+            }
+            other => panic!("expected a validation error, got {:?}", other.err()),
+        }
+    }
+
+    #[test]
+    fn validate_reports_unknown_keys_and_shadowed_mappings() {
+        let config = Config::from_reader(
+            r#"{
+                "device": { "stdin": {} },
+                "key_map": { "stateful": { "0x02": "TOP", "0x03": "C1" }, "stateless": {} },
+                "mappings": [
+                    { "keys": "C1", "action": "A", "trigger": "on_press" },
+                    { "keys": "C1", "action": "B", "trigger": "on_press" },
+                    { "keys": "C1", "action": "C", "trigger": "on_release" },
+                    { "keys": "TOP+C9", "action": "D", "trigger": "on_press" }
+                ]
+            }"#
+            .as_bytes(),
+            "test.json",
+        )
+        .unwrap();
+
+        assert_eq!(
+            config.validate(),
+            vec![
+                "mapping `TOP+C9`: `C9` is not a key in `key_map`",
+                "mapping `C1` (OnPress) in `default` never fires, a later mapping has the same keys and trigger",
+            ]
+        );
+    }
+
+    #[test]
+    fn labels_may_name_keys_and_state_variables() {
+        let config = Config::from_reader(
+            r#"{
+                "device": { "stdin": {} },
+                "key_map": { "stateful": { "0x02": "TOP" }, "stateless": {} },
+                "mappings": [
+                    { "keys": "TOP", "action": ["set:size=2", "toggle:eraser"], "trigger": "on_press" }
+                ],
+                "ui": { "labels": { "TOP": "Top", "size": "Brush Size", "eraser": "Eraser", "C9": "?" } }
+            }"#
+            .as_bytes(),
+            "test.json",
+        )
+        .unwrap();
+
+        assert_eq!(
+            config.unknown_keys(),
+            vec!["`ui.labels` key `C9` is not a key in `key_map` or a state variable"]
+        );
+    }
+
+    #[test]
+    fn dumped_config_loads_the_same() {
+        let config = Config::from_reader(
+            r#"{
+                "device": { "winusb": { "vid": "0xC251", "pid": 8246, "handshake": [
+                    { "write": "b5 0x00" }, { "expect": "A5 ??" }, { "delay_ms": 10 }
+                ] } },
+                "key_map": { "stateful": { "0x02": "TOP" }, "stateless": { "0x09": "CW" } },
+                "profiles": { "paint": [
+                    { "keys": "TOP", "action": ["A", "char:→"], "trigger": "hold" }
+                ] }
+            }"#
+            .as_bytes(),
+            "test.json",
+        )
+        .unwrap();
+
+        let dumped = config.to_json();
+        assert!(dumped.contains(r#""vid": "0xC251""#));
+        assert!(dumped.contains(r#""pid": "0x2036""#));
+        assert!(dumped.contains(r#""trigger": "on_hold""#));
+        assert!(dumped.contains(r#""co_press_window_ms": 50"#));
+        assert!(dumped.contains(r#""write": "B5 00""#));
+        assert!(dumped.contains(r#""expect": "A5 ??""#));
+        let reloaded = Config::from_reader(dumped.as_bytes(), "dumped.json").unwrap();
+        assert_eq!(reloaded.to_json(), dumped);
+    }
+
+    #[test]
+    fn later_files_override_earlier_ones() {
+        let dir = std::env::temp_dir().join(format!("routbox-merge-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let base = dir.join("base.json");
+        let local = dir.join("local.json");
+        fs::write(
+            &base,
+            r#"{
+                "device": { "stdin": {} },
+                "key_map": { "stateful": { "0x02": "TOP", "0x03": "C1" }, "stateless": {} },
+                "mappings": [{ "keys": "TOP", "action": "A", "trigger": "press" }],
+                "ui": { "theme": "dark" },
+            }"#,
+        )
+        .unwrap();
+        fs::write(
+            &local,
+            r#"{
+                "key_map": { "stateful": { "0x03": "C2" } },
+                "mappings": [{ "keys": "C2", "action": "B", "trigger": "press" }],
+            }"#,
+        )
+        .unwrap();
+
+        let config = Config::from_files(&[&base, &local]).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        // objects are merged, arrays replaced
+        assert_eq!(config.key_map.stateful["0x02"], "TOP");
+        assert_eq!(config.key_map.stateful["0x03"], "C2");
+        assert_eq!(config.mappings.len(), 1);
+        assert_eq!(config.mappings[0].keys, "C2");
+        assert_eq!(config.ui.theme, Theme::Dark);
+    }
+
+    #[test]
+    fn merged_devices_are_replaced_and_codes_normalized() {
+        let dir = std::env::temp_dir().join(format!("routbox-replace-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let base = dir.join("base.json");
+        let local = dir.join("local.json");
+        fs::write(
+            &base,
+            r#"{
+                "device": { "serial": { "serial_port": "COM3", "baud_rate": 115200 } },
+                "key_map": { "stateful": { "0x1a": "TOP", "0x03": "C1" }, "stateless": {} },
+            }"#,
+        )
+        .unwrap();
+        fs::write(
+            &local,
+            r#"{
+                "device": { "stdin": { "prefix": "left." } },
+                "key_map": { "stateful": { "26": "SIDE", "0X04": "C2" } },
+            }"#,
+        )
+        .unwrap();
+
+        let config = Config::from_files(&[&base, &local]).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        assert!(matches!(config.device, TourBoxDevice::Stdin { .. }));
+        assert_eq!(config.device.prefix(), Some("left."));
+        // `26` is `0x1a`, the later file wins
+        assert_eq!(config.key_map.stateful.len(), 3);
+        assert_eq!(config.key_map.stateful["0x1a"], "SIDE");
+        assert_eq!(config.key_map.stateful["0x04"], "C2");
+        assert_eq!(
+            config.key_map.decode(0x1a),
+            Some(InputEvent::KeyPressed("SIDE".to_string()))
+        );
+    }
+
+    #[test]
+    fn sections_can_live_in_their_own_files() {
+        let dir = std::env::temp_dir().join(format!("routbox-include-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let main = dir.join("config.json");
+        fs::write(
+            &main,
+            r#"{
+                "device": { "stdin": {} },
+                "key_map": { "$file": "keymap.json" },
+                "mappings": { "$file": "maps.json" },
+            }"#,
+        )
+        .unwrap();
+        fs::write(
+            dir.join("keymap.json"),
+            r#"{ "stateful": { "0x02": "TOP" }, "stateless": {} }"#,
+        )
+        .unwrap();
+        fs::write(
+            dir.join("maps.json"),
+            r#"[{ "keys": "TOP", "action": "A", "trigger": "press" }, ]"#,
+        )
+        .unwrap();
+
+        let config = Config::from_file(&main).unwrap();
+        assert_eq!(config.key_map.stateful["0x02"], "TOP");
+        assert_eq!(config.mappings[0].keys, "TOP");
+
+        fs::remove_file(dir.join("maps.json")).unwrap();
+        let missing = Config::from_file(&main).unwrap_err();
+        fs::remove_dir_all(&dir).unwrap();
+        assert!(matches!(missing, ConfigError::Io { path, .. } if path.ends_with("maps.json")));
+    }
+
+    #[test]
+    fn exported_layout_merges_over_another_config() {
+        let mine = Config::from_reader(
+            r#"{
+                "device": { "stdin": {} },
+                "key_map": { "stateful": { "0x02": "TOP" }, "stateless": {} },
+                "mappings": [{ "keys": "TOP", "action": "A", "trigger": "press" }],
+                "profiles": { "paint": [{ "keys": "TOP", "action": "B", "trigger": "hold" }] },
+                "key_press_ms": 20,
+            }"#
+            .as_bytes(),
+            "mine.json",
+        )
+        .unwrap();
+        let layout = mine.export_layout(false);
+        assert!(!layout.contains("device"));
+        assert!(!layout.contains("key_map"));
+        assert!(!layout.contains("key_press_ms"));
+        assert!(mine.export_layout(true).contains("key_map"));
+
+        let dir = std::env::temp_dir().join(format!("routbox-layout-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let theirs = dir.join("theirs.json");
+        let shared = dir.join("layout.json");
+        fs::write(
+            &theirs,
+            r#"{ "device": { "stdin": {} }, "key_map": { "stateful": {}, "stateless": {} } }"#,
+        )
+        .unwrap();
+        fs::write(&shared, &layout).unwrap();
+        let merged = Config::from_files(&[&theirs, &shared]).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(merged.mappings[0].action, vec!["A"]);
+        assert_eq!(
+            merged.profiles["paint"][0].trigger,
+            KeyTriggerTiming::OnHold
+        );
+    }
+
+    #[test]
+    fn imported_layout_replaces_mappings_with_the_same_keys() {
+        let mine = Config::from_reader(
+            r#"{
+                "device": { "stdin": {} },
+                "key_map": { "stateful": { "0x02": "TOP", "0x03": "SIDE" }, "stateless": {} },
+                "mappings": [
+                    { "keys": "TOP", "action": "A", "trigger": "press" },
+                    { "keys": "TOP", "action": "B", "trigger": "release" }
+                ],
+                "profiles": { "paint": [{ "keys": "TOP", "action": "C", "trigger": "hold" }] },
+                "sticky_keys": ["SIDE"],
+                "timers": [{ "every_ms": 100, "action": "D", "keys": "TOP" }],
+                "schedule": [{ "from": "22:00", "to": "06:00", "profile": "paint" }],
+                "ui": { "labels": { "TOP": "Top" } },
+            }"#
+            .as_bytes(),
+            "mine.json",
+        )
+        .unwrap();
+        let mut theirs = Config::from_reader(
+            r#"{
+                "device": { "stdin": {} },
+                "key_map": { "stateful": { "0x02": "TOP", "0x03": "SIDE" }, "stateless": {} },
+                "mappings": [
+                    { "keys": "TOP", "action": "X", "trigger": "press" },
+                    { "keys": "SIDE", "action": "Y", "trigger": "press" }
+                ],
+                "ui": { "theme": "dark", "labels": { "SIDE": "Side" } },
+            }"#
+            .as_bytes(),
+            "theirs.json",
+        )
+        .unwrap();
+
+        let layout: Layout = serde_json::from_str(&mine.export_layout(false)).unwrap();
+        theirs.import_layout(layout);
+        let mappings: Vec<(&str, &str)> = theirs
+            .mappings
+            .iter()
+            .map(|m| (m.keys.as_str(), m.action[0].as_str()))
+            .collect();
+        assert_eq!(mappings, [("SIDE", "Y"), ("TOP", "A"), ("TOP", "B")]);
+        assert_eq!(theirs.profiles["paint"][0].action, ["C"]);
+        assert_eq!(theirs.sticky_keys, ["SIDE"]);
+        assert_eq!(theirs.timers, mine.timers);
+        assert_eq!(theirs.schedule, mine.schedule);
+        assert_eq!(theirs.ui.label("TOP"), "Top");
+        assert_eq!(theirs.ui.label("SIDE"), "Side");
+        assert_eq!(theirs.ui.theme, Theme::Dark);
+
+        // importing the same layout again changes nothing
+        let once = theirs.to_json();
+        let layout: Layout = serde_json::from_str(&mine.export_layout(false)).unwrap();
+        theirs.import_layout(layout);
+        assert_eq!(theirs.to_json(), once);
+    }
+
+    #[test]
+    fn mappings_name_keys_with_or_without_the_device_prefix() {
+        let mut config = Config::from_reader(
+            r#"{
+                "device": { "stdin": { "prefix": "left." } },
+                "key_map": { "stateful": { "0x02": "TOP", "0x03": "SIDE" }, "stateless": {} },
+                "mappings": [
+                    { "keys": "left.SIDE", "action": "A", "trigger": "on_press" },
+                    { "keys": "left.TOP&SIDE", "action": "B", "trigger": "on_press" },
+                    { "keys": "right.SIDE", "action": "C", "trigger": "on_release" },
+                    { "keys": "SIDE", "action": ["lock:left.TOP", "left.A"], "trigger": "on_release" }
+                ],
+                "timers": [{ "every_ms": 100, "action": "lock:left.SIDE", "keys": "left.TOP" }],
+                "on_connect": "lock:left.TOP",
+                "shift_key": "left.TOP",
+                "pass_through": { "left.SIDE": "CTRL_L" },
+                "sticky_keys": ["left.TOP"],
+                "ui": { "labels": { "left.SIDE": "Side", "TOP": "Top" } }
+            }"#
+            .as_bytes(),
+            "test.json",
+        )
+        .unwrap();
+        config.strip_key_prefix();
+
+        let keys: Vec<&str> = config.mappings.iter().map(|m| m.keys.as_str()).collect();
+        assert_eq!(keys, ["SIDE", "TOP&SIDE", "right.SIDE", "SIDE"]);
+        // `left.A` is a key of the computer, only `lock:` names a TourBox key
+        assert_eq!(config.mappings[3].action, ["lock:TOP", "left.A"]);
+        assert_eq!(config.timers[0].keys.as_deref(), Some("TOP"));
+        assert_eq!(config.timers[0].action, ["lock:SIDE"]);
+        assert_eq!(config.on_connect, ["lock:TOP"]);
+        assert_eq!(config.shift_key.as_deref(), Some("TOP"));
+        assert_eq!(config.pass_through["SIDE"], "CTRL_L");
+        assert_eq!(config.sticky_keys, ["TOP"]);
+        assert_eq!(config.ui.label("SIDE"), "Side");
+        assert_eq!(config.ui.label("TOP"), "Top");
+        // another device's key is never sent by this one
+        assert_eq!(
+            config.validate(),
+            vec!["mapping `right.SIDE`: `right.SIDE` is not a key in `key_map`"]
+        );
     }
 }
@@ -0,0 +1,37 @@
+// Where the log goes while there is no console, see `free_own`, and with `--tui`
+pub fn log_path() -> std::path::PathBuf {
+    std::env::temp_dir().join("routbox.log")
+}
+
+// Closes the console window that opened with routbox when nothing else uses it, which is
+// how a console app started from Explorer looks. Started from a terminal, routbox shares
+// its console and keeps it. Debug builds always keep theirs. Returns whether it was closed.
+pub fn free_own() -> bool {
+    !cfg!(debug_assertions) && platform::free_own()
+}
+
+#[cfg(target_os = "windows")]
+mod platform {
+    #[link(name = "kernel32")]
+    unsafe extern "system" {
+        fn GetConsoleProcessList(process_ids: *mut u32, count: u32) -> u32;
+        fn FreeConsole() -> i32;
+    }
+
+    pub fn free_own() -> bool {
+        let mut process_ids = [0u32; 2];
+        // SAFETY: plain Win32 calls, the buffer outlives the call and its length is passed along
+        unsafe {
+            GetConsoleProcessList(process_ids.as_mut_ptr(), process_ids.len() as u32) == 1
+                && FreeConsole() != 0
+        }
+    }
+}
+
+// A console only opens with routbox on Windows
+#[cfg(not(target_os = "windows"))]
+mod platform {
+    pub fn free_own() -> bool {
+        false
+    }
+}
@@ -0,0 +1,764 @@
+use log::{error, info, warn};
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::Sender;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::config::{HandshakeStep, KeyMap, UnknownCodePolicy, code_name};
+use crate::event::{InputEvent, SourcedEvent};
+
+// One connection to a device, handing out the raw key codes it sends
+pub trait ByteStream {
+    // `Ok(None)` when nothing arrived in time. An error ends the connection, unless it is
+    // one that a single bad read causes, see `is_recoverable`.
+    fn read_byte(&mut self) -> io::Result<Option<u8>>;
+}
+
+// Hands out the bytes of multi-byte reads one at a time
+pub struct ReadBuffer {
+    buf: [u8; 64],
+    start: usize,
+    end: usize,
+}
+
+impl Default for ReadBuffer {
+    fn default() -> Self {
+        Self {
+            buf: [0; 64],
+            start: 0,
+            end: 0,
+        }
+    }
+}
+
+impl ReadBuffer {
+    // Calls `read` for the next chunk once all buffered bytes are taken. It fills the
+    // buffer and returns how many bytes it read, `0` if none arrived in time.
+    pub fn next_byte(
+        &mut self,
+        read: impl FnOnce(&mut [u8]) -> io::Result<usize>,
+    ) -> io::Result<Option<u8>> {
+        if self.start == self.end {
+            self.start = 0;
+            // a failed read leaves nothing buffered
+            self.end = 0;
+            self.end = read(&mut self.buf)?;
+            if self.end == 0 {
+                return Ok(None);
+            }
+        }
+        self.start += 1;
+        Ok(Some(self.buf[self.start - 1]))
+    }
+}
+
+// A device connection while it is being initialized
+pub trait HandshakePort {
+    fn send(&mut self, bytes: &[u8]) -> io::Result<()>;
+    // like the `read` of `ReadBuffer::next_byte`, `0` if nothing arrived in time
+    fn receive(&mut self, buf: &mut [u8]) -> io::Result<usize>;
+}
+
+// Runs the steps in order. A read step fails when its bytes don't arrive within `timeout`.
+// Returns every byte the steps read, a failed step fails the whole handshake.
+pub fn handshake(
+    steps: &[HandshakeStep],
+    timeout: Duration,
+    port: &mut impl HandshakePort,
+) -> io::Result<Vec<u8>> {
+    let mut buffer = ReadBuffer::default();
+    let mut response = vec![];
+    for (i, step) in steps.iter().enumerate() {
+        match step {
+            HandshakeStep::Write(bytes) => {
+                info!("Handshake step {}: writing {:02X?}", i + 1, bytes);
+                port.send(bytes)?;
+            }
+            HandshakeStep::Expect(pattern) => {
+                let bytes = read_bytes(&mut buffer, port, pattern.len(), timeout)?;
+                response.extend(&bytes);
+                let matches = bytes
+                    .iter()
+                    .zip(pattern)
+                    .all(|(byte, expected)| expected.is_none_or(|e| e == *byte));
+                if !matches {
+                    let expected: Vec<String> = pattern
+                        .iter()
+                        .map(|b| b.map_or("??".to_string(), |b| format!("{:02X}", b)))
+                        .collect();
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!(
+                            "handshake step {} expected [{}], the device sent {:02X?}",
+                            i + 1,
+                            expected.join(", "),
+                            bytes
+                        ),
+                    ));
+                }
+            }
+            HandshakeStep::Read(count) => {
+                let bytes = read_bytes(&mut buffer, port, *count, timeout)?;
+                info!("Handshake step {}: read {:02X?}", i + 1, bytes);
+                response.extend(bytes);
+            }
+            HandshakeStep::DelayMs(ms) => thread::sleep(Duration::from_millis(*ms)),
+        }
+    }
+    Ok(response)
+}
+
+fn read_bytes(
+    buffer: &mut ReadBuffer,
+    port: &mut impl HandshakePort,
+    count: usize,
+    timeout: Duration,
+) -> io::Result<Vec<u8>> {
+    let deadline = Instant::now() + timeout;
+    let mut bytes = Vec::with_capacity(count);
+    while bytes.len() < count {
+        if Instant::now() >= deadline {
+            return Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                format!(
+                    "the handshake got {} of {} bytes within {:?}",
+                    bytes.len(),
+                    count,
+                    timeout
+                ),
+            ));
+        }
+        if let Some(byte) = buffer.next_byte(|buf| port.receive(buf))? {
+            bytes.push(byte);
+        }
+    }
+    Ok(bytes)
+}
+
+// Requests to the device thread from the rest of the app. Clones share the requests, so
+// whoever handles the actions keeps one and `run` checks another.
+#[derive(Clone, Default)]
+pub struct DeviceControl {
+    reinit: Arc<AtomicBool>,
+    stop: Arc<AtomicBool>,
+}
+
+impl DeviceControl {
+    // The connection is closed and opened again, which sends the init command again
+    pub fn request_reinit(&self) {
+        self.reinit.store(true, Ordering::Relaxed);
+    }
+
+    // `run` closes the connection and returns, for good
+    pub fn request_stop(&self) {
+        self.stop.store(true, Ordering::Relaxed);
+    }
+
+    fn take_reinit(&self) -> bool {
+        self.reinit.swap(false, Ordering::Relaxed)
+    }
+
+    fn stopping(&self) -> bool {
+        self.stop.load(Ordering::Relaxed)
+    }
+
+    // Sleeps for `duration`, or less if a stop is requested meanwhile
+    fn sleep(&self, duration: Duration) {
+        let step = Duration::from_millis(100);
+        let mut left = duration;
+        while !left.is_zero() && !self.stopping() {
+            thread::sleep(left.min(step));
+            left = left.saturating_sub(step);
+        }
+    }
+}
+
+// how many recoverable read errors in a row end the connection anyway
+pub const MAX_READ_ERRORS: u32 = 5;
+
+// A read that came back short, overran or was interrupted, with the device still there.
+// Streams return `InvalidData` or `Interrupted` for those.
+pub fn is_recoverable(e: &io::Error) -> bool {
+    matches!(
+        e.kind(),
+        io::ErrorKind::InvalidData | io::ErrorKind::Interrupted
+    )
+}
+
+// Why `pump` stopped without a read error
+enum Stop {
+    ReceiverGone,
+    Reinit,
+    Requested,
+}
+
+// Connects and sends the events of every connection, each one between `Connected` and
+// `Disconnected`, until the receiver is gone. A failed connect is tried again after
+// `retry_delay`, a failed read or a reinit request of `control` connects again right away.
+// A stop request of `control` ends it without a last `Disconnected`. Every event is tagged
+// with `source`, the `prefix` of the device.
+pub fn run<S, C>(
+    name: &str,
+    source: Option<&str>,
+    mut connect: C,
+    key_map: &KeyMap,
+    sender: &Sender<SourcedEvent>,
+    retry_delay: Duration,
+    control: &DeviceControl,
+) where
+    S: ByteStream,
+    C: FnMut() -> io::Result<S>,
+{
+    loop {
+        let mut stream = loop {
+            if control.stopping() {
+                return;
+            }
+            match connect() {
+                Ok(stream) => break stream,
+                Err(e) => {
+                    warn!(
+                        "Could not initialize {} device: {}. Retrying in {:?}...",
+                        name, e, retry_delay
+                    );
+                    control.sleep(retry_delay);
+                }
+            }
+        };
+        // the device was just initialized, an earlier request is done with
+        control.take_reinit();
+        if sender
+            .send(SourcedEvent::new(InputEvent::Connected, source))
+            .is_err()
+        {
+            warn!("UI thread has been closed. Exiting {} thread.", name);
+            return;
+        }
+
+        match pump(&mut stream, source, key_map, sender, control) {
+            Err(e) => error!("{} read error: {}", name, e),
+            Ok(Stop::Reinit) => info!("Re-initializing the {} device", name),
+            Ok(Stop::ReceiverGone) => {
+                warn!("UI thread has been closed. Exiting {} thread.", name);
+                return;
+            }
+            // the stream is dropped on the way out, closing the connection
+            Ok(Stop::Requested) => {
+                info!("Stopping the {} thread", name);
+                return;
+            }
+        }
+        // the stream is closed before connecting again
+        drop(stream);
+        // a closed channel shows up on the next send
+        sender
+            .send(SourcedEvent::new(InputEvent::Disconnected, source))
+            .ok();
+    }
+}
+
+// Sends decoded events until the stream fails, the receiver is gone or a reinit or stop is
+// requested, which is noticed at the latest when a read times out. Recoverable errors are
+// skipped until `MAX_READ_ERRORS` of them come in a row.
+fn pump<S: ByteStream>(
+    stream: &mut S,
+    source: Option<&str>,
+    key_map: &KeyMap,
+    sender: &Sender<SourcedEvent>,
+    control: &DeviceControl,
+) -> io::Result<Stop> {
+    let mut errors = 0;
+    loop {
+        match stream.read_byte() {
+            Ok(Some(code)) => {
+                errors = 0;
+                let read = Instant::now();
+                let ev = match key_map.status_kind(code) {
+                    Some(kind) => match status_value(stream, code, control)? {
+                        Ok(value) => kind.event(value),
+                        Err(stop) => return Ok(stop),
+                    },
+                    None => key_map
+                        .decode(code)
+                        .unwrap_or_else(|| unknown(code, &key_map.unknown_code_policy)),
+                };
+                if sender
+                    .send(SourcedEvent::new(ev, source).with_read_at(read))
+                    .is_err()
+                {
+                    return Ok(Stop::ReceiverGone);
+                }
+            }
+            Ok(None) => {}
+            Err(e) if is_recoverable(&e) && errors + 1 < MAX_READ_ERRORS => {
+                errors += 1;
+                warn!("Skipping a failed read ({} in a row): {}", errors, e);
+            }
+            Err(e) => return Err(e),
+        }
+        if let Some(stop) = requested(control) {
+            return Ok(stop);
+        }
+    }
+}
+
+// What `control` asks `pump` to stop for, taking a reinit request
+fn requested(control: &DeviceControl) -> Option<Stop> {
+    if control.stopping() {
+        Some(Stop::Requested)
+    } else if control.take_reinit() {
+        Some(Stop::Reinit)
+    } else {
+        None
+    }
+}
+
+// how long the byte after a status code may take to arrive
+const STATUS_VALUE_TIMEOUT: Duration = Duration::from_secs(1);
+
+// The byte after the status code `code`, or `Err` with the stop if `control` asks for one
+// meanwhile. One that doesn't arrive within `STATUS_VALUE_TIMEOUT` is a `TimedOut` error.
+fn status_value<S: ByteStream>(
+    stream: &mut S,
+    code: u8,
+    control: &DeviceControl,
+) -> io::Result<Result<u8, Stop>> {
+    let deadline = Instant::now() + STATUS_VALUE_TIMEOUT;
+    loop {
+        if let Some(stop) = requested(control) {
+            return Ok(Err(stop));
+        }
+        if let Some(value) = stream.read_byte()? {
+            return Ok(Ok(value));
+        }
+        if Instant::now() >= deadline {
+            return Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                format!(
+                    "no value after status code 0x{:02x} within {:?}",
+                    code, STATUS_VALUE_TIMEOUT
+                ),
+            ));
+        }
+    }
+}
+
+// Follows the policy for a code that isn't in the key map, only a passthrough maps it
+fn unknown(code: u8, policy: &UnknownCodePolicy) -> InputEvent {
+    match policy {
+        UnknownCodePolicy::Warn => warn!("Unknown key code 0x{:02x}.", code),
+        UnknownCodePolicy::Ignore => {}
+        UnknownCodePolicy::Capture(path) => {
+            let appended = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .and_then(|mut file| writeln!(file, "{}", code_name(code)));
+            if let Err(e) = appended {
+                warn!(
+                    "Could not capture key code 0x{:02x} to '{}': {}",
+                    code, path, e
+                );
+            }
+        }
+        UnknownCodePolicy::Passthrough => return InputEvent::KeyPressed(code_name(code)),
+    }
+    InputEvent::Unknown(code)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::StatusKind;
+    use std::collections::{HashMap, VecDeque};
+    use std::sync::mpsc;
+
+    // Reads scripted as `Some(byte)` or `None` for a timeout, failing once they run out
+    struct Script(VecDeque<Option<u8>>);
+
+    impl ByteStream for Script {
+        fn read_byte(&mut self) -> io::Result<Option<u8>> {
+            self.0
+                .pop_front()
+                .ok_or_else(|| io::Error::new(io::ErrorKind::BrokenPipe, "unplugged"))
+        }
+    }
+
+    // Reads scripted with their errors, failing for good once they run out
+    struct Glitchy(VecDeque<io::Result<Option<u8>>>);
+
+    impl ByteStream for Glitchy {
+        fn read_byte(&mut self) -> io::Result<Option<u8>> {
+            self.0
+                .pop_front()
+                .unwrap_or_else(|| Err(io::Error::new(io::ErrorKind::BrokenPipe, "unplugged")))
+        }
+    }
+
+    // Never fails, so only a closed receiver ends `run`
+    struct Endless(u8);
+
+    impl ByteStream for Endless {
+        fn read_byte(&mut self) -> io::Result<Option<u8>> {
+            Ok(Some(self.0))
+        }
+    }
+
+    // Sends the one byte and then nothing, every read times out
+    struct Silent(Option<u8>);
+
+    impl ByteStream for Silent {
+        fn read_byte(&mut self) -> io::Result<Option<u8>> {
+            Ok(self.0.take())
+        }
+    }
+
+    // Answers every read with the next reply, nothing once they run out
+    struct Replies {
+        written: Vec<Vec<u8>>,
+        replies: VecDeque<Vec<u8>>,
+    }
+
+    impl HandshakePort for Replies {
+        fn send(&mut self, bytes: &[u8]) -> io::Result<()> {
+            self.written.push(bytes.to_vec());
+            Ok(())
+        }
+
+        fn receive(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let reply = self.replies.pop_front().unwrap_or_default();
+            buf[..reply.len()].copy_from_slice(&reply);
+            Ok(reply.len())
+        }
+    }
+
+    enum Connection {
+        Script(Script),
+        Endless(Endless),
+    }
+
+    impl ByteStream for Connection {
+        fn read_byte(&mut self) -> io::Result<Option<u8>> {
+            match self {
+                Connection::Script(s) => s.read_byte(),
+                Connection::Endless(s) => s.read_byte(),
+            }
+        }
+    }
+
+    fn key_map() -> KeyMap {
+        serde_json::from_str(r#"{ "stateful": { "0x02": "TOP" }, "stateless": { "0x03": "CW" } }"#)
+            .unwrap()
+    }
+
+    fn pressed(k: &str) -> InputEvent {
+        InputEvent::KeyPressed(k.to_owned())
+    }
+
+    #[test]
+    fn read_buffer_hands_out_every_byte() {
+        let mut buffer = ReadBuffer::default();
+        let mut reads = VecDeque::from([vec![0x02, 0x82, 0x03], vec![], vec![0x03]]);
+        let mut next = || {
+            buffer.next_byte(|buf| {
+                let read = reads.pop_front().unwrap();
+                buf[..read.len()].copy_from_slice(&read);
+                Ok(read.len())
+            })
+        };
+
+        assert_eq!(next().unwrap(), Some(0x02));
+        assert_eq!(next().unwrap(), Some(0x82));
+        assert_eq!(next().unwrap(), Some(0x03));
+        assert_eq!(next().unwrap(), None);
+        assert_eq!(next().unwrap(), Some(0x03));
+    }
+
+    #[test]
+    fn decodes_streams_and_reconnects() {
+        let mut connections = VecDeque::from([
+            Err(io::Error::new(io::ErrorKind::NotFound, "not plugged in")),
+            Ok(Connection::Script(Script(VecDeque::from([
+                Some(0x02),
+                None,
+                Some(0x55),
+                Some(0x82),
+            ])))),
+            Ok(Connection::Script(Script(VecDeque::from([Some(0x03)])))),
+        ]);
+        let (sender, receiver) = mpsc::channel();
+        let device = thread::spawn(move || {
+            let connect = || {
+                connections
+                    .pop_front()
+                    .unwrap_or(Ok(Connection::Endless(Endless(0x03))))
+            };
+            run(
+                "test",
+                Some("left."),
+                connect,
+                &key_map(),
+                &sender,
+                Duration::ZERO,
+                &DeviceControl::default(),
+            );
+        });
+
+        let events: Vec<SourcedEvent> = receiver.iter().take(9).collect();
+        // every event tells which device it came from
+        assert!(
+            events
+                .iter()
+                .all(|ev| ev.source.as_deref() == Some("left."))
+        );
+        let events: Vec<InputEvent> = events.into_iter().map(|ev| ev.event).collect();
+        assert_eq!(
+            events,
+            vec![
+                InputEvent::Connected,
+                pressed("TOP"),
+                InputEvent::Unknown(0x55),
+                InputEvent::KeyReleased("TOP".into()),
+                InputEvent::Disconnected,
+                InputEvent::Connected,
+                pressed("CW"),
+                InputEvent::Disconnected,
+                InputEvent::Connected,
+            ]
+        );
+        // the endless connection only ends once nobody listens anymore
+        drop(receiver);
+        device.join().unwrap();
+    }
+
+    #[test]
+    fn status_codes_report_the_next_byte() {
+        let mut key_map = key_map();
+        key_map.status = HashMap::from([
+            ("0x60".to_string(), StatusKind::Battery),
+            ("0x61".to_string(), StatusKind::LinkQuality),
+        ]);
+        let mut stream = Script(VecDeque::from([
+            Some(0x60),
+            None,
+            Some(0x55),
+            Some(0x61),
+            Some(0x02),
+            Some(0x02),
+        ]));
+        let (sender, receiver) = mpsc::channel();
+
+        let started = Instant::now();
+        let ended = pump(
+            &mut stream,
+            None,
+            &key_map,
+            &sender,
+            &DeviceControl::default(),
+        );
+        assert!(ended.is_err());
+        drop(sender);
+        let events: Vec<SourcedEvent> = receiver.iter().collect();
+        // stamped when the status code was read, before its value
+        assert!(events.iter().all(|ev| ev.at >= started));
+        assert!(events.windows(2).all(|pair| pair[0].at <= pair[1].at));
+        assert_eq!(
+            events.into_iter().map(|ev| ev.event).collect::<Vec<_>>(),
+            vec![
+                InputEvent::Battery(0x55),
+                InputEvent::LinkQuality(0x02),
+                pressed("TOP")
+            ]
+        );
+    }
+
+    #[test]
+    fn a_status_code_without_a_value_times_out() {
+        let mut key_map = key_map();
+        key_map.status = HashMap::from([("0x60".to_string(), StatusKind::Battery)]);
+        let (sender, receiver) = mpsc::channel();
+
+        // the port never answers after the status code
+        let mut stream = Silent(Some(0x60));
+        let ended = pump(
+            &mut stream,
+            None,
+            &key_map,
+            &sender,
+            &DeviceControl::default(),
+        );
+        assert_eq!(ended.err().map(|e| e.kind()), Some(io::ErrorKind::TimedOut));
+
+        // a stop request doesn't wait for the value
+        let control = DeviceControl::default();
+        control.request_stop();
+        let mut stream = Script(VecDeque::from([Some(0x60), Some(0x55)]));
+        let ended = pump(&mut stream, None, &key_map, &sender, &control);
+        assert!(matches!(ended, Ok(Stop::Requested)));
+        drop(sender);
+        assert_eq!(receiver.iter().count(), 0);
+    }
+
+    #[test]
+    fn a_bad_read_is_skipped_until_too_many_come_in_a_row() {
+        let glitch = || Err(io::Error::new(io::ErrorKind::InvalidData, "overrun"));
+        let mut reads = vec![Ok(Some(0x02)), glitch(), Ok(Some(0x82)), glitch()];
+        reads.extend((1..MAX_READ_ERRORS).map(|_| glitch()));
+        // never read, the errors before it end the connection
+        reads.push(Ok(Some(0x03)));
+        let mut stream = Glitchy(reads.into());
+        let (sender, receiver) = mpsc::channel();
+
+        let ended = pump(
+            &mut stream,
+            None,
+            &key_map(),
+            &sender,
+            &DeviceControl::default(),
+        );
+        assert_eq!(
+            ended.err().map(|e| e.kind()),
+            Some(io::ErrorKind::InvalidData)
+        );
+        drop(sender);
+        assert_eq!(
+            receiver.iter().map(|ev| ev.event).collect::<Vec<_>>(),
+            vec![pressed("TOP"), InputEvent::KeyReleased("TOP".into())]
+        );
+
+        // a device that is gone ends it right away
+        let mut stream = Glitchy(VecDeque::from([Err(io::Error::new(
+            io::ErrorKind::NotConnected,
+            "gone",
+        ))]));
+        let (sender, _receiver) = mpsc::channel();
+        let ended = pump(
+            &mut stream,
+            None,
+            &key_map(),
+            &sender,
+            &DeviceControl::default(),
+        );
+        assert_eq!(
+            ended.err().map(|e| e.kind()),
+            Some(io::ErrorKind::NotConnected)
+        );
+    }
+
+    #[test]
+    fn a_reinit_request_connects_again() {
+        let control = DeviceControl::default();
+        let device_control = control.clone();
+        let (sender, receiver) = mpsc::channel();
+        let device = thread::spawn(move || {
+            let mut connects = 0;
+            let connect = || {
+                connects += 1;
+                Ok(Endless(0x03))
+            };
+            run(
+                "test",
+                None,
+                connect,
+                &key_map(),
+                &sender,
+                Duration::ZERO,
+                &device_control,
+            );
+            connects
+        });
+
+        assert_eq!(receiver.recv().unwrap().event, InputEvent::Connected);
+        assert_eq!(receiver.recv().unwrap().event, pressed("CW"));
+        control.request_reinit();
+        let mut others = receiver
+            .iter()
+            .map(|ev| ev.event)
+            .filter(|ev| *ev != pressed("CW"));
+        assert_eq!(others.next(), Some(InputEvent::Disconnected));
+        assert_eq!(others.next(), Some(InputEvent::Connected));
+        drop(receiver);
+        assert_eq!(device.join().unwrap(), 2);
+    }
+
+    #[test]
+    fn handshake_runs_its_steps_in_order() {
+        let steps: Vec<HandshakeStep> = serde_json::from_str(
+            r#"[{ "write": "B5 00" }, { "expect": "A5 ??" }, { "read": 1 }, { "write": "0x01" }]"#,
+        )
+        .unwrap();
+        let run = |replies: Vec<Vec<u8>>| {
+            let mut port = Replies {
+                written: vec![],
+                replies: VecDeque::from(replies),
+            };
+            let result = handshake(&steps, Duration::from_millis(50), &mut port);
+            (result.map_err(|e| e.kind()), port.written)
+        };
+
+        assert_eq!(
+            run(vec![vec![0xA5], vec![], vec![0x07, 0x42]]),
+            (
+                Ok(vec![0xA5, 0x07, 0x42]),
+                vec![vec![0xB5, 0x00], vec![0x01]]
+            )
+        );
+        // the later steps never run
+        assert_eq!(
+            run(vec![vec![0xA6, 0x07, 0x42]]),
+            (Err(io::ErrorKind::InvalidData), vec![vec![0xB5, 0x00]])
+        );
+        assert_eq!(
+            run(vec![vec![0xA5]]),
+            (Err(io::ErrorKind::TimedOut), vec![vec![0xB5, 0x00]])
+        );
+    }
+
+    #[test]
+    fn a_stop_request_ends_run() {
+        let control = DeviceControl::default();
+        let device_control = control.clone();
+        let (sender, receiver) = mpsc::channel();
+        let device = thread::spawn(move || {
+            let connect = || Ok(Endless(0x03));
+            run(
+                "test",
+                None,
+                connect,
+                &key_map(),
+                &sender,
+                Duration::ZERO,
+                &device_control,
+            );
+        });
+
+        assert_eq!(receiver.recv().unwrap().event, InputEvent::Connected);
+        control.request_stop();
+        device.join().unwrap();
+        // no `Disconnected`, nothing fires `on_disconnect` on the way out
+        assert!(receiver.iter().all(|ev| ev.event == pressed("CW")));
+    }
+
+    #[test]
+    fn unknown_codes_follow_the_policy() {
+        let path = std::env::temp_dir().join(format!("routbox-unknown-{}.txt", std::process::id()));
+        let capture = UnknownCodePolicy::Capture(path.display().to_string());
+
+        assert_eq!(
+            unknown(0x55, &UnknownCodePolicy::Ignore),
+            InputEvent::Unknown(0x55)
+        );
+        assert_eq!(
+            unknown(0x55, &UnknownCodePolicy::Passthrough),
+            pressed("0x55")
+        );
+        assert_eq!(unknown(0x55, &capture), InputEvent::Unknown(0x55));
+        assert_eq!(unknown(0x0a, &capture), InputEvent::Unknown(0x0a));
+        let captured = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(captured, "0x55\n0x0a\n");
+    }
+}
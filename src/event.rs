@@ -1,5 +1,130 @@
-#[derive(Debug, Clone)]
+use serde::{Serialize, Serializer};
+use std::sync::mpsc::Sender;
+use std::time::Instant;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum InputEvent {
     KeyPressed(String),
     KeyReleased(String),
-}
\ No newline at end of file
+    // the device was (re)initialized or the connection to it was lost
+    Connected,
+    Disconnected,
+    // a code the key map has no key for, mapped to nothing but shown by `--probe`
+    Unknown(u8),
+    // status reports of wireless devices, see `KeyMap::status`
+    Battery(u8),
+    LinkQuality(u8),
+    // not from a device: `ResetRequest` asks the key thread for a `reset`
+    ResetRequested,
+}
+
+// The serialized form of `InputEvent`, `{"type": "KeyPressed", "key": "TOP"}`, kept apart
+// like the one of `TourAction`
+#[derive(Serialize)]
+#[serde(tag = "type")]
+enum TaggedEvent<'a> {
+    KeyPressed { key: &'a str },
+    KeyReleased { key: &'a str },
+    Connected,
+    Disconnected,
+    Unknown { code: u8 },
+    Battery { percent: u8 },
+    LinkQuality { percent: u8 },
+    ResetRequested,
+}
+
+impl Serialize for InputEvent {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let tagged = match self {
+            InputEvent::KeyPressed(key) => TaggedEvent::KeyPressed { key },
+            InputEvent::KeyReleased(key) => TaggedEvent::KeyReleased { key },
+            InputEvent::Connected => TaggedEvent::Connected,
+            InputEvent::Disconnected => TaggedEvent::Disconnected,
+            InputEvent::Unknown(code) => TaggedEvent::Unknown { code: *code },
+            InputEvent::Battery(percent) => TaggedEvent::Battery { percent: *percent },
+            InputEvent::LinkQuality(percent) => TaggedEvent::LinkQuality { percent: *percent },
+            InputEvent::ResetRequested => TaggedEvent::ResetRequested,
+        };
+        tagged.serialize(serializer)
+    }
+}
+
+// An event together with the device it came from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SourcedEvent {
+    pub event: InputEvent,
+    // the `prefix` of that device, `None` if it has none
+    pub source: Option<String>,
+    // when the device sent it, action latency is measured from here
+    pub at: Instant,
+}
+
+impl SourcedEvent {
+    pub fn new(event: InputEvent, source: Option<&str>) -> Self {
+        Self {
+            event,
+            source: source.map(str::to_owned),
+            at: Instant::now(),
+        }
+    }
+
+    // The time its code was read, when decoding took a while like for a status report
+    pub fn with_read_at(mut self, at: Instant) -> Self {
+        self.at = at;
+        self
+    }
+
+    // The key of a key event as mappings may name it, with the prefix like `left.SIDE`
+    pub fn key_name(&self) -> Option<String> {
+        match &self.event {
+            InputEvent::KeyPressed(k) | InputEvent::KeyReleased(k) => {
+                Some(format!("{}{}", self.source.as_deref().unwrap_or(""), k))
+            }
+            _ => None,
+        }
+    }
+}
+
+// A producer of input events, e.g. a TourBox connection.
+// `run` blocks and keeps feeding `sender` until the receiving side is closed.
+pub trait EventSource {
+    fn run(self, sender: Sender<SourcedEvent>);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn events_serialize_with_a_type_tag() {
+        let json = |ev: InputEvent| serde_json::to_value(ev).unwrap();
+
+        assert_eq!(
+            json(InputEvent::KeyReleased("TOP".into())),
+            json!({ "type": "KeyReleased", "key": "TOP" })
+        );
+        assert_eq!(json(InputEvent::Connected), json!({ "type": "Connected" }));
+        assert_eq!(
+            json(InputEvent::Battery(80)),
+            json!({ "type": "Battery", "percent": 80 })
+        );
+    }
+
+    #[test]
+    fn key_names_carry_the_prefix_of_their_source() {
+        let side = InputEvent::KeyPressed("SIDE".into());
+        assert_eq!(
+            SourcedEvent::new(side.clone(), Some("left.")).key_name(),
+            Some("left.SIDE".into())
+        );
+        assert_eq!(
+            SourcedEvent::new(side, None).key_name(),
+            Some("SIDE".into())
+        );
+        assert_eq!(
+            SourcedEvent::new(InputEvent::Connected, Some("left.")).key_name(),
+            None
+        );
+    }
+}
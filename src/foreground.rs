@@ -0,0 +1,170 @@
+use log::debug;
+use std::time::{Duration, Instant};
+
+// how long the foreground app that was looked up is trusted, looking it up can mean
+// running a program, which a fast dial spin shouldn't do for every tick
+const FOREGROUND_TTL: Duration = Duration::from_millis(250);
+
+// Which apps routbox acts in, matched against the foreground app
+pub struct AppFilter {
+    active_apps: Vec<String>,
+    inactive_apps: Vec<String>,
+    lookup: fn() -> Option<String>,
+    // when the foreground app was last looked up, and whether it allowed actions
+    last: Option<(Instant, bool)>,
+}
+
+impl AppFilter {
+    pub fn new(active_apps: &[String], inactive_apps: &[String]) -> Self {
+        let lowercase = |apps: &[String]| apps.iter().map(|a| a.to_lowercase()).collect();
+        Self {
+            active_apps: lowercase(active_apps),
+            inactive_apps: lowercase(inactive_apps),
+            lookup: platform::foreground_app,
+            last: None,
+        }
+    }
+
+    // Whether actions may be sent right now. Without a filter the foreground app is not looked
+    // up at all, and when it can't be found out, everything is allowed. The answer is
+    // reused for `FOREGROUND_TTL`.
+    pub fn allows_foreground(&mut self) -> bool {
+        self.allows_foreground_at(Instant::now())
+    }
+
+    fn allows_foreground_at(&mut self, now: Instant) -> bool {
+        if self.active_apps.is_empty() && self.inactive_apps.is_empty() {
+            return true;
+        }
+        if let Some((at, allowed)) = self.last
+            && now.duration_since(at) < FOREGROUND_TTL
+        {
+            return allowed;
+        }
+        let allowed = (self.lookup)().is_none_or(|app| {
+            debug!("Foreground app: {}", app);
+            self.allows(&app)
+        });
+        self.last = Some((now, allowed));
+        allowed
+    }
+
+    // `app` matches a pattern that appears anywhere in it, ignoring case
+    fn allows(&self, app: &str) -> bool {
+        let app = app.to_lowercase();
+        let matches = |patterns: &[String]| patterns.iter().any(|p| app.contains(p.as_str()));
+        (self.active_apps.is_empty() || matches(&self.active_apps)) && !matches(&self.inactive_apps)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    static LOOKUPS: AtomicUsize = AtomicUsize::new(0);
+
+    fn krita() -> Option<String> {
+        LOOKUPS.fetch_add(1, Ordering::SeqCst);
+        Some("Krita".to_string())
+    }
+
+    #[test]
+    fn the_foreground_app_is_looked_up_once_per_ttl() {
+        let mut filter = AppFilter::new(&[], &["krita".to_string()]);
+        filter.lookup = krita;
+        let start = Instant::now();
+
+        assert!(!filter.allows_foreground_at(start));
+        assert!(!filter.allows_foreground_at(start + FOREGROUND_TTL / 2));
+        assert_eq!(LOOKUPS.load(Ordering::SeqCst), 1);
+        assert!(!filter.allows_foreground_at(start + FOREGROUND_TTL));
+        assert_eq!(LOOKUPS.load(Ordering::SeqCst), 2);
+    }
+}
+
+// Full path of the foreground window's executable
+#[cfg(target_os = "windows")]
+mod platform {
+    use std::ffi::c_void;
+
+    type Handle = *mut c_void;
+
+    const PROCESS_QUERY_LIMITED_INFORMATION: u32 = 0x1000;
+
+    #[link(name = "user32")]
+    unsafe extern "system" {
+        fn GetForegroundWindow() -> Handle;
+        fn GetWindowThreadProcessId(window: Handle, process_id: *mut u32) -> u32;
+    }
+
+    #[link(name = "kernel32")]
+    unsafe extern "system" {
+        fn OpenProcess(access: u32, inherit_handle: i32, process_id: u32) -> Handle;
+        fn QueryFullProcessImageNameW(
+            process: Handle,
+            flags: u32,
+            name: *mut u16,
+            size: *mut u32,
+        ) -> i32;
+        fn CloseHandle(handle: Handle) -> i32;
+    }
+
+    pub fn foreground_app() -> Option<String> {
+        // SAFETY: plain Win32 calls, the handle is closed again and the buffer outlives the call
+        unsafe {
+            let window = GetForegroundWindow();
+            if window.is_null() {
+                return None;
+            }
+            let mut process_id = 0;
+            GetWindowThreadProcessId(window, &mut process_id);
+            let process = OpenProcess(PROCESS_QUERY_LIMITED_INFORMATION, 0, process_id);
+            if process.is_null() {
+                return None;
+            }
+            let mut name = [0u16; 1024];
+            let mut size = name.len() as u32;
+            let ok = QueryFullProcessImageNameW(process, 0, name.as_mut_ptr(), &mut size);
+            CloseHandle(process);
+            (ok != 0).then(|| String::from_utf16_lossy(&name[..size as usize]))
+        }
+    }
+}
+
+// Name of the frontmost application
+#[cfg(target_os = "macos")]
+mod platform {
+    use std::process::Command;
+
+    pub fn foreground_app() -> Option<String> {
+        let output = Command::new("osascript")
+            .args([
+                "-e",
+                "tell application \"System Events\" to get name of first application process whose frontmost is true",
+            ])
+            .output()
+            .ok()?;
+        output
+            .status
+            .success()
+            .then(|| String::from_utf8_lossy(&output.stdout).trim().to_string())
+    }
+}
+
+// Title of the active window, needs xdotool
+#[cfg(not(any(target_os = "windows", target_os = "macos")))]
+mod platform {
+    use std::process::Command;
+
+    pub fn foreground_app() -> Option<String> {
+        let output = Command::new("xdotool")
+            .args(["getactivewindow", "getwindowname"])
+            .output()
+            .ok()?;
+        output
+            .status
+            .success()
+            .then(|| String::from_utf8_lossy(&output.stdout).trim().to_string())
+    }
+}
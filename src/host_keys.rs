@@ -0,0 +1,70 @@
+// Whether this platform can tell which modifiers of the computer's keyboard are held
+pub const SUPPORTED: bool = cfg!(any(target_os = "windows", target_os = "macos"));
+
+// `key` is one of `HOST_MODIFIERS`, checked against the real keyboard right now
+pub fn is_held(key: &str) -> bool {
+    platform::is_held(key)
+}
+
+// GetAsyncKeyState sees keys held in any app, either side of the keyboard
+#[cfg(target_os = "windows")]
+mod platform {
+    const VK_SHIFT: i32 = 0x10;
+    const VK_CONTROL: i32 = 0x11;
+    const VK_MENU: i32 = 0x12;
+    const VK_LWIN: i32 = 0x5B;
+    const VK_RWIN: i32 = 0x5C;
+
+    #[link(name = "user32")]
+    unsafe extern "system" {
+        fn GetAsyncKeyState(key: i32) -> i16;
+    }
+
+    pub fn is_held(key: &str) -> bool {
+        let keys: &[i32] = match key {
+            "ALT" => &[VK_MENU],
+            "CTRL" => &[VK_CONTROL],
+            "SHIFT" => &[VK_SHIFT],
+            "WIN" => &[VK_LWIN, VK_RWIN],
+            _ => &[],
+        };
+        // SAFETY: a plain Win32 call, the top bit is set while the key is down
+        keys.iter()
+            .any(|k| unsafe { GetAsyncKeyState(*k) } as u16 & 0x8000 != 0)
+    }
+}
+
+// The modifier flags of the combined session, `WIN` is the command key
+#[cfg(target_os = "macos")]
+mod platform {
+    const COMBINED_SESSION_STATE: i32 = 0;
+    const FLAG_SHIFT: u64 = 0x0002_0000;
+    const FLAG_CONTROL: u64 = 0x0004_0000;
+    const FLAG_ALTERNATE: u64 = 0x0008_0000;
+    const FLAG_COMMAND: u64 = 0x0010_0000;
+
+    #[link(name = "CoreGraphics", kind = "framework")]
+    unsafe extern "C" {
+        fn CGEventSourceFlagsState(state: i32) -> u64;
+    }
+
+    pub fn is_held(key: &str) -> bool {
+        let flag = match key {
+            "ALT" => FLAG_ALTERNATE,
+            "CTRL" => FLAG_CONTROL,
+            "SHIFT" => FLAG_SHIFT,
+            "WIN" => FLAG_COMMAND,
+            _ => return false,
+        };
+        // SAFETY: a plain Core Graphics call without pointers
+        unsafe { CGEventSourceFlagsState(COMBINED_SESSION_STATE) & flag != 0 }
+    }
+}
+
+// Not looked up, mappings with host modifiers never fire
+#[cfg(not(any(target_os = "windows", target_os = "macos")))]
+mod platform {
+    pub fn is_held(_key: &str) -> bool {
+        false
+    }
+}
@@ -1,204 +1,3218 @@
-use std::collections::{HashMap, HashSet};
-
-use crate::{
-    config::{KeyMappingConfig, KeyTriggerTiming},
-    event::InputEvent,
-    key_sender::TourAction,
-};
-
-#[derive(Debug)]
-pub struct KeyMappingEntry {
-    trigger_key: String,
-    action: String,
-    modifier: Vec<String>,
-    trigger: KeyTriggerTiming,
-}
-
-pub struct KeyMappingProcessor {
-    // as the entrys won't change after it is created, usize is pointing to entrys
-    entrys: Vec<KeyMappingEntry>,
-    // config with mappings
-    mappings: HashMap<String, Vec<usize>>,
-    // store pressed_key of tourbox
-    pressed_key: HashSet<String>,
-    // outputed action
-    output_action: Vec<usize>,
-}
-
-impl KeyMappingProcessor {
-    fn get_actived_action(&self, ev: &InputEvent) -> Option<usize> {
-        // v.modifier key should not be possible more than 1000
-        let delta = match ev {
-            InputEvent::KeyPressed(_) => 1000,
-            InputEvent::KeyReleased(_) => -1000,
-        };
-
-        let k = match ev {
-            InputEvent::KeyPressed(k) => k,
-            InputEvent::KeyReleased(k) => k,
-        };
-
-        if let Some(key_mapping) = self.mappings.get(k) {
-            key_mapping
-                .iter()
-                .filter_map(|kk| {
-                    let k = &self.entrys[*kk];
-                    if k.modifier.iter().all(|k| self.pressed_key.contains(k)) {
-                        Some(*kk)
-                    } else {
-                        None
-                    }
-                })
-                .max_by_key(|kk| {
-                    let k = &self.entrys[*kk];
-
-                    k.modifier.len() as i32
-                        + match k.trigger {
-                            KeyTriggerTiming::OnPress => delta,
-                            KeyTriggerTiming::OnHold => 1000,
-                            KeyTriggerTiming::OnRelease => -delta,
-                        }
-                })
-        } else {
-            None
-        }
-    }
-
-    pub fn process(&mut self, ev: InputEvent) -> Vec<TourAction> {
-        println!("+{:?}", ev);
-        let actived_key_index = self.get_actived_action(&ev);
-        let actived_key = actived_key_index.as_ref().map(|k| &self.entrys[*k]);
-
-        let mut key_actions = vec![];
-
-        match ev {
-            InputEvent::KeyPressed(k) => {
-                if let Some(actived_key) = actived_key {
-                    match &actived_key.trigger {
-                        KeyTriggerTiming::OnPress => {
-                            println!("Action {}", actived_key.action);
-                            key_actions.push(TourAction::KeyClick(actived_key.action.clone()));
-                        }
-                        KeyTriggerTiming::OnHold => {
-                            let new_output_key: Vec<_> = actived_key.action.split("+").collect();
-
-                            let mut new_output_action: Vec<usize> = self
-                                .output_action
-                                .iter()
-                                .filter_map(|vk| {
-                                    let v = &self.entrys[*vk];
-                                    let b = actived_key
-                                        .modifier
-                                        .iter()
-                                        .any(|mv| v.modifier.contains(mv) || &v.trigger_key == mv);
-
-                                    if b {
-                                        for kb in v.action.split("+") {
-                                            // if we won't add back the key at new action (new_output_key), then release the key
-                                            if !new_output_key.contains(&kb) {
-                                                key_actions
-                                                    .push(TourAction::KeyRelease(kb.to_owned()));
-                                            }
-                                        }
-                                        None
-                                    } else {
-                                        Some(*vk)
-                                    }
-                                })
-                                .collect();
-
-                            for kb in new_output_key {
-                                // it is assumed that press a pressed key is fine
-                                key_actions.push(TourAction::KeyPress(kb.to_owned()));
-                            }
-
-                            new_output_action.push(actived_key_index.unwrap());
-
-                            drop(std::mem::replace(
-                                &mut self.output_action,
-                                new_output_action,
-                            ));
-                        }
-                        KeyTriggerTiming::OnRelease => {
-                            // do nothing on release
-                        }
-                    }
-                }
-                self.pressed_key.insert(k);
-            }
-            InputEvent::KeyReleased(k) => {
-                if let Some(actived_key) = actived_key {
-                    match &actived_key.trigger {
-                        KeyTriggerTiming::OnRelease => {
-                            println!("Action {}", actived_key.action);
-                            key_actions.push(TourAction::KeyClick(actived_key.action.clone()));
-                        }
-                        _ => {
-                            // do nothing
-                        }
-                    }
-                }
-
-                let new_hold_action: Vec<usize> = self
-                    .output_action
-                    .iter()
-                    .filter_map(|vk| {
-                        let v = &self.entrys[*vk];
-                        if v.trigger_key == k || v.modifier.iter().any(|mk| mk == &k) {
-                            // release hold action releated key when release the input key
-                            for kb in v.action.split("+") {
-                                key_actions.push(TourAction::KeyRelease(kb.to_owned()));
-                            }
-                            None
-                        } else {
-                            Some(*vk)
-                        }
-                    })
-                    .collect();
-
-                drop(std::mem::replace(&mut self.output_action, new_hold_action));
-                self.pressed_key.remove(&k);
-            }
-        }
-
-        key_actions
-    }
-
-    pub fn from_config(mappings: &Vec<KeyMappingConfig>) -> Self {
-        let mut trigger_key_map = HashMap::new();
-        let mut entrys = vec![];
-        mappings.iter().for_each(|m| {
-            let mut key_iter = m.keys.split("+");
-            let mut modifiers = vec![];
-            let mut trigger_key = key_iter
-                .next()
-                .expect("Should be at least contains one key")
-                .to_owned();
-            while let Some(k) = key_iter.next() {
-                modifiers.push(std::mem::replace(&mut trigger_key, k.to_owned()));
-            }
-            if !trigger_key_map.contains_key(&trigger_key) {
-                trigger_key_map.insert(trigger_key.clone(), vec![]);
-            }
-
-            trigger_key_map
-                .get_mut(&trigger_key)
-                .unwrap()
-                .push(entrys.len());
-
-            entrys.push(KeyMappingEntry {
-                trigger_key,
-                action: m.action.clone(),
-                modifier: modifiers,
-                trigger: m.trigger,
-            });
-        });
-
-        Self {
-            entrys,
-            mappings: trigger_key_map,
-            pressed_key: HashSet::new(),
-            output_action: vec![],
-        }
-    }
-}
+use log::{debug, info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::iter;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::{
+    action::{OsCommand, TourAction, WindowOp},
+    config::{KeyMappingConfig, KeyTriggerTiming, ScheduleConfig, TimerConfig},
+    event::{InputEvent, SourcedEvent},
+};
+
+#[derive(Debug, PartialEq)]
+enum MappedAction {
+    // a key or `+` separated chord, clicked or held depending on the trigger
+    Keys(String),
+    // `repeat_last`: clicks the last action fired by any other mapping again
+    RepeatLast,
+    // `char:→` or `u+2192`: types a unicode character
+    Text(String),
+    // `os:lock_screen`: runs a system action
+    Os(OsCommand),
+    // `win:snap_left`: moves or resizes the foreground window
+    Window(WindowOp),
+    // `mouse_to:<x>,<y>`: moves the mouse cursor there
+    MouseTo(i32, i32),
+    // `notify:Title|Body`: shows a desktop notification
+    Notify { title: String, body: String },
+    // `profile:<name>`, `push_profile:<name>` and `pop_profile`
+    SwitchProfile(String),
+    PushProfile(String),
+    PopProfile,
+    // `lock:<KEY>`: toggles whether a TourBox key counts as held for modifiers
+    Lock(String),
+    // `set:<name>=<value>` and `toggle:<name>`: change a state variable for `when`
+    SetState(String, i64),
+    ToggleState(String),
+    // `down:<keys>` and `up:<keys>`: only press or only release, whatever the trigger
+    Down(String),
+    Up(String),
+    // `release:<keys>`: releases those keys and forgets the holds that only held them
+    Release(String),
+    // `device:reinit`: connects to the device again, for when it gets stuck
+    ReinitDevice,
+    // `clipboard:set:<text>`: puts the text on the clipboard
+    SetClipboard(String),
+    // `clipboard:paste` and `clipboard:paste:<text>`: pastes, the text replaces the clipboard first
+    Paste(Option<String>),
+    // `ui:reset`: back to a clean state, see `KeyMappingProcessor::reset`
+    Reset,
+}
+
+impl MappedAction {
+    fn parse(action: &str) -> Self {
+        if let Some(text) = action.strip_prefix("char:") {
+            return MappedAction::Text(text.to_owned());
+        }
+        // 4 to 6 hex digits, anything else like `U+A` is a chord of the key `U`
+        if let Some(code_point) = action
+            .strip_prefix("u+")
+            .or_else(|| action.strip_prefix("U+"))
+            .filter(|c| (4..=6).contains(&c.len()) && c.chars().all(|c| c.is_ascii_hexdigit()))
+        {
+            match u32::from_str_radix(code_point, 16)
+                .ok()
+                .and_then(char::from_u32)
+            {
+                Some(c) => return MappedAction::Text(c.to_string()),
+                None => warn!("`{}` is not a valid unicode code point", action),
+            }
+        }
+        if let Some(name) = action.strip_prefix("os:") {
+            match OsCommand::from_name(name) {
+                Some(cmd) => return MappedAction::Os(cmd),
+                None => warn!("`{}` is not a known os command", action),
+            }
+        }
+        if let Some(name) = action.strip_prefix("win:") {
+            match WindowOp::from_name(name) {
+                Some(op) => return MappedAction::Window(op),
+                None => warn!("`{}` is not a known window action", action),
+            }
+        }
+        if let Some(position) = action.strip_prefix("mouse_to:") {
+            match position
+                .split_once(',')
+                .and_then(|(x, y)| Some((x.trim().parse().ok()?, y.trim().parse().ok()?)))
+            {
+                Some((x, y)) => return MappedAction::MouseTo(x, y),
+                None => warn!("`{}` is not a `mouse_to:<x>,<y>` action", action),
+            }
+        }
+        if let Some(name) = action.strip_prefix("device:") {
+            match name {
+                "reinit" => return MappedAction::ReinitDevice,
+                _ => warn!("`{}` is not a known device action", action),
+            }
+        }
+        if let Some(name) = action.strip_prefix("ui:") {
+            match name {
+                "reset" => return MappedAction::Reset,
+                _ => warn!("`{}` is not a known ui action", action),
+            }
+        }
+        if let Some(clipboard) = action.strip_prefix("clipboard:") {
+            if let Some(text) = clipboard.strip_prefix("set:") {
+                return MappedAction::SetClipboard(text.to_owned());
+            }
+            if clipboard == "paste" {
+                return MappedAction::Paste(None);
+            }
+            if let Some(text) = clipboard.strip_prefix("paste:") {
+                return MappedAction::Paste(Some(text.to_owned()));
+            }
+            warn!("`{}` is not a known clipboard action", action);
+        }
+        if let Some(text) = action.strip_prefix("notify:") {
+            let (title, body) = text.split_once('|').unwrap_or((text, ""));
+            return MappedAction::Notify {
+                title: title.to_owned(),
+                body: body.to_owned(),
+            };
+        }
+        if let Some(name) = action.strip_prefix("profile:") {
+            return MappedAction::SwitchProfile(name.to_owned());
+        }
+        if let Some(name) = action.strip_prefix("push_profile:") {
+            return MappedAction::PushProfile(name.to_owned());
+        }
+        if let Some(key) = action.strip_prefix("lock:") {
+            return MappedAction::Lock(key.to_owned());
+        }
+        if let Some(assignment) = action.strip_prefix("set:") {
+            match assignment
+                .split_once('=')
+                .and_then(|(name, value)| Some((name.trim(), parse_state_value(value)?)))
+            {
+                Some((name, value)) if !name.is_empty() => {
+                    return MappedAction::SetState(name.to_owned(), value);
+                }
+                _ => warn!("`{}` is not a `set:<name>=<value>` action", action),
+            }
+        }
+        if let Some(name) = action.strip_prefix("toggle:") {
+            return MappedAction::ToggleState(name.to_owned());
+        }
+        if let Some(keys) = action.strip_prefix("down:") {
+            return MappedAction::Down(keys.to_owned());
+        }
+        if let Some(keys) = action.strip_prefix("up:") {
+            return MappedAction::Up(keys.to_owned());
+        }
+        if let Some(keys) = action.strip_prefix("release:") {
+            return MappedAction::Release(keys.to_owned());
+        }
+        match action {
+            "repeat_last" => MappedAction::RepeatLast,
+            "pop_profile" => MappedAction::PopProfile,
+            keys => MappedAction::Keys(keys.to_owned()),
+        }
+    }
+
+    // keys that are pressed down while the action is held
+    fn hold_keys(&self) -> Vec<String> {
+        match self {
+            MappedAction::Keys(keys) => split_chord(keys),
+            _ => vec![],
+        }
+    }
+}
+
+// The key or chord an action sends, `None` for special actions like `char:` or `profile:`
+pub fn action_keys(action: &str) -> Option<String> {
+    match MappedAction::parse(action) {
+        MappedAction::Keys(keys)
+        | MappedAction::Down(keys)
+        | MappedAction::Up(keys)
+        | MappedAction::Release(keys) => Some(keys),
+        _ => None,
+    }
+}
+
+// State values are integers, `true` and `false` stand for 1 and 0
+fn parse_state_value(value: &str) -> Option<i64> {
+    match value.trim() {
+        "true" => Some(1),
+        "false" => Some(0),
+        v => v.parse().ok(),
+    }
+}
+
+// `when` of a mapping: `mode==1`, `mode!=1`, or just `mode` for any value but 0.
+// Variables that were never set are 0.
+#[derive(Debug)]
+struct Condition {
+    name: String,
+    value: i64,
+    equal: bool,
+}
+
+impl Condition {
+    fn parse(when: &str) -> Option<Self> {
+        let (name, value, equal) = if let Some((name, value)) = when.split_once("!=") {
+            (name, parse_state_value(value)?, false)
+        } else if let Some((name, value)) = when.split_once("==") {
+            (name, parse_state_value(value)?, true)
+        } else {
+            (when, 0, false)
+        };
+        let name = name.trim();
+        if name.is_empty() || name.contains(['=', '!']) {
+            return None;
+        }
+        Some(Self {
+            name: name.to_owned(),
+            value,
+            equal,
+        })
+    }
+
+    fn holds(&self, state: &HashMap<String, i64>) -> bool {
+        (state.get(&self.name).copied().unwrap_or(0) == self.value) == self.equal
+    }
+}
+
+// Splits a `+` separated chord into its keys, `\+` is a literal `+` and `\\` a literal `\`
+pub fn split_chord(chord: &str) -> Vec<String> {
+    let mut keys = vec![String::new()];
+    let mut chars = chord.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => keys.last_mut().unwrap().extend(chars.next().or(Some('\\'))),
+            '+' => keys.push(String::new()),
+            c => keys.last_mut().unwrap().push(c),
+        }
+    }
+    keys
+}
+
+// `(A|B)` is a modifier group that is held while either key is, any other key stands alone
+pub fn split_group(key: &str) -> Vec<String> {
+    match key.strip_prefix('(').and_then(|k| k.strip_suffix(')')) {
+        Some(group) => group.split('|').map(str::to_owned).collect(),
+        None => vec![key.to_owned()],
+    }
+}
+
+#[derive(Debug)]
+pub struct KeyMappingEntry {
+    trigger_key: String,
+    // the other key of a co-press (`A&B`), which has to be pressed together with trigger_key
+    co_press: Option<String>,
+    actions: Vec<MappedAction>,
+    // one of the keys of every group has to be held, most groups are a single key
+    modifier: Vec<Vec<String>>,
+    trigger: KeyTriggerTiming,
+    // the profile the mapping belongs to, `None` for the top level `mappings`
+    profile: Option<String>,
+    shifted: bool,
+    // presses it takes to fire an on_multi_tap mapping
+    tap_count: u32,
+    // an on_release mapping doesn't fire if the key was held longer
+    max_hold: Option<Duration>,
+    when: Option<Condition>,
+    // an on_press mapping of a dial key fires more often when the dial turns fast
+    accelerate: bool,
+    // keys of the computer's keyboard that have to be held, uppercase like `HOST_MODIFIERS`
+    host_modifiers: Vec<String>,
+    enabled: bool,
+    // a `default` mapping loses against every other one
+    fallback: bool,
+    // where the mapping is in its list of the config, `None` for hooks and timers
+    position: Option<usize>,
+    // a `PlaySound` sent after the actions
+    feedback: Option<TourAction>,
+}
+
+impl KeyMappingEntry {
+    fn is_triggered_by(&self, k: &str) -> bool {
+        self.trigger_key == k || self.co_press.as_deref() == Some(k)
+    }
+
+    // any mapping wins over a default one, shifted mappings over unshifted ones, then
+    // profile mappings over top level ones
+    fn precedence(&self) -> (bool, bool, bool) {
+        (!self.fallback, self.shifted, self.profile.is_some())
+    }
+
+    fn hold_keys(&self) -> Vec<String> {
+        self.actions.iter().flat_map(|a| a.hold_keys()).collect()
+    }
+
+    fn modifiers_held(&self, is_held: impl Fn(&str) -> bool) -> bool {
+        self.modifier
+            .iter()
+            .all(|group| group.iter().any(|k| is_held(k)))
+    }
+
+    fn has_modifier(&self, k: &str) -> bool {
+        self.modifier.iter().flatten().any(|mk| mk == k)
+    }
+
+    // a `when` condition and host modifiers narrow a mapping down like more modifiers
+    fn specificity(&self) -> usize {
+        self.modifier.len() + self.when.is_some() as usize + self.host_modifiers.len()
+    }
+
+    // Roughly how the mapping was written in the config, for logs
+    fn describe(&self) -> String {
+        let mut keys: Vec<String> = self
+            .modifier
+            .iter()
+            .map(|group| match group.as_slice() {
+                [key] => key.clone(),
+                _ => format!("({})", group.join("|")),
+            })
+            .collect();
+        keys.push(match &self.co_press {
+            Some(other) => format!("{}&{}", self.trigger_key, other),
+            None => self.trigger_key.clone(),
+        });
+        let profile = self
+            .profile
+            .as_ref()
+            .map_or(String::new(), |p| format!(" in `{}`", p));
+        format!(
+            "`{}` {:?}{} -> {:?}",
+            keys.join("+"),
+            self.trigger,
+            profile,
+            self.actions
+        )
+    }
+}
+
+// A press held back because it may become the first half of a co-press
+struct PendingPress {
+    key: String,
+    at: Instant,
+}
+
+// Presses of one key counted for its on_multi_tap mappings
+struct PendingTaps {
+    key: String,
+    count: u32,
+    // the last press, the window starts again with every press
+    at: Instant,
+    // the mappings that were available on the first press
+    entrys: Vec<usize>,
+}
+
+// Ticks of one dial key in a row, see `turn_dial`
+struct DialMotion {
+    key: String,
+    ticks: u32,
+    at: Instant,
+}
+
+// The state of a sticky key, see `with_sticky_keys`
+enum Sticky {
+    Idle,
+    // `used` once another key was pressed while it is held, its release doesn't arm it then
+    Held { used: bool },
+    // tapped at this time, counts as held until another key is pressed or it times out
+    Armed(Instant),
+}
+
+// A `timers` entry, `next` is set while its keys and `when` allow it to fire
+struct Timer {
+    entry: usize,
+    every: Duration,
+    next: Option<Instant>,
+}
+
+// An active profile, `held_by` is the key whose on_hold mapping pushed it
+struct ProfileLayer {
+    name: String,
+    held_by: Option<String>,
+}
+
+// Tells whether one of `HOST_MODIFIERS` is held on the computer's keyboard
+type HostKeys = Box<dyn Fn(&str) -> bool>;
+
+// The local time of day in minutes since midnight
+type Clock = Box<dyn Fn() -> u32>;
+
+// how often the schedule is checked against the clock
+const SCHEDULE_INTERVAL: Duration = Duration::from_secs(30);
+
+// The `UiAction` that follows the releases of a reset, the key sender releases
+// every key it still holds for it
+pub const RESET_ACTION: &str = "reset";
+
+// A `schedule` entry, `active` is `None` until the clock was first checked
+struct ScheduleRule {
+    from: u32,
+    to: u32,
+    profile: Option<String>,
+    variable: Option<String>,
+    active: Option<bool>,
+}
+
+impl ScheduleRule {
+    fn covers(&self, minute: u32) -> bool {
+        match self.from.cmp(&self.to) {
+            std::cmp::Ordering::Less => self.from <= minute && minute < self.to,
+            std::cmp::Ordering::Greater => minute >= self.from || minute < self.to,
+            std::cmp::Ordering::Equal => true,
+        }
+    }
+}
+
+// "22:30" as minutes since midnight
+fn parse_time_of_day(time: &str) -> Option<u32> {
+    let (hours, minutes) = time.trim().split_once(':')?;
+    let (hours, minutes): (u32, u32) = (hours.parse().ok()?, minutes.parse().ok()?);
+    (hours < 24 && minutes < 60).then_some(hours * 60 + minutes)
+}
+
+pub struct KeyMappingProcessor {
+    // as the entrys won't change after it is created, usize is pointing to entrys
+    entrys: Vec<KeyMappingEntry>,
+    // config with mappings
+    mappings: HashMap<String, Vec<usize>>,
+    // store pressed_key of tourbox
+    pressed_key: HashSet<String>,
+    // when each pressed key went down
+    pressed_at: HashMap<String, Instant>,
+    // outputed action
+    output_action: Vec<usize>,
+    // actions of the last clicked or held mapping, replayed by `repeat_last`
+    last_action: Vec<TourAction>,
+    // co-press mappings, not part of `mappings` as they are matched on two keys at once
+    co_press_entrys: Vec<usize>,
+    // how close together both keys of a co-press have to be pressed
+    co_press_window: Duration,
+    pending_press: Option<PendingPress>,
+    // keys pressed as part of a co-press, pointing to its entry
+    co_pressed_key: HashMap<String, usize>,
+    // how long after a press the next one still counts as a tap of the same series
+    multi_tap_window: Duration,
+    pending_taps: Option<PendingTaps>,
+    // how long a press waits for a modifier that would pick a more specific mapping
+    combo_grace: Duration,
+    pending_combo: Option<PendingPress>,
+    timers: Vec<Timer>,
+    profile_names: HashSet<String>,
+    // only the last profile is active, popping it goes back to the one before
+    profile_stack: Vec<ProfileLayer>,
+    shift_key: Option<String>,
+    // TourBox keys adding their host keys to what other mappings click while held
+    pass_through: BTreeMap<String, String>,
+    // TourBox keys locked on by `lock:`, treated as held modifiers
+    locked_key: HashSet<String>,
+    // entries fired on `InputEvent::Connected` / `Disconnected`, not bound to any key
+    on_connect: Option<usize>,
+    on_disconnect: Option<usize>,
+    // fired when a battery report drops below `low_battery_percent`
+    on_low_battery: Option<usize>,
+    low_battery_percent: u8,
+    // the last report was below it, so the next low one doesn't fire again
+    battery_low: bool,
+    // log how each key event picks its mapping
+    trace: bool,
+    // variables set by `set:` and `toggle:`, checked by `when`
+    state: HashMap<String, i64>,
+    // stateless keys sent by turning the dial, knob or scroll wheel
+    dial_keys: HashSet<String>,
+    // ticks of a turn swallowed before the next one fires
+    dial_dead_zone: u32,
+    // a pause this long starts the dead zone over
+    dial_idle: Duration,
+    dial_motion: Option<DialMotion>,
+    // TourBox keys that count as held for the next press of another key after a tap
+    sticky_keys: HashMap<String, Sticky>,
+    sticky_timeout: Duration,
+    // ticks closer together than this fire accelerated mappings twice, closer than half of
+    // it three times and so on, up to `dial_accel_max` times
+    dial_accel: Duration,
+    dial_accel_max: u32,
+    // how often the current tick fires accelerated mappings
+    dial_repeat: u32,
+    // whether a key of the computer's keyboard is held, for `host_modifiers`
+    host_keys: Option<HostKeys>,
+    switches: Option<MappingSwitches>,
+    schedule: Vec<ScheduleRule>,
+    clock: Option<Clock>,
+    schedule_checked: Option<Instant>,
+}
+
+type MappingId = (Option<String>, usize);
+
+// Turns mappings of the config on and off while the processor runs, e.g. from the window.
+// A mapping is known by its profile, `None` for the top level `mappings`, and its index there.
+#[derive(Clone, Default)]
+pub struct MappingSwitches {
+    disabled: Arc<Mutex<HashSet<MappingId>>>,
+    changed: Arc<AtomicBool>,
+}
+
+impl MappingSwitches {
+    // Starts with the `enabled` of every mapping
+    pub fn from_config(
+        mappings: &[KeyMappingConfig],
+        profiles: &HashMap<String, Vec<KeyMappingConfig>>,
+    ) -> Self {
+        let switches = Self::default();
+        let lists = iter::once((None, mappings)).chain(
+            profiles
+                .iter()
+                .map(|(name, ms)| (Some(name.as_str()), &ms[..])),
+        );
+        for (profile, mappings) in lists {
+            for (index, m) in mappings.iter().enumerate() {
+                switches.set_enabled(profile, index, m.enabled);
+            }
+        }
+        switches
+    }
+
+    pub fn is_enabled(&self, profile: Option<&str>, index: usize) -> bool {
+        let key = (profile.map(str::to_owned), index);
+        !self.disabled.lock().unwrap().contains(&key)
+    }
+
+    pub fn set_enabled(&self, profile: Option<&str>, index: usize, enabled: bool) {
+        let key = (profile.map(str::to_owned), index);
+        let mut disabled = self.disabled.lock().unwrap();
+        let changed = match enabled {
+            true => disabled.remove(&key),
+            false => disabled.insert(key),
+        };
+        if changed {
+            self.changed.store(true, Ordering::Relaxed);
+        }
+    }
+}
+
+// Asks the processor to `reset` from another thread, e.g. from a button of the window. It
+// sends `ResetRequested` to the key thread, which wakes it up like any other event. As a
+// sender of that channel it keeps the key thread running, drop it when it isn't needed.
+#[derive(Clone)]
+pub struct ResetRequest(Sender<SourcedEvent>);
+
+impl ResetRequest {
+    pub fn new(key_thread: Sender<SourcedEvent>) -> Self {
+        Self(key_thread)
+    }
+
+    pub fn request(&self) {
+        // a key thread that is gone has nothing left to reset
+        let _ = self
+            .0
+            .send(SourcedEvent::new(InputEvent::ResetRequested, None));
+    }
+}
+
+// What `set:`, `toggle:` and `lock:` changed, for keeping it across restarts
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProcessorState {
+    #[serde(default)]
+    pub variables: BTreeMap<String, i64>,
+    #[serde(default)]
+    pub locked: BTreeSet<String>,
+}
+
+// Keys of the computer's keyboard a mapping can require with `host_modifiers`
+pub const HOST_MODIFIERS: &[&str] = &["ALT", "CTRL", "SHIFT", "WIN"];
+
+// Name that `profile:` switches to for only the top level `mappings`
+pub const DEFAULT_PROFILE: &str = "default";
+
+impl KeyMappingProcessor {
+    fn is_available(&self, entry: &KeyMappingEntry) -> bool {
+        entry.enabled
+            && entry.modifiers_held(|k| self.is_held(k))
+            && self.in_active_profile(entry)
+            && (!entry.shifted || self.shift_held())
+            && self.condition_met(entry)
+            && self.host_modifiers_held(entry)
+    }
+
+    fn host_modifiers_held(&self, entry: &KeyMappingEntry) -> bool {
+        entry
+            .host_modifiers
+            .iter()
+            .all(|k| self.host_keys.as_ref().is_some_and(|held| held(k)))
+    }
+
+    fn condition_met(&self, entry: &KeyMappingEntry) -> bool {
+        entry.when.as_ref().is_none_or(|c| c.holds(&self.state))
+    }
+
+    fn is_held(&self, k: &str) -> bool {
+        self.pressed_key.contains(k)
+            || self.locked_key.contains(k)
+            || matches!(self.sticky_keys.get(k), Some(Sticky::Armed(_)))
+    }
+
+    fn shift_held(&self) -> bool {
+        self.shift_key
+            .as_ref()
+            .is_some_and(|k| self.pressed_key.contains(k))
+    }
+
+    fn in_active_profile(&self, entry: &KeyMappingEntry) -> bool {
+        match &entry.profile {
+            None => true,
+            Some(profile) => self
+                .profile_stack
+                .last()
+                .is_some_and(|l| &l.name == profile),
+        }
+    }
+
+    pub fn snapshot(&self) -> ProcessorState {
+        ProcessorState {
+            variables: self.state.clone().into_iter().collect(),
+            locked: self.locked_key.iter().cloned().collect(),
+        }
+    }
+
+    pub fn active_profile(&self) -> &str {
+        self.profile_stack
+            .last()
+            .map_or(DEFAULT_PROFILE, |l| l.name.as_str())
+    }
+
+    // Applies a profile action produced by a mapping, `held_by` for on_hold pushes
+    fn change_profile(&mut self, action: &TourAction, held_by: Option<String>) {
+        let name = match action {
+            TourAction::SwitchProfile(name) | TourAction::PushProfile(name) => name,
+            TourAction::PopProfile => {
+                if self.profile_stack.pop().is_none() {
+                    warn!("No profile to pop, staying in `{}`", DEFAULT_PROFILE);
+                }
+                return;
+            }
+            _ => return,
+        };
+        if name != DEFAULT_PROFILE && !self.profile_names.contains(name) {
+            warn!("Unknown profile `{}`", name);
+        }
+        if let TourAction::SwitchProfile(_) = action {
+            self.profile_stack.clear();
+            self.last_action.clear();
+            if name == DEFAULT_PROFILE {
+                return;
+            }
+        }
+        self.profile_stack.push(ProfileLayer {
+            name: name.clone(),
+            // only pushed profiles follow the key that pushed them
+            held_by: held_by.filter(|_| matches!(action, TourAction::PushProfile(_))),
+        });
+    }
+
+    // Releases the keys held by mappings other than `keep`, so nothing stays down across
+    // a profile change
+    fn release_outputs(&mut self, keep: Option<usize>) -> Vec<TourAction> {
+        let entrys = &self.entrys;
+        let kept = keep.map_or(vec![], |i| entrys[i].hold_keys());
+        let mut released = vec![];
+        self.output_action.retain(|i| {
+            if Some(*i) == keep {
+                return true;
+            }
+            for kb in entrys[*i].hold_keys().into_iter().rev() {
+                if !kept.contains(&kb) {
+                    released.push(TourAction::KeyRelease(kb));
+                }
+            }
+            false
+        });
+        released
+    }
+
+    // Locks or unlocks `key`, unlocking it releases what is held through it as a modifier
+    fn set_lock(&mut self, key: &str, locked: bool) -> Vec<TourAction> {
+        if locked {
+            self.locked_key.insert(key.to_owned());
+            return vec![];
+        }
+        self.locked_key.remove(key);
+        let mut released = vec![];
+        let (entrys, pressed, locked) = (&self.entrys, &self.pressed_key, &self.locked_key);
+        self.output_action.retain(|i| {
+            let v = &entrys[*i];
+            if !v.has_modifier(key)
+                || v.modifiers_held(|k| pressed.contains(k) || locked.contains(k))
+            {
+                return true;
+            }
+            for kb in v.hold_keys().into_iter().rev() {
+                released.push(TourAction::KeyRelease(kb));
+            }
+            false
+        });
+        released
+    }
+
+    // `keys` with the host keys of the held pass-through keys in front, except the ones the
+    // entry has as modifiers, as it asked for them itself
+    fn passed_through(&self, index: usize, keys: &str) -> String {
+        // wheel scrolls can't be part of a chord
+        let wheel = keys
+            .get(..6)
+            .is_some_and(|p| p.eq_ignore_ascii_case("WHEEL_"));
+        if wheel
+            || !self
+                .pass_through
+                .keys()
+                .any(|k| self.pressed_key.contains(k))
+        {
+            return keys.to_owned();
+        }
+        let entry = &self.entrys[index];
+        let mut chord: Vec<&str> = self
+            .pass_through
+            .iter()
+            .filter(|(k, _)| self.pressed_key.contains(*k) && !entry.has_modifier(k))
+            .map(|(_, host_keys)| host_keys.as_str())
+            .collect();
+        chord.push(keys);
+        chord.join("+")
+    }
+
+    // Releases `keys`, last first, and drops every hold that has nothing else held, so a
+    // hold still holding other keys releases those when its trigger is released
+    fn release_held(&mut self, keys: &[String]) -> Vec<TourAction> {
+        let entrys = &self.entrys;
+        self.output_action
+            .retain(|i| !entrys[*i].hold_keys().iter().all(|k| keys.contains(k)));
+        keys.iter()
+            .rev()
+            .map(|k| TourAction::KeyRelease(k.clone()))
+            .collect()
+    }
+
+    // Follows the turns of the dial keys and returns whether the tick is swallowed. The
+    // first ticks of every turn are, so resting a finger on the dial doesn't fire anything.
+    // A pause or a tick of another dial key starts over.
+    fn turn_dial(&mut self, k: &str, now: Instant) -> bool {
+        if !self.dial_keys.contains(k) {
+            return false;
+        }
+        let (ticks, interval) = match &self.dial_motion {
+            Some(m) if m.key == k && now.duration_since(m.at) <= self.dial_idle => {
+                (m.ticks + 1, Some(now.duration_since(m.at)))
+            }
+            _ => (1, None),
+        };
+        // the same key keeps its name, ticks come too often to allocate it every time
+        match &mut self.dial_motion {
+            Some(m) if m.key == k => {
+                m.ticks = ticks;
+                m.at = now;
+            }
+            motion => {
+                *motion = Some(DialMotion {
+                    key: k.to_owned(),
+                    ticks,
+                    at: now,
+                })
+            }
+        }
+        // the faster the ticks come, the more often accelerated mappings fire. Two ticks at
+        // the same instant give an infinite ratio, which saturates to the fastest.
+        self.dial_repeat = interval.map_or(1, |interval| {
+            let ratio = self.dial_accel.as_secs_f64() / interval.as_secs_f64();
+            (ratio.floor() as u32)
+                .saturating_add(1)
+                .clamp(1, self.dial_accel_max.max(1))
+        });
+        ticks <= self.dial_dead_zone
+    }
+
+    // whether pressing `k` could start a co-press
+    fn joins_co_press(&self, k: &str) -> bool {
+        self.co_press_entrys.iter().any(|i| {
+            let entry = &self.entrys[*i];
+            entry.is_triggered_by(k) && self.is_available(entry)
+        })
+    }
+
+    fn find_co_press(&self, first: &str, second: &str) -> Option<usize> {
+        self.co_press_entrys
+            .iter()
+            .copied()
+            .filter(|i| {
+                let entry = &self.entrys[*i];
+                entry.is_triggered_by(first)
+                    && entry.is_triggered_by(second)
+                    && first != second
+                    && self.is_available(entry)
+            })
+            .max_by_key(|i| {
+                let entry = &self.entrys[*i];
+                (entry.precedence(), entry.specificity())
+            })
+    }
+
+    // whether the entry would be available if `extra` were held as well
+    fn is_available_with(&self, entry: &KeyMappingEntry, extra: &str) -> bool {
+        entry.enabled
+            && entry.modifiers_held(|k| k == extra || self.is_held(k))
+            && self.in_active_profile(entry)
+            && (!entry.shifted || self.shift_held() || self.shift_key.as_deref() == Some(extra))
+            && self.condition_met(entry)
+            && self.host_modifiers_held(entry)
+    }
+
+    // mappings of `k` waiting for a single modifier that isn't held yet
+    fn combo_entrys(&self, k: &str) -> impl Iterator<Item = &KeyMappingEntry> {
+        self.mappings
+            .get(k)
+            .into_iter()
+            .flatten()
+            .map(|i| &self.entrys[*i])
+            .filter(|entry| !self.is_available(entry))
+    }
+
+    // whether pressing `k` should wait for a modifier, see `with_combo_grace`
+    fn awaits_modifier(&self, k: &str) -> bool {
+        !self.combo_grace.is_zero()
+            && self.combo_entrys(k).any(|entry| {
+                entry
+                    .modifier
+                    .iter()
+                    .flatten()
+                    .chain(self.shift_key.iter().filter(|_| entry.shifted))
+                    .any(|m| self.is_available_with(entry, m))
+            })
+    }
+
+    fn completes_combo(&self, k: &str, modifier: &str) -> bool {
+        self.combo_entrys(k)
+            .any(|entry| self.is_available_with(entry, modifier))
+    }
+
+    fn get_actived_action(&self, ev: &InputEvent) -> Option<usize> {
+        // v.modifier key should not be possible more than 1000
+        let (k, delta) = match ev {
+            InputEvent::KeyPressed(k) => (k, 1000),
+            InputEvent::KeyReleased(k) => (k, -1000),
+            // fire the hooks instead, see `process_at`
+            InputEvent::Connected
+            | InputEvent::Disconnected
+            | InputEvent::Unknown(_)
+            | InputEvent::Battery(_)
+            | InputEvent::LinkQuality(_)
+            | InputEvent::ResetRequested => {
+                return None;
+            }
+        };
+
+        let key_mapping = self.mappings.get(k)?;
+        let actived = key_mapping
+            .iter()
+            .filter_map(|kk| {
+                let k = &self.entrys[*kk];
+                // multi taps are counted separately, see `count_tap`
+                if self.is_available(k) && !matches!(k.trigger, KeyTriggerTiming::OnMultiTap) {
+                    Some(*kk)
+                } else {
+                    None
+                }
+            })
+            .max_by_key(|kk| Self::score(&self.entrys[*kk], delta));
+        if self.trace {
+            self.trace_candidates(ev, key_mapping, delta, actived);
+        }
+        actived
+    }
+
+    // The highest score wins, see `get_actived_action`
+    fn score(entry: &KeyMappingEntry, delta: i32) -> ((bool, bool, bool), i32) {
+        (
+            entry.precedence(),
+            entry.specificity() as i32
+                + match entry.trigger {
+                    KeyTriggerTiming::OnPress => delta,
+                    KeyTriggerTiming::OnHold => 1000,
+                    KeyTriggerTiming::OnRelease => -delta,
+                    KeyTriggerTiming::OnMultiTap => 0,
+                },
+        )
+    }
+
+    // Logs every mapping of the key with its score, or why it wasn't considered
+    fn trace_candidates(
+        &self,
+        ev: &InputEvent,
+        candidates: &[usize],
+        delta: i32,
+        actived: Option<usize>,
+    ) {
+        info!("{:?}: {} candidate(s)", ev, candidates.len());
+        for i in candidates {
+            let entry = &self.entrys[*i];
+            let verdict = if matches!(entry.trigger, KeyTriggerTiming::OnMultiTap) {
+                "skipped, counted as taps".to_string()
+            } else if !entry.enabled {
+                "skipped, disabled".to_string()
+            } else if !entry.modifiers_held(|k| self.is_held(k)) {
+                "skipped, modifiers not held".to_string()
+            } else if !self.in_active_profile(entry) {
+                "skipped, profile not active".to_string()
+            } else if !self.condition_met(entry) {
+                "skipped, `when` not met".to_string()
+            } else if !self.host_modifiers_held(entry) {
+                "skipped, host modifiers not held".to_string()
+            } else if !self.is_available(entry) {
+                "skipped, shift key not held".to_string()
+            } else {
+                let ((_, shifted, profile), rank) = Self::score(entry, delta);
+                let won = if actived == Some(*i) {
+                    ", selected"
+                } else {
+                    ""
+                };
+                format!(
+                    "score (default {}, shifted {}, profile {}, {}){}",
+                    entry.fallback, shifted, profile, rank, won
+                )
+            };
+            info!("  #{} {}: {}", i, entry.describe(), verdict);
+        }
+        if actived.is_none() {
+            info!("  no mapping selected");
+        }
+    }
+
+    pub fn process(&mut self, ev: InputEvent) -> Vec<TourAction> {
+        self.process_at(ev, Instant::now())
+    }
+
+    // Fires a pending press whose co-press window has closed, see `next_deadline`
+    pub fn tick(&mut self) -> Vec<TourAction> {
+        self.tick_at(Instant::now())
+    }
+
+    // When `tick` has to be called next, if some press is waiting for its co-press partner,
+    // some taps for the next one or a timer runs
+    pub fn next_deadline(&self) -> Option<Instant> {
+        let co_press = self
+            .pending_press
+            .as_ref()
+            .map(|p| p.at + self.co_press_window);
+        let taps = self
+            .pending_taps
+            .as_ref()
+            .map(|t| t.at + self.multi_tap_window);
+        let combo = self.pending_combo.as_ref().map(|p| p.at + self.combo_grace);
+        let timers = self.timers.iter().filter_map(|t| t.next);
+        let schedule = self
+            .schedule_checked
+            .filter(|_| !self.schedule.is_empty())
+            .map(|at| at + SCHEDULE_INTERVAL);
+        co_press
+            .into_iter()
+            .chain(taps)
+            .chain(combo)
+            .chain(timers)
+            .chain(schedule)
+            .min()
+    }
+
+    // Starts timers that became available, counting from `now`, and stops the others
+    fn schedule_timers(&mut self, now: Instant) {
+        for i in 0..self.timers.len() {
+            let available = self.is_available(&self.entrys[self.timers[i].entry]);
+            let timer = &mut self.timers[i];
+            timer.next = match timer.next {
+                Some(next) if available => Some(next),
+                None if available => Some(now + timer.every),
+                _ => None,
+            };
+        }
+    }
+
+    fn fire_timers(&mut self, now: Instant) -> Vec<TourAction> {
+        let mut key_actions = vec![];
+        for i in 0..self.timers.len() {
+            let timer = &mut self.timers[i];
+            if timer.next.is_none_or(|next| next > now) {
+                continue;
+            }
+            let next = timer.next.unwrap() + timer.every;
+            // a late tick doesn't catch up on the firings it missed
+            timer.next = Some(next.max(now));
+            let entry = timer.entry;
+            key_actions.extend(self.fire(entry, None));
+        }
+        key_actions
+    }
+
+    fn tick_at(&mut self, now: Instant) -> Vec<TourAction> {
+        self.apply_switches(false);
+        let mut key_actions = vec![];
+        let window = self.co_press_window;
+        if let Some(pending) = self
+            .pending_press
+            .take_if(|p| now.duration_since(p.at) >= window)
+        {
+            key_actions.extend(self.activate(InputEvent::KeyPressed(pending.key), now));
+            self.disarm_sticky_keys();
+        }
+        let window = self.multi_tap_window;
+        if let Some(taps) = self
+            .pending_taps
+            .take_if(|t| now.duration_since(t.at) >= window)
+        {
+            key_actions.extend(self.fire_taps(taps));
+        }
+        let grace = self.combo_grace;
+        if let Some(pending) = self
+            .pending_combo
+            .take_if(|p| now.duration_since(p.at) >= grace)
+        {
+            // no modifier came, it fires on its own
+            key_actions.extend(self.activate(InputEvent::KeyPressed(pending.key), now));
+            self.disarm_sticky_keys();
+        }
+        key_actions.extend(self.fire_timers(now));
+        self.schedule_timers(now);
+        if self
+            .schedule_checked
+            .is_none_or(|at| now.duration_since(at) >= SCHEDULE_INTERVAL)
+        {
+            key_actions.extend(self.check_schedule(now));
+        }
+        key_actions
+    }
+
+    // Activates what the schedule has for the current time and deactivates what it no
+    // longer has, only changes are sent
+    fn check_schedule(&mut self, now: Instant) -> Vec<TourAction> {
+        let Some(clock) = &self.clock else {
+            return vec![];
+        };
+        self.schedule_checked = Some(now);
+        let minute = clock();
+        let mut changes = vec![];
+        for rule in &mut self.schedule {
+            let active = rule.covers(minute);
+            if rule.active == Some(active) {
+                continue;
+            }
+            let starting = rule.active.is_none();
+            rule.active = Some(active);
+            if let Some(name) = &rule.variable {
+                changes.push(TourAction::SetState {
+                    name: name.clone(),
+                    value: active as i64,
+                });
+            }
+            match &rule.profile {
+                Some(profile) if active => changes.push(TourAction::SwitchProfile(profile.clone())),
+                // a profile chosen by hand in the meantime stays
+                Some(profile)
+                    if !starting && self.profile_stack.iter().any(|l| &l.name == profile) =>
+                {
+                    changes.push(TourAction::SwitchProfile(DEFAULT_PROFILE.to_string()))
+                }
+                _ => {}
+            }
+        }
+        let mut key_actions = vec![];
+        for change in changes {
+            match &change {
+                TourAction::SetState { name, value } => {
+                    // a restored state is kept when it already matches
+                    if self.state.get(name).copied().unwrap_or(0) == *value {
+                        continue;
+                    }
+                    self.state.insert(name.clone(), *value);
+                }
+                _ => {
+                    key_actions.extend(self.release_outputs(None));
+                    self.change_profile(&change, None);
+                }
+            }
+            key_actions.push(change);
+        }
+        key_actions
+    }
+
+    // Counts a press of `k` towards its on_multi_tap mappings. The highest count fires right
+    // away, lower ones once no further press follows within the window.
+    fn count_tap(&mut self, k: &str, now: Instant) -> Vec<TourAction> {
+        let mut key_actions = vec![];
+        let mut taps = match self.pending_taps.take() {
+            Some(taps) if taps.key == k && now.duration_since(taps.at) <= self.multi_tap_window => {
+                taps
+            }
+            other => {
+                // a different key or a late press ends the previous series
+                if let Some(taps) = other {
+                    key_actions.extend(self.fire_taps(taps));
+                }
+                let entrys: Vec<usize> = self.mappings.get(k).map_or(vec![], |v| {
+                    v.iter()
+                        .copied()
+                        .filter(|i| {
+                            let entry = &self.entrys[*i];
+                            matches!(entry.trigger, KeyTriggerTiming::OnMultiTap)
+                                && self.is_available(entry)
+                        })
+                        .collect()
+                });
+                if entrys.is_empty() {
+                    return key_actions;
+                }
+                PendingTaps {
+                    key: k.to_owned(),
+                    count: 0,
+                    at: now,
+                    entrys,
+                }
+            }
+        };
+        taps.count += 1;
+        taps.at = now;
+        let max_count = taps
+            .entrys
+            .iter()
+            .map(|i| self.entrys[*i].tap_count)
+            .max()
+            .unwrap_or(0);
+        if taps.count >= max_count {
+            key_actions.extend(self.fire_taps(taps));
+        } else {
+            self.pending_taps = Some(taps);
+        }
+        key_actions
+    }
+
+    fn fire_taps(&mut self, taps: PendingTaps) -> Vec<TourAction> {
+        let index = taps
+            .entrys
+            .iter()
+            .copied()
+            .filter(|i| self.entrys[*i].tap_count == taps.count)
+            .max_by_key(|i| {
+                let entry = &self.entrys[*i];
+                (entry.precedence(), entry.specificity())
+            });
+        index.map_or(vec![], |index| self.fire(index, None))
+    }
+
+    fn process_at(&mut self, ev: InputEvent, now: Instant) -> Vec<TourAction> {
+        debug!("+{:?}", ev);
+        self.apply_switches(false);
+        let consumes_sticky = self.track_sticky_keys(&ev, now);
+        let key_actions = self.handle(ev, now);
+        // a press held back for a co-press partner or a modifier uses them once it is
+        // decided, in `tick_at`
+        if let Some(k) = consumes_sticky
+            && self.pending_press.as_ref().is_none_or(|p| p.key != k)
+            && self.pending_combo.as_ref().is_none_or(|p| p.key != k)
+        {
+            self.disarm_sticky_keys();
+        }
+        self.schedule_timers(now);
+        key_actions
+    }
+
+    // Back to a clean state without restarting: releases what mappings hold, unlocks every
+    // locked key, sets every state variable to 0 and forgets held keys, armed sticky keys and
+    // presses waiting for a partner, a modifier or more taps. The profile stays as it is.
+    // Ends with `UiAction(RESET_ACTION)`, for the keys sent with `down:`.
+    pub fn reset(&mut self) -> Vec<TourAction> {
+        info!("Resetting the key state");
+        let mut actions = self.forget_input();
+        let mut locked: Vec<String> = self.locked_key.drain().collect();
+        locked.sort();
+        actions.extend(
+            locked
+                .into_iter()
+                .map(|key| TourAction::ModifierLock { key, locked: false }),
+        );
+        let mut variables: Vec<String> = self.state.drain().map(|(name, _)| name).collect();
+        variables.sort();
+        actions.extend(
+            variables
+                .into_iter()
+                .map(|name| TourAction::SetState { name, value: 0 }),
+        );
+        actions.push(TourAction::UiAction(RESET_ACTION.to_string()));
+        actions
+    }
+
+    // Releases what mappings hold and forgets held keys, armed sticky keys and presses
+    // waiting for a partner, a modifier or more taps. Locks and state variables stay.
+    fn forget_input(&mut self) -> Vec<TourAction> {
+        let actions = self.release_outputs(None);
+        self.pressed_key.clear();
+        self.pressed_at.clear();
+        self.co_pressed_key.clear();
+        self.pending_press = None;
+        self.pending_taps = None;
+        self.pending_combo = None;
+        self.dial_motion = None;
+        self.last_action.clear();
+        for sticky in self.sticky_keys.values_mut() {
+            *sticky = Sticky::Idle;
+        }
+        actions
+    }
+
+    // Arms a sticky key on its release after a tap and drops armed keys that timed out.
+    // Returns the key of a press that uses the armed keys.
+    fn track_sticky_keys(&mut self, ev: &InputEvent, now: Instant) -> Option<String> {
+        if self.sticky_keys.is_empty() {
+            return None;
+        }
+        for sticky in self.sticky_keys.values_mut() {
+            if let Sticky::Armed(at) = sticky
+                && now.duration_since(*at) > self.sticky_timeout
+            {
+                *sticky = Sticky::Idle;
+            }
+        }
+        match ev {
+            InputEvent::KeyPressed(k) if self.sticky_keys.contains_key(k) => {
+                self.sticky_keys
+                    .insert(k.clone(), Sticky::Held { used: false });
+                None
+            }
+            InputEvent::KeyPressed(k) => {
+                for sticky in self.sticky_keys.values_mut() {
+                    if let Sticky::Held { used } = sticky {
+                        *used = true;
+                    }
+                }
+                Some(k.clone())
+            }
+            InputEvent::KeyReleased(k) => {
+                if let Some(sticky) = self.sticky_keys.get_mut(k) {
+                    *sticky = match sticky {
+                        Sticky::Held { used: false } => Sticky::Armed(now),
+                        _ => Sticky::Idle,
+                    };
+                }
+                None
+            }
+            _ => None,
+        }
+    }
+
+    fn disarm_sticky_keys(&mut self) {
+        for sticky in self.sticky_keys.values_mut() {
+            if let Sticky::Armed(_) = sticky {
+                *sticky = Sticky::Idle;
+            }
+        }
+    }
+
+    fn handle(&mut self, ev: InputEvent, now: Instant) -> Vec<TourAction> {
+        let mut key_actions = vec![];
+
+        let hook = match ev {
+            InputEvent::Connected => Some(self.on_connect),
+            InputEvent::Disconnected => Some(self.on_disconnect),
+            InputEvent::Battery(level) => Some(self.battery_reported(level)),
+            InputEvent::ResetRequested => return self.reset(),
+            // nothing is mapped to it, and it doesn't end a co-press window either
+            InputEvent::Unknown(_) | InputEvent::LinkQuality(_) => return key_actions,
+            _ => None,
+        };
+
+        if let Some(pending) = self.pending_combo.take() {
+            if let InputEvent::KeyPressed(k) = &ev
+                && now.duration_since(pending.at) <= self.combo_grace
+                && self.completes_combo(&pending.key, k)
+            {
+                // the modifier was meant to be pressed first
+                key_actions.extend(self.handle(ev, now));
+                key_actions.extend(self.activate(InputEvent::KeyPressed(pending.key), now));
+                return key_actions;
+            }
+            key_actions.extend(self.activate(InputEvent::KeyPressed(pending.key), now));
+        }
+        // the releases of keys held on a device that is gone never arrive
+        if ev == InputEvent::Disconnected {
+            key_actions.extend(self.forget_input());
+        }
+        if let Some(hook) = hook {
+            key_actions.extend(hook.map_or(vec![], |index| self.fire(index, None)));
+            return key_actions;
+        }
+
+        let selects_only =
+            |k: &String| self.shift_key.as_ref() == Some(k) || self.pass_through.contains_key(k);
+        match &ev {
+            InputEvent::KeyPressed(k) if selects_only(k) => {
+                self.pressed_key.insert(k.clone());
+                return key_actions;
+            }
+            InputEvent::KeyReleased(k) if selects_only(k) => {
+                self.pressed_key.remove(k);
+                return key_actions;
+            }
+            _ => {}
+        }
+
+        if let InputEvent::KeyPressed(k) = &ev
+            && self.turn_dial(k, now)
+        {
+            return key_actions;
+        }
+
+        if let Some(pending) = self.pending_press.take() {
+            if let InputEvent::KeyPressed(k) = &ev
+                && now.duration_since(pending.at) <= self.co_press_window
+                && let Some(index) = self.find_co_press(&pending.key, k)
+            {
+                self.pressed_key.insert(pending.key.clone());
+                self.co_pressed_key.insert(pending.key, index);
+                self.co_pressed_key.insert(k.clone(), index);
+                return self.apply(ev, Some(index), now);
+            }
+            // not a co-press after all, the held back press goes first
+            key_actions.extend(self.activate(InputEvent::KeyPressed(pending.key), now));
+        }
+
+        match &ev {
+            InputEvent::KeyPressed(k) if self.joins_co_press(k) => {
+                self.pending_press = Some(PendingPress {
+                    key: k.clone(),
+                    at: now,
+                });
+            }
+            InputEvent::KeyPressed(k) if self.awaits_modifier(k) => {
+                self.pending_combo = Some(PendingPress {
+                    key: k.clone(),
+                    at: now,
+                });
+            }
+            InputEvent::KeyReleased(k) if self.co_pressed_key.contains_key(k) => {
+                // the keys of a co-press never trigger their own mappings,
+                // an on_release co-press fires when the first of its keys is released
+                let index = self.co_pressed_key.remove(k).unwrap();
+                let first_release = self.co_pressed_key.values().any(|i| *i == index);
+                key_actions.extend(self.apply(ev, first_release.then_some(index), now));
+            }
+            _ => key_actions.extend(self.activate(ev, now)),
+        }
+
+        key_actions
+    }
+
+    // Fires the entry's actions in order, remembering them for `repeat_last`. With `held_by`
+    // its keys are held by the caller instead of clicked, and profiles it pushes are popped
+    // again when that key is released.
+    fn fire(&mut self, index: usize, held_by: Option<&str>) -> Vec<TourAction> {
+        let mut fired = vec![];
+        let mut repeatable = vec![];
+        let mut repeated = false;
+        for i in 0..self.entrys[index].actions.len() {
+            let action = match &self.entrys[index].actions[i] {
+                MappedAction::Keys(keys) if held_by.is_some() => {
+                    // repeating a hold clicks its keys
+                    repeatable.push(TourAction::KeyClick(keys.clone()));
+                    continue;
+                }
+                MappedAction::Keys(keys) => TourAction::KeyClick(self.passed_through(index, keys)),
+                // sent as they are, without a matching release or press, so not repeated either
+                MappedAction::Down(keys) => {
+                    fired.extend(split_chord(keys).into_iter().map(TourAction::KeyPress));
+                    continue;
+                }
+                MappedAction::Up(keys) => {
+                    // in the order written, like `down:`
+                    let keys = split_chord(keys).into_iter();
+                    fired.extend(keys.map(TourAction::KeyRelease));
+                    continue;
+                }
+                MappedAction::Release(keys) => {
+                    let keys = split_chord(keys);
+                    fired.extend(self.release_held(&keys));
+                    continue;
+                }
+                MappedAction::Reset => {
+                    fired.extend(self.reset());
+                    continue;
+                }
+                MappedAction::Text(text) => TourAction::TypeText(text.clone()),
+                MappedAction::Os(cmd) => TourAction::OsCommand(*cmd),
+                MappedAction::Window(op) => TourAction::WindowAction(*op),
+                MappedAction::MouseTo(x, y) => TourAction::MouseMoveAbs { x: *x, y: *y },
+                MappedAction::ReinitDevice => TourAction::ReinitDevice,
+                MappedAction::SetClipboard(text) => TourAction::SetClipboard(text.clone()),
+                MappedAction::Paste(text) => TourAction::PasteClipboard(text.clone()),
+                MappedAction::Notify { title, body } => TourAction::Notify {
+                    title: title.clone(),
+                    body: body.clone(),
+                },
+                MappedAction::RepeatLast => {
+                    repeated = true;
+                    fired.extend(self.last_action.iter().cloned());
+                    continue;
+                }
+                // profile changes are not worth repeating
+                MappedAction::SwitchProfile(name) => TourAction::SwitchProfile(name.clone()),
+                MappedAction::PushProfile(name) => TourAction::PushProfile(name.clone()),
+                MappedAction::PopProfile => TourAction::PopProfile,
+                MappedAction::Lock(key) => TourAction::ModifierLock {
+                    key: key.clone(),
+                    locked: !self.locked_key.contains(key),
+                },
+                MappedAction::SetState(name, value) => TourAction::SetState {
+                    name: name.clone(),
+                    value: *value,
+                },
+                MappedAction::ToggleState(name) => TourAction::SetState {
+                    name: name.clone(),
+                    value: match self.state.get(name) {
+                        None | Some(0) => 1,
+                        Some(_) => 0,
+                    },
+                },
+            };
+            debug!("Action {:?}", action);
+            match &action {
+                TourAction::SwitchProfile(_)
+                | TourAction::PushProfile(_)
+                | TourAction::PopProfile => {
+                    fired.extend(self.release_outputs(Some(index)));
+                    self.change_profile(&action, held_by.map(str::to_owned))
+                }
+                TourAction::ModifierLock { key, locked } => {
+                    fired.extend(self.set_lock(key, *locked))
+                }
+                TourAction::SetState { name, value } => {
+                    self.state.insert(name.clone(), *value);
+                }
+                // a recovery tool, not something to repeat by accident
+                TourAction::ReinitDevice => {}
+                _ => repeatable.push(action.clone()),
+            }
+            fired.push(action);
+        }
+        if !repeated && !repeatable.is_empty() {
+            self.last_action = repeatable;
+        }
+        // confirms the mapping, not something `repeat_last` sends again
+        fired.extend(self.entrys[index].feedback.clone());
+        fired
+    }
+
+    fn activate(&mut self, ev: InputEvent, now: Instant) -> Vec<TourAction> {
+        let mut key_actions = match &ev {
+            InputEvent::KeyPressed(k) => self.count_tap(k, now),
+            _ => vec![],
+        };
+        let actived_key_index = self.get_actived_action(&ev);
+        key_actions.extend(self.apply(ev, actived_key_index, now));
+        key_actions
+    }
+
+    fn apply(
+        &mut self,
+        ev: InputEvent,
+        actived_key_index: Option<usize>,
+        now: Instant,
+    ) -> Vec<TourAction> {
+        let actived_key = actived_key_index.as_ref().map(|k| &self.entrys[*k]);
+
+        let mut key_actions = vec![];
+
+        match ev {
+            InputEvent::KeyPressed(k) => {
+                if let Some(actived_key) = actived_key {
+                    let index = actived_key_index.unwrap();
+                    match &actived_key.trigger {
+                        KeyTriggerTiming::OnHold => {
+                            let new_output_key = actived_key.hold_keys();
+
+                            if !new_output_key.is_empty() {
+                                let mut new_output_action: Vec<usize> = self
+                                    .output_action
+                                    .iter()
+                                    .filter_map(|vk| {
+                                        let v = &self.entrys[*vk];
+                                        let b =
+                                            actived_key.modifier.iter().flatten().any(|mv| {
+                                                v.has_modifier(mv) || &v.trigger_key == mv
+                                            });
+
+                                        if b {
+                                            // released in reverse, like chords are
+                                            for kb in v.hold_keys().into_iter().rev() {
+                                                // if we won't add back the key at new action (new_output_key), then release the key
+                                                if !new_output_key.contains(&kb) {
+                                                    key_actions.push(TourAction::KeyRelease(
+                                                        kb.to_owned(),
+                                                    ));
+                                                }
+                                            }
+                                            None
+                                        } else {
+                                            Some(*vk)
+                                        }
+                                    })
+                                    .collect();
+
+                                for kb in new_output_key {
+                                    // it is assumed that press a pressed key is fine
+                                    key_actions.push(TourAction::KeyPress(kb.to_owned()));
+                                }
+
+                                new_output_action.push(index);
+
+                                drop(std::mem::replace(
+                                    &mut self.output_action,
+                                    new_output_action,
+                                ));
+                            }
+                            // everything that can't be held is clicked, in order
+                            key_actions.extend(self.fire(index, Some(&k)));
+                        }
+                        KeyTriggerTiming::OnPress => {
+                            let repeat = if actived_key.accelerate && self.dial_keys.contains(&k) {
+                                self.dial_repeat
+                            } else {
+                                1
+                            };
+                            for _ in 0..repeat {
+                                key_actions.extend(self.fire(index, None));
+                            }
+                        }
+                        KeyTriggerTiming::OnRelease | KeyTriggerTiming::OnMultiTap => {
+                            // do nothing on release, taps are fired by `count_tap`
+                        }
+                    }
+                }
+                // dial ticks are never released, their entry is there already
+                match self.pressed_at.get_mut(&k) {
+                    Some(at) => *at = now,
+                    None => {
+                        self.pressed_at.insert(k.clone(), now);
+                    }
+                }
+                self.pressed_key.insert(k);
+            }
+            InputEvent::KeyReleased(k) => {
+                if let Some(actived_key) = actived_key {
+                    match &actived_key.trigger {
+                        KeyTriggerTiming::OnRelease => {
+                            // a long hold is doing something else, e.g. holding a profile
+                            let held_too_long = actived_key.max_hold.is_some_and(|max| {
+                                self.pressed_at
+                                    .get(&k)
+                                    .is_some_and(|at| now.duration_since(*at) > max)
+                            });
+                            if !held_too_long {
+                                key_actions.extend(self.fire(actived_key_index.unwrap(), None));
+                            }
+                        }
+                        _ => {
+                            // do nothing
+                        }
+                    }
+                }
+
+                let new_hold_action: Vec<usize> = self
+                    .output_action
+                    .iter()
+                    .filter_map(|vk| {
+                        let v = &self.entrys[*vk];
+                        // a group stays held while another of its keys is
+                        let modifier_released = v.has_modifier(&k)
+                            && !v.modifiers_held(|mk| mk != k && self.is_held(mk));
+                        if v.is_triggered_by(&k) || modifier_released {
+                            // release hold action releated key when release the input key,
+                            // last pressed first like the key sender does for clicked chords
+                            for kb in v.hold_keys().into_iter().rev() {
+                                key_actions.push(TourAction::KeyRelease(kb.to_owned()));
+                            }
+                            None
+                        } else {
+                            Some(*vk)
+                        }
+                    })
+                    .collect();
+
+                drop(std::mem::replace(&mut self.output_action, new_hold_action));
+
+                if let Some(i) = self
+                    .profile_stack
+                    .iter()
+                    .position(|l| l.held_by.as_ref() == Some(&k))
+                {
+                    self.profile_stack.remove(i);
+                    key_actions.extend(self.release_outputs(None));
+                    key_actions.push(TourAction::PopProfile);
+                }
+                self.pressed_key.remove(&k);
+                self.pressed_at.remove(&k);
+            }
+            InputEvent::Connected
+            | InputEvent::Disconnected
+            | InputEvent::Unknown(_)
+            | InputEvent::Battery(_)
+            | InputEvent::LinkQuality(_)
+            | InputEvent::ResetRequested => {}
+        }
+
+        key_actions
+    }
+
+    pub fn with_co_press_window(mut self, window: Duration) -> Self {
+        self.co_press_window = window;
+        self
+    }
+
+    // Logs the candidate mappings of every key event, their scores and which one wins
+    pub fn with_trace(mut self, trace: bool) -> Self {
+        self.trace = trace;
+        self
+    }
+
+    pub fn with_multi_tap_window(mut self, window: Duration) -> Self {
+        self.multi_tap_window = window;
+        self
+    }
+
+    // Holds a press back for up to `grace` when a modifier pressed right after it would
+    // pick a more specific mapping, zero fires it right away
+    pub fn with_combo_grace(mut self, grace: Duration) -> Self {
+        self.combo_grace = grace;
+        self
+    }
+
+    // Ignores the first `dead_zone` ticks of `keys` after every pause of `idle`
+    pub fn with_dial_dead_zone(
+        mut self,
+        keys: HashSet<String>,
+        dead_zone: u32,
+        idle: Duration,
+    ) -> Self {
+        self.dial_keys = keys;
+        self.dial_dead_zone = dead_zone;
+        self.dial_idle = idle;
+        self
+    }
+
+    // Picks up the variables and locks of a `snapshot`, e.g. one from the last session
+    pub fn with_state(mut self, state: ProcessorState) -> Self {
+        self.state = state.variables.into_iter().collect();
+        self.locked_key = state.locked.into_iter().collect();
+        self
+    }
+
+    // How fast dial ticks have to come for `accelerate` mappings to fire more than once
+    pub fn with_dial_acceleration(mut self, fast: Duration, max_repeat: u32) -> Self {
+        self.dial_accel = fast;
+        self.dial_accel_max = max_repeat;
+        self
+    }
+
+    // Looks up the keys of `host_modifiers`, called with one of `HOST_MODIFIERS`. Without
+    // it mappings with host modifiers never fire.
+    pub fn with_host_keys(mut self, is_held: impl Fn(&str) -> bool + 'static) -> Self {
+        self.host_keys = Some(Box::new(is_held));
+        self
+    }
+
+    pub fn from_config(mappings: &Vec<KeyMappingConfig>) -> Self {
+        let mut processor = Self {
+            entrys: vec![],
+            mappings: HashMap::new(),
+            pressed_key: HashSet::new(),
+            pressed_at: HashMap::new(),
+            output_action: vec![],
+            last_action: vec![],
+            co_press_entrys: vec![],
+            co_press_window: Duration::from_millis(50),
+            pending_press: None,
+            co_pressed_key: HashMap::new(),
+            multi_tap_window: Duration::from_millis(300),
+            pending_taps: None,
+            combo_grace: Duration::ZERO,
+            pending_combo: None,
+            timers: vec![],
+            profile_names: HashSet::new(),
+            profile_stack: vec![],
+            shift_key: None,
+            pass_through: BTreeMap::new(),
+            locked_key: HashSet::new(),
+            on_connect: None,
+            on_disconnect: None,
+            on_low_battery: None,
+            low_battery_percent: 0,
+            battery_low: false,
+            trace: false,
+            state: HashMap::new(),
+            dial_keys: HashSet::new(),
+            dial_dead_zone: 0,
+            dial_idle: Duration::from_millis(250),
+            dial_motion: None,
+            sticky_keys: HashMap::new(),
+            sticky_timeout: Duration::ZERO,
+            dial_accel: Duration::from_millis(100),
+            dial_accel_max: 4,
+            dial_repeat: 1,
+            host_keys: None,
+            switches: None,
+            schedule: vec![],
+            clock: None,
+            schedule_checked: None,
+        };
+        processor.add_mappings(mappings, None);
+        processor
+    }
+
+    // Adds the named sets of mappings that `profile:`, `push_profile:` and `pop_profile` switch between
+    pub fn with_profiles(mut self, profiles: &HashMap<String, Vec<KeyMappingConfig>>) -> Self {
+        for (name, mappings) in profiles {
+            self.profile_names.insert(name.clone());
+            self.add_mappings(mappings, Some(name));
+        }
+        self
+    }
+
+    // `key` only selects the mappings marked `shifted` and never fires any itself
+    pub fn with_shift_key(mut self, key: Option<String>) -> Self {
+        self.shift_key = key;
+        self
+    }
+
+    // While one of these keys is held, what other mappings click gets its host keys first,
+    // so `SIDE` set to `CTRL_L` makes a `Z` mapping click `CTRL_L+Z`. The keys themselves
+    // never fire mappings, like the shift key.
+    pub fn with_pass_through(mut self, pass_through: &HashMap<String, String>) -> Self {
+        self.pass_through = pass_through
+            .iter()
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .collect();
+        self
+    }
+
+    // A tap of one of `keys` makes it count as held for the next press of another key, so
+    // `SIDE+C1` fires for a tap of `SIDE` followed by `C1`. It stays armed for `timeout`.
+    pub fn with_sticky_keys(mut self, keys: &[String], timeout: Duration) -> Self {
+        self.sticky_keys = keys.iter().map(|k| (k.clone(), Sticky::Idle)).collect();
+        self.sticky_timeout = timeout;
+        self
+    }
+
+    // Actions fired when the device connects or disconnects
+    pub fn with_hooks(mut self, on_connect: &[String], on_disconnect: &[String]) -> Self {
+        self.on_connect = self.add_hook(on_connect);
+        self.on_disconnect = self.add_hook(on_disconnect);
+        self
+    }
+
+    // Actions fired when the battery level drops below `percent`, once until it is back up
+    pub fn with_low_battery_hook(mut self, on_low_battery: &[String], percent: u8) -> Self {
+        self.on_low_battery = self.add_hook(on_low_battery);
+        self.low_battery_percent = percent;
+        self
+    }
+
+    // The hook to fire for a battery report, only the first low one fires
+    fn battery_reported(&mut self, level: u8) -> Option<usize> {
+        let was_low = self.battery_low;
+        self.battery_low = level < self.low_battery_percent;
+        self.on_low_battery.filter(|_| self.battery_low && !was_low)
+    }
+
+    fn add_hook(&mut self, actions: &[String]) -> Option<usize> {
+        if actions.is_empty() {
+            return None;
+        }
+        self.entrys.push(KeyMappingEntry {
+            trigger_key: String::new(),
+            co_press: None,
+            actions: actions.iter().map(|a| MappedAction::parse(a)).collect(),
+            modifier: vec![],
+            trigger: KeyTriggerTiming::OnPress,
+            profile: None,
+            shifted: false,
+            tap_count: 1,
+            max_hold: None,
+            when: None,
+            accelerate: false,
+            host_modifiers: vec![],
+            enabled: true,
+            fallback: false,
+            position: None,
+            feedback: None,
+        });
+        Some(self.entrys.len() - 1)
+    }
+
+    // Fires the actions of every timer on its interval while it is available
+    pub fn with_timers(mut self, timers: &[TimerConfig]) -> Self {
+        for timer in timers {
+            if timer.every_ms == 0 {
+                warn!("A timer needs an `every_ms` above 0, ignoring it");
+                continue;
+            }
+            let when = match timer.when.as_deref().map(|w| (w, Condition::parse(w))) {
+                None => None,
+                Some((_, Some(when))) => Some(when),
+                Some((w, None)) => {
+                    warn!("A timer has an invalid `when` `{}`, ignoring it", w);
+                    continue;
+                }
+            };
+            let modifier = timer
+                .keys
+                .as_deref()
+                .map_or(vec![], split_chord)
+                .iter()
+                .map(|k| split_group(k))
+                .collect();
+            let Some(entry) = self.add_hook(&timer.action) else {
+                continue;
+            };
+            self.entrys[entry].modifier = modifier;
+            self.entrys[entry].when = when;
+            self.timers.push(Timer {
+                entry,
+                every: Duration::from_millis(timer.every_ms),
+                next: None,
+            });
+        }
+        self
+    }
+
+    // Follows the `schedule` with the local time of `clock`, in minutes since midnight. The
+    // clock is checked on `tick`, twice a minute.
+    pub fn with_schedule(
+        mut self,
+        schedule: &[ScheduleConfig],
+        clock: impl Fn() -> u32 + 'static,
+    ) -> Self {
+        for entry in schedule {
+            let (Some(from), Some(to)) =
+                (parse_time_of_day(&entry.from), parse_time_of_day(&entry.to))
+            else {
+                warn!(
+                    "Schedule `{}`-`{}` needs times like \"22:00\", ignoring it",
+                    entry.from, entry.to
+                );
+                continue;
+            };
+            self.schedule.push(ScheduleRule {
+                from,
+                to,
+                profile: entry.profile.clone(),
+                variable: entry.variable.clone(),
+                active: None,
+            });
+        }
+        self.clock = Some(Box::new(clock));
+        self
+    }
+
+    // Follows `switches` from now on, see `MappingSwitches`
+    pub fn with_switches(mut self, switches: MappingSwitches) -> Self {
+        self.switches = Some(switches);
+        self.apply_switches(true);
+        self
+    }
+
+    // Picks up mappings switched on or off since the last call, all of them with `all`
+    fn apply_switches(&mut self, all: bool) {
+        let Some(switches) = &self.switches else {
+            return;
+        };
+        if !switches.changed.swap(false, Ordering::Relaxed) && !all {
+            return;
+        }
+        for entry in &mut self.entrys {
+            if let Some(position) = entry.position {
+                entry.enabled = switches.is_enabled(entry.profile.as_deref(), position);
+            }
+        }
+    }
+
+    fn add_mappings(&mut self, mappings: &[KeyMappingConfig], profile: Option<&String>) {
+        mappings.iter().enumerate().for_each(|(position, m)| {
+            let mut modifiers = split_chord(&m.keys);
+            let trigger_key = modifiers
+                .pop()
+                .expect("Should be at least contains one key");
+            if split_group(&trigger_key).len() > 1 {
+                warn!("`{}` ends in a modifier group, ignoring it", m.keys);
+                return;
+            }
+            let modifiers: Vec<Vec<String>> = modifiers.iter().map(|k| split_group(k)).collect();
+            let when = match m.when.as_deref().map(|w| (w, Condition::parse(w))) {
+                None => None,
+                Some((_, Some(when))) => Some(when),
+                Some((w, None)) => {
+                    warn!("`{}` has an invalid `when` `{}`, ignoring it", m.keys, w);
+                    return;
+                }
+            };
+
+            let host_modifiers: Vec<String> =
+                m.host_modifiers.iter().map(|k| k.to_uppercase()).collect();
+            let feedback = m.feedback.as_deref().and_then(|f| match f {
+                "beep" => Some(TourAction::PlaySound(None)),
+                _ => match f.strip_prefix("sound:") {
+                    Some(path) => Some(TourAction::PlaySound(Some(path.to_owned()))),
+                    None => {
+                        warn!(
+                            "`{}` has an unknown `feedback` `{}`, ignoring it",
+                            m.keys, f
+                        );
+                        None
+                    }
+                },
+            });
+
+            if let Some((first, second)) = trigger_key.split_once('&') {
+                if second.contains('&') {
+                    warn!("Co-press `{}` has more than two keys, ignoring it", m.keys);
+                    return;
+                }
+                if let KeyTriggerTiming::OnMultiTap = m.trigger {
+                    warn!("Co-press `{}` can't be a multi tap, ignoring it", m.keys);
+                    return;
+                }
+                self.co_press_entrys.push(self.entrys.len());
+                self.entrys.push(KeyMappingEntry {
+                    trigger_key: first.to_owned(),
+                    co_press: Some(second.to_owned()),
+                    actions: m.action.iter().map(|a| MappedAction::parse(a)).collect(),
+                    modifier: modifiers,
+                    trigger: m.trigger,
+                    profile: profile.cloned(),
+                    shifted: m.shifted,
+                    tap_count: m.count,
+                    max_hold: m.max_hold_ms.map(Duration::from_millis),
+                    when,
+                    accelerate: m.accelerate,
+                    host_modifiers: host_modifiers.clone(),
+                    enabled: m.enabled,
+                    fallback: m.default,
+                    position: Some(position),
+                    feedback: feedback.clone(),
+                });
+                return;
+            }
+
+            if !self.mappings.contains_key(&trigger_key) {
+                self.mappings.insert(trigger_key.clone(), vec![]);
+            }
+
+            self.mappings
+                .get_mut(&trigger_key)
+                .unwrap()
+                .push(self.entrys.len());
+
+            self.entrys.push(KeyMappingEntry {
+                trigger_key,
+                co_press: None,
+                actions: m.action.iter().map(|a| MappedAction::parse(a)).collect(),
+                modifier: modifiers,
+                trigger: m.trigger,
+                profile: profile.cloned(),
+                shifted: m.shifted,
+                tap_count: m.count,
+                max_hold: m.max_hold_ms.map(Duration::from_millis),
+                when,
+                accelerate: m.accelerate,
+                host_modifiers,
+                enabled: m.enabled,
+                fallback: m.default,
+                position: Some(position),
+                feedback,
+            });
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use KeyTriggerTiming::{OnHold, OnMultiTap, OnPress, OnRelease};
+    use TourAction::{KeyClick, KeyPress, KeyRelease, TypeText};
+
+    fn configs(mappings: &[(&str, &str, KeyTriggerTiming)]) -> Vec<KeyMappingConfig> {
+        mappings
+            .iter()
+            .map(|(keys, action, trigger)| KeyMappingConfig {
+                keys: keys.to_string(),
+                action: vec![action.to_string()],
+                trigger: *trigger,
+                shifted: false,
+                count: 2,
+                max_hold_ms: None,
+                when: None,
+                accelerate: false,
+                host_modifiers: vec![],
+                default: false,
+                enabled: true,
+                feedback: None,
+            })
+            .collect()
+    }
+
+    fn processor(mappings: &[(&str, &str, KeyTriggerTiming)]) -> KeyMappingProcessor {
+        KeyMappingProcessor::from_config(&configs(mappings))
+    }
+
+    fn press(k: &str) -> InputEvent {
+        InputEvent::KeyPressed(k.to_owned())
+    }
+
+    fn release(k: &str) -> InputEvent {
+        InputEvent::KeyReleased(k.to_owned())
+    }
+
+    fn keys(f: fn(String) -> TourAction, keys: &[&str]) -> Vec<TourAction> {
+        keys.iter().map(|k| f(k.to_string())).collect()
+    }
+
+    #[test]
+    fn on_press_clicks_once() {
+        let mut p = processor(&[("KNOB_BTN", "X", OnPress)]);
+
+        assert_eq!(p.process(press("KNOB_BTN")), vec![KeyClick("X".into())]);
+        assert_eq!(p.process(release("KNOB_BTN")), vec![]);
+    }
+
+    #[test]
+    fn on_release_clicks_on_release() {
+        let mut p = processor(&[("C1", "CTRL_L+Z", OnRelease)]);
+
+        assert_eq!(p.process(press("C1")), vec![]);
+        assert_eq!(p.process(release("C1")), vec![KeyClick("CTRL_L+Z".into())]);
+    }
+
+    #[test]
+    fn unmapped_key_does_nothing() {
+        let mut p = processor(&[("KNOB_BTN", "X", OnPress)]);
+
+        assert_eq!(p.process(press("TOUR")), vec![]);
+        assert_eq!(p.process(release("TOUR")), vec![]);
+    }
+
+    #[test]
+    fn modifier_selects_more_specific_mapping() {
+        let mut p = processor(&[("KNOB_BTN", "X", OnPress), ("TOP+KNOB_BTN", "Y", OnPress)]);
+
+        assert_eq!(p.process(press("KNOB_BTN")), vec![KeyClick("X".into())]);
+        p.process(release("KNOB_BTN"));
+
+        p.process(press("TOP"));
+        assert_eq!(p.process(press("KNOB_BTN")), vec![KeyClick("Y".into())]);
+    }
+
+    #[test]
+    fn repeat_last_replays_previous_action() {
+        let mut p = processor(&[
+            ("C1", "CTRL_L+Z", OnPress),
+            ("TALL", "ALT_L", OnHold),
+            ("TOUR", "repeat_last", OnPress),
+        ]);
+
+        assert_eq!(p.process(press("TOUR")), vec![]);
+
+        p.process(press("C1"));
+        assert_eq!(p.process(press("TOUR")), vec![KeyClick("CTRL_L+Z".into())]);
+        // the repeat itself does not replace the remembered action
+        assert_eq!(p.process(press("TOUR")), vec![KeyClick("CTRL_L+Z".into())]);
+
+        p.process(press("TALL"));
+        p.process(release("TALL"));
+        assert_eq!(p.process(press("TOUR")), vec![KeyClick("ALT_L".into())]);
+    }
+
+    fn co_press_processor() -> KeyMappingProcessor {
+        processor(&[
+            ("C1", "1", OnPress),
+            ("C2", "2", OnRelease),
+            ("C1&C2", "3", OnPress),
+        ])
+        .with_co_press_window(Duration::from_millis(50))
+    }
+
+    #[test]
+    fn a_modifier_pressed_just_after_its_key_still_counts() {
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+        let grace = || {
+            processor(&[("SIDE", "1", OnPress), ("TOP+SIDE", "2", OnPress)])
+                .with_combo_grace(Duration::from_millis(30))
+        };
+
+        let mut p = grace();
+        assert_eq!(p.process_at(press("SIDE"), at(0)), vec![]);
+        assert_eq!(p.next_deadline(), Some(at(30)));
+        assert_eq!(
+            p.process_at(press("TOP"), at(10)),
+            vec![KeyClick("2".into())]
+        );
+        assert_eq!(p.next_deadline(), None);
+
+        // without a modifier it fires once the window is over
+        let mut p = grace();
+        assert_eq!(p.process_at(press("SIDE"), at(0)), vec![]);
+        assert_eq!(p.tick_at(at(30)), vec![KeyClick("1".into())]);
+
+        // the modifier already held, nothing waits
+        let mut p = grace();
+        p.process_at(press("TOP"), at(0));
+        assert_eq!(
+            p.process_at(press("SIDE"), at(10)),
+            vec![KeyClick("2".into())]
+        );
+    }
+
+    #[test]
+    fn timers_fire_while_their_keys_are_held() {
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+        let timer = |every_ms, action: &str, keys: Option<&str>| TimerConfig {
+            every_ms,
+            action: vec![action.to_string()],
+            keys: keys.map(str::to_string),
+            when: None,
+        };
+        let mut p = processor(&[("TOUR", "lock:C1", OnPress)]).with_timers(&[
+            timer(1000, "F5", None),
+            timer(200, "MOUSE_LEFT", Some("C1")),
+        ]);
+
+        assert_eq!(p.tick_at(at(0)), vec![]);
+        assert_eq!(p.next_deadline(), Some(at(1000)));
+        p.process_at(press("C1"), at(100));
+        assert_eq!(p.next_deadline(), Some(at(300)));
+        assert_eq!(p.tick_at(at(300)), vec![KeyClick("MOUSE_LEFT".into())]);
+        assert_eq!(p.tick_at(at(500)), vec![KeyClick("MOUSE_LEFT".into())]);
+        p.process_at(release("C1"), at(600));
+        assert_eq!(p.next_deadline(), Some(at(1000)));
+        assert_eq!(p.tick_at(at(1000)), vec![KeyClick("F5".into())]);
+        assert_eq!(p.next_deadline(), Some(at(2000)));
+
+        // a lock keeps it running without holding the key
+        p.process_at(press("TOUR"), at(1100));
+        p.process_at(release("TOUR"), at(1150));
+        assert_eq!(p.tick_at(at(1300)), vec![KeyClick("MOUSE_LEFT".into())]);
+    }
+
+    #[test]
+    fn schedule_follows_the_clock() {
+        let start = Instant::now();
+        let at = |s| start + Duration::from_secs(s);
+        let minute = std::rc::Rc::new(std::cell::Cell::new(8 * 60));
+        let clock = minute.clone();
+        let profiles = HashMap::from([("night".to_string(), configs(&[("C1", "N", OnPress)]))]);
+        let rule =
+            |from: &str, to: &str, profile: Option<&str>, variable: Option<&str>| ScheduleConfig {
+                from: from.into(),
+                to: to.into(),
+                profile: profile.map(str::to_string),
+                variable: variable.map(str::to_string),
+            };
+        let mut p = processor(&[("C1", "D", OnPress)])
+            .with_profiles(&profiles)
+            .with_schedule(
+                &[
+                    rule("09:00", "18:00", None, Some("work")),
+                    rule("22:00", "06:00", Some("night"), None),
+                ],
+                move || clock.get(),
+            );
+
+        // outside of both, nothing changes
+        assert_eq!(p.tick_at(at(0)), vec![]);
+        assert_eq!(p.next_deadline(), Some(at(30)));
+        minute.set(9 * 60);
+        assert_eq!(
+            p.tick_at(at(30)),
+            vec![TourAction::SetState {
+                name: "work".into(),
+                value: 1
+            }]
+        );
+        minute.set(23 * 60);
+        assert_eq!(
+            p.tick_at(at(60)),
+            vec![
+                TourAction::SetState {
+                    name: "work".into(),
+                    value: 0
+                },
+                TourAction::SwitchProfile("night".into())
+            ]
+        );
+        assert_eq!(
+            p.process_at(press("C1"), at(61)),
+            vec![KeyClick("N".into())]
+        );
+        minute.set(6 * 60);
+        assert_eq!(
+            p.tick_at(at(90)),
+            vec![TourAction::SwitchProfile(DEFAULT_PROFILE.into())]
+        );
+    }
+
+    #[test]
+    fn co_press_fires_instead_of_single_keys() {
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+
+        for (first, second) in [("C1", "C2"), ("C2", "C1")] {
+            let mut p = co_press_processor();
+            assert_eq!(p.process_at(press(first), at(0)), vec![]);
+            assert_eq!(p.next_deadline(), Some(at(50)));
+            assert_eq!(
+                p.process_at(press(second), at(20)),
+                vec![KeyClick("3".into())]
+            );
+            assert_eq!(p.process_at(release(first), at(100)), vec![]);
+            assert_eq!(p.process_at(release(second), at(110)), vec![]);
+            assert_eq!(p.next_deadline(), None);
+        }
+    }
+
+    #[test]
+    fn co_press_window_expires() {
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+        let mut p = co_press_processor();
+
+        assert_eq!(p.process_at(press("C1"), at(0)), vec![]);
+        assert_eq!(p.tick_at(at(30)), vec![]);
+        assert_eq!(p.tick_at(at(50)), vec![KeyClick("1".into())]);
+        assert_eq!(p.process_at(press("C2"), at(60)), vec![]);
+        assert_eq!(
+            p.process_at(release("C2"), at(70)),
+            vec![KeyClick("2".into())]
+        );
+    }
+
+    #[test]
+    fn co_press_flushes_on_other_event() {
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+        let mut p = co_press_processor();
+
+        p.process_at(press("C1"), at(0));
+        assert_eq!(
+            p.process_at(release("C1"), at(10)),
+            vec![KeyClick("1".into())]
+        );
+
+        p.process_at(press("C1"), at(20));
+        assert_eq!(
+            p.process_at(press("C2"), at(80)),
+            vec![KeyClick("1".into())]
+        );
+    }
+
+    #[test]
+    fn unicode_actions_type_text() {
+        let mut p = processor(&[
+            ("C1", "char:→", OnPress),
+            ("C2", "u+1F600", OnPress),
+            ("TOUR", "repeat_last", OnPress),
+        ]);
+
+        assert_eq!(p.process(press("C1")), vec![TypeText("→".into())]);
+        assert_eq!(p.process(press("C2")), vec![TypeText("😀".into())]);
+        assert_eq!(p.process(press("TOUR")), vec![TypeText("😀".into())]);
+    }
+
+    #[test]
+    fn chords_starting_with_u_are_not_code_points() {
+        let mut p = processor(&[
+            ("C1", "U+A", OnPress),
+            ("C2", "U+CTRL", OnPress),
+            ("TOUR", "u+00B0", OnPress),
+        ]);
+
+        assert_eq!(p.process(press("C1")), vec![KeyClick("U+A".into())]);
+        assert_eq!(p.process(press("C2")), vec![KeyClick("U+CTRL".into())]);
+        assert_eq!(p.process(press("TOUR")), vec![TypeText("°".into())]);
+        assert_eq!(action_keys("U+A"), Some("U+A".into()));
+    }
+
+    #[test]
+    fn os_actions_run_commands() {
+        let mut p = processor(&[
+            ("C1", "os:lock_screen", OnPress),
+            ("C2", "os:screenshot", OnHold),
+        ]);
+
+        assert_eq!(
+            p.process(press("C1")),
+            vec![TourAction::OsCommand(OsCommand::LockScreen)]
+        );
+        assert_eq!(
+            p.process(press("C2")),
+            vec![TourAction::OsCommand(OsCommand::Screenshot)]
+        );
+        assert_eq!(p.process(release("C2")), vec![]);
+    }
+
+    #[test]
+    fn switched_off_mappings_never_fire() {
+        let mut mappings = configs(&[("C1", "1", OnPress), ("C2", "2", OnPress)]);
+        mappings[1].enabled = false;
+        let switches = MappingSwitches::from_config(&mappings, &HashMap::new());
+        let mut p = KeyMappingProcessor::from_config(&mappings).with_switches(switches.clone());
+
+        assert_eq!(p.process(press("C2")), vec![]);
+        p.process(release("C2"));
+        switches.set_enabled(None, 0, false);
+        switches.set_enabled(None, 1, true);
+        assert_eq!(p.process(press("C1")), vec![]);
+        assert_eq!(p.process(press("C2")), vec![KeyClick("2".into())]);
+    }
+
+    #[test]
+    fn clipboard_actions_carry_their_text() {
+        let mut p = processor(&[
+            ("C1", "clipboard:set:→ done", OnPress),
+            ("C2", "clipboard:paste", OnPress),
+            ("C3", "clipboard:paste:a|b", OnPress),
+        ]);
+
+        assert_eq!(
+            p.process(press("C1")),
+            vec![TourAction::SetClipboard("→ done".into())]
+        );
+        assert_eq!(
+            p.process(press("C2")),
+            vec![TourAction::PasteClipboard(None)]
+        );
+        assert_eq!(
+            p.process(press("C3")),
+            vec![TourAction::PasteClipboard(Some("a|b".into()))]
+        );
+    }
+
+    #[test]
+    fn window_actions_parse_their_op() {
+        let mut p = processor(&[
+            ("C1", "win:snap_left", OnPress),
+            ("C2", "win:nope", OnPress),
+        ]);
+
+        assert_eq!(
+            p.process(press("C1")),
+            vec![TourAction::WindowAction(WindowOp::SnapLeft)]
+        );
+        // unknown names are sent as keys, which `--validate` reports
+        assert_eq!(p.process(press("C2")), vec![KeyClick("win:nope".into())]);
+    }
+
+    #[test]
+    fn notify_splits_title_and_body() {
+        let mut p = processor(&[
+            ("C1", "notify:Render|Done, 3 frames", OnPress),
+            ("C2", "notify:Hi", OnPress),
+        ]);
+
+        let notify = |title: &str, body: &str| TourAction::Notify {
+            title: title.into(),
+            body: body.into(),
+        };
+        assert_eq!(
+            p.process(press("C1")),
+            vec![notify("Render", "Done, 3 frames")]
+        );
+        assert_eq!(p.process(press("C2")), vec![notify("Hi", "")]);
+    }
+
+    #[test]
+    fn on_hold_releases_with_input_key() {
+        let mut p = processor(&[("TOP", "CTRL_L", OnHold)]);
+
+        assert_eq!(p.process(press("TOP")), keys(KeyPress, &["CTRL_L"]));
+        assert_eq!(p.process(release("TOP")), keys(KeyRelease, &["CTRL_L"]));
+        assert_eq!(p.process(release("TOP")), vec![]);
+    }
+
+    #[test]
+    fn modifier_hold_upgrades_held_chord() {
+        let mut p = processor(&[
+            ("TOP", "CTRL_L", OnHold),
+            ("TOP+TALL", "CTRL_L+SHIFT_L", OnHold),
+        ]);
+
+        assert_eq!(p.process(press("TOP")), keys(KeyPress, &["CTRL_L"]));
+        // CTRL_L is part of the new chord, so it is kept down instead of released
+        assert_eq!(
+            p.process(press("TALL")),
+            keys(KeyPress, &["CTRL_L", "SHIFT_L"])
+        );
+        // releasing either key of the combo releases the whole chord
+        assert_eq!(
+            p.process(release("TALL")),
+            keys(KeyRelease, &["SHIFT_L", "CTRL_L"])
+        );
+        assert_eq!(p.process(release("TOP")), vec![]);
+    }
+
+    #[test]
+    fn modifier_hold_replaces_unrelated_held_keys() {
+        let mut p = processor(&[
+            ("TOP", "CTRL_L", OnHold),
+            ("TOP+SHORT", "ALT_L+SPACE", OnHold),
+        ]);
+
+        p.process(press("TOP"));
+        let mut expected = keys(KeyRelease, &["CTRL_L"]);
+        expected.extend(keys(KeyPress, &["ALT_L", "SPACE"]));
+        assert_eq!(p.process(press("SHORT")), expected);
+
+        assert_eq!(
+            p.process(release("TOP")),
+            keys(KeyRelease, &["SPACE", "ALT_L"])
+        );
+        assert_eq!(p.process(release("SHORT")), vec![]);
+    }
+
+    #[test]
+    fn independent_holds_overlap() {
+        let mut p = processor(&[("TALL", "ALT_L", OnHold), ("SHORT", "SPACE", OnHold)]);
+
+        assert_eq!(p.process(press("TALL")), keys(KeyPress, &["ALT_L"]));
+        assert_eq!(p.process(press("SHORT")), keys(KeyPress, &["SPACE"]));
+        assert_eq!(p.process(release("TALL")), keys(KeyRelease, &["ALT_L"]));
+        assert_eq!(p.process(release("SHORT")), keys(KeyRelease, &["SPACE"]));
+    }
+
+    #[test]
+    fn held_profile_pops_on_release() {
+        let profiles = HashMap::from([("nav".to_string(), configs(&[("C1", "Y", OnPress)]))]);
+        let mut p = processor(&[("TOUR", "push_profile:nav", OnHold), ("C1", "X", OnPress)])
+            .with_profiles(&profiles);
+
+        assert_eq!(p.process(press("C1")), vec![KeyClick("X".into())]);
+        p.process(release("C1"));
+        assert_eq!(
+            p.process(press("TOUR")),
+            vec![TourAction::PushProfile("nav".into())]
+        );
+        assert_eq!(p.active_profile(), "nav");
+        assert_eq!(p.process(press("C1")), vec![KeyClick("Y".into())]);
+        p.process(release("C1"));
+        assert_eq!(p.process(release("TOUR")), vec![TourAction::PopProfile]);
+        assert_eq!(p.active_profile(), DEFAULT_PROFILE);
+        assert_eq!(p.process(press("C1")), vec![KeyClick("X".into())]);
+    }
+
+    #[test]
+    fn held_chords_are_released_on_a_profile_change() {
+        let profiles = HashMap::from([("nav".to_string(), configs(&[("C1", "Y", OnPress)]))]);
+        let mut p = processor(&[
+            ("C1", "CTRL_L+SHIFT_L", OnHold),
+            ("C2", "profile:nav", OnPress),
+            ("TOUR", "push_profile:nav", OnHold),
+            ("TALL", "ALT_L", OnHold),
+        ])
+        .with_profiles(&profiles);
+
+        assert_eq!(
+            p.process(press("C1")),
+            keys(KeyPress, &["CTRL_L", "SHIFT_L"])
+        );
+        assert_eq!(
+            p.process(press("C2")),
+            vec![
+                KeyRelease("SHIFT_L".into()),
+                KeyRelease("CTRL_L".into()),
+                TourAction::SwitchProfile("nav".into())
+            ]
+        );
+        // already released, nothing is sent twice
+        assert_eq!(p.process(release("C1")), vec![]);
+
+        p.process(press("C2"));
+        p.process(release("C2"));
+        p.process(press("TOUR"));
+        assert_eq!(p.process(press("TALL")), keys(KeyPress, &["ALT_L"]));
+        assert_eq!(
+            p.process(release("TOUR")),
+            vec![KeyRelease("ALT_L".into()), TourAction::PopProfile]
+        );
+    }
+
+    #[test]
+    fn reset_returns_to_a_clean_state() {
+        let mut mappings = configs(&[
+            ("C1", "CTRL_L+SHIFT_L", OnHold),
+            ("C2", "lock:TOP", OnPress),
+            ("TALL", "toggle:eraser", OnPress),
+            ("SHORT", "ui:reset", OnPress),
+            ("TOP+SIDE", "X", OnPress),
+            ("SIDE", "E", OnPress),
+        ]);
+        mappings[5].when = Some("eraser".into());
+        let mut p = KeyMappingProcessor::from_config(&mappings);
+        let reset = || TourAction::UiAction(RESET_ACTION.into());
+
+        p.process(press("C1"));
+        p.process(press("C2"));
+        p.process(press("TALL"));
+        assert_eq!(
+            p.process(press("SHORT")),
+            vec![
+                KeyRelease("SHIFT_L".into()),
+                KeyRelease("CTRL_L".into()),
+                TourAction::ModifierLock {
+                    key: "TOP".into(),
+                    locked: false
+                },
+                TourAction::SetState {
+                    name: "eraser".into(),
+                    value: 0
+                },
+                reset(),
+            ]
+        );
+        // nothing stays held, locked or set
+        assert_eq!(p.process(release("C1")), vec![]);
+        assert_eq!(p.process(press("SIDE")), vec![]);
+
+        p.process(press("C2"));
+        // as the window's button asks for it
+        assert_eq!(
+            p.process(InputEvent::ResetRequested),
+            vec![
+                TourAction::ModifierLock {
+                    key: "TOP".into(),
+                    locked: false
+                },
+                reset(),
+            ]
+        );
+        assert_eq!(p.tick(), vec![]);
+    }
+
+    #[test]
+    fn feedback_follows_the_actions_of_its_mapping() {
+        let mut mappings = configs(&[
+            ("C1", "os:lock_screen", OnPress),
+            ("C2", "X", OnRelease),
+            ("TOUR", "repeat_last", OnPress),
+            ("TALL", "Y", OnPress),
+        ]);
+        mappings[0].feedback = Some("beep".into());
+        mappings[1].feedback = Some("sound:done.wav".into());
+        mappings[3].feedback = Some("buzz".into());
+        let mut p = KeyMappingProcessor::from_config(&mappings);
+
+        assert_eq!(
+            p.process(press("C1")),
+            vec![
+                TourAction::OsCommand(OsCommand::LockScreen),
+                TourAction::PlaySound(None)
+            ]
+        );
+        p.process(press("C2"));
+        assert_eq!(
+            p.process(release("C2")),
+            vec![
+                KeyClick("X".into()),
+                TourAction::PlaySound(Some("done.wav".into()))
+            ]
+        );
+        // repeating doesn't sound again, an unknown feedback is left out
+        assert_eq!(p.process(press("TOUR")), vec![KeyClick("X".into())]);
+        assert_eq!(p.process(press("TALL")), vec![KeyClick("Y".into())]);
+    }
+
+    #[test]
+    fn pop_profile_returns_to_previous() {
+        let profiles = HashMap::from([
+            ("edit".to_string(), configs(&[("C2", "E", OnPress)])),
+            ("nav".to_string(), configs(&[("C2", "N", OnPress)])),
+        ]);
+        let mut p = processor(&[
+            ("SHORT", "profile:edit", OnPress),
+            ("C1", "push_profile:nav", OnPress),
+            ("TOUR", "pop_profile", OnPress),
+            ("C2", "B", OnPress),
+        ])
+        .with_profiles(&profiles);
+
+        assert_eq!(
+            p.process(press("SHORT")),
+            vec![TourAction::SwitchProfile("edit".into())]
+        );
+        assert_eq!(p.process(press("C2")), vec![KeyClick("E".into())]);
+        p.process(press("C1"));
+        assert_eq!(p.process(press("C2")), vec![KeyClick("N".into())]);
+        assert_eq!(p.process(press("TOUR")), vec![TourAction::PopProfile]);
+        assert_eq!(p.process(press("C2")), vec![KeyClick("E".into())]);
+        p.process(press("TOUR"));
+        assert_eq!(p.process(press("C2")), vec![KeyClick("B".into())]);
+    }
+
+    #[test]
+    fn shift_key_selects_shifted_mappings() {
+        let mut mappings = configs(&[
+            ("C1", "X", OnPress),
+            ("C1", "Y", OnPress),
+            ("C2", "Z", OnPress),
+        ]);
+        mappings[1].shifted = true;
+        let mut p = KeyMappingProcessor::from_config(&mappings).with_shift_key(Some("TOUR".into()));
+
+        assert_eq!(p.process(press("C1")), vec![KeyClick("X".into())]);
+        p.process(release("C1"));
+        assert_eq!(p.process(press("TOUR")), vec![]);
+        assert_eq!(p.process(press("C1")), vec![KeyClick("Y".into())]);
+        p.process(release("C1"));
+        // keys without a shifted mapping keep their meaning
+        assert_eq!(p.process(press("C2")), vec![KeyClick("Z".into())]);
+        assert_eq!(p.process(release("TOUR")), vec![]);
+        assert_eq!(p.process(press("C1")), vec![KeyClick("X".into())]);
+    }
+
+    #[test]
+    fn default_mappings_fire_only_without_another_match() {
+        let mut mappings = configs(&[
+            ("SIDE", "F1", OnPress),
+            ("SIDE", "F2", OnPress),
+            ("TOP+SIDE", "F3", OnPress),
+        ]);
+        mappings[0].default = true;
+        mappings[1].shifted = true;
+        let mut p = KeyMappingProcessor::from_config(&mappings).with_shift_key(Some("TOUR".into()));
+
+        assert_eq!(p.process(press("SIDE")), vec![KeyClick("F1".into())]);
+        p.process(release("SIDE"));
+        p.process(press("TOUR"));
+        assert_eq!(p.process(press("SIDE")), vec![KeyClick("F2".into())]);
+        p.process(release("SIDE"));
+        p.process(release("TOUR"));
+        p.process(press("TOP"));
+        assert_eq!(p.process(press("SIDE")), vec![KeyClick("F3".into())]);
+    }
+
+    #[test]
+    fn multiple_actions_fire_in_order() {
+        let mut mappings = configs(&[("C1", "", OnPress), ("TOP", "", OnHold)]);
+        mappings[0].action = vec!["char:→".into(), "os:screenshot".into()];
+        mappings[1].action = vec!["CTRL_L".into(), "char:x".into(), "SHIFT_L".into()];
+        let mut p = KeyMappingProcessor::from_config(&mappings);
+
+        assert_eq!(
+            p.process(press("C1")),
+            vec![
+                TypeText("→".into()),
+                TourAction::OsCommand(OsCommand::Screenshot)
+            ]
+        );
+        // held keys go down first, the rest is clicked while they are held
+        let mut expected = keys(KeyPress, &["CTRL_L", "SHIFT_L"]);
+        expected.push(TypeText("x".into()));
+        assert_eq!(p.process(press("TOP")), expected);
+        assert_eq!(
+            p.process(release("TOP")),
+            keys(KeyRelease, &["SHIFT_L", "CTRL_L"])
+        );
+    }
+
+    #[test]
+    fn connection_hooks_fire_their_actions() {
+        let mut p = processor(&[("C1", "X", OnPress)])
+            .with_hooks(&["char:+".into()], &["os:lock_screen".into(), "C".into()]);
+
+        assert_eq!(p.process(InputEvent::Connected), vec![TypeText("+".into())]);
+        assert_eq!(p.process(press("C1")), vec![KeyClick("X".into())]);
+        assert_eq!(
+            p.process(InputEvent::Disconnected),
+            vec![
+                TourAction::OsCommand(OsCommand::LockScreen),
+                KeyClick("C".into())
+            ]
+        );
+
+        let mut p = processor(&[]);
+        assert_eq!(p.process(InputEvent::Connected), vec![]);
+    }
+
+    #[test]
+    fn disconnecting_releases_what_is_held() {
+        let mut p = processor(&[
+            ("C1", "CTRL_L+SHIFT_L", OnHold),
+            ("C2", "lock:TOP", OnPress),
+            ("TOP+SIDE", "X", OnPress),
+        ])
+        .with_hooks(&[], &["C".into()]);
+
+        p.process(press("C1"));
+        p.process(press("C2"));
+        assert_eq!(
+            p.process(InputEvent::Disconnected),
+            vec![
+                KeyRelease("SHIFT_L".into()),
+                KeyRelease("CTRL_L".into()),
+                KeyClick("C".into()),
+            ]
+        );
+        // the key held before doesn't count as held after reconnecting, locks stay
+        p.process(InputEvent::Connected);
+        assert_eq!(p.process(release("C1")), vec![]);
+        assert_eq!(p.process(press("SIDE")), vec![KeyClick("X".into())]);
+    }
+
+    #[test]
+    fn low_battery_fires_once_per_drop() {
+        let mut p = processor(&[]).with_low_battery_hook(&["notify:Battery low".into()], 20);
+        let notify = vec![TourAction::Notify {
+            title: "Battery low".into(),
+            body: String::new(),
+        }];
+
+        assert_eq!(p.process(InputEvent::Battery(50)), vec![]);
+        assert_eq!(p.process(InputEvent::Battery(19)), notify);
+        assert_eq!(p.process(InputEvent::Battery(15)), vec![]);
+        assert_eq!(p.process(InputEvent::LinkQuality(10)), vec![]);
+        // charged in between, the next drop fires again
+        assert_eq!(p.process(InputEvent::Battery(80)), vec![]);
+        assert_eq!(p.process(InputEvent::Battery(10)), notify);
+    }
+
+    fn multi_tap_processor() -> KeyMappingProcessor {
+        let mut mappings = configs(&[
+            ("C1", "1", OnMultiTap),
+            ("C1", "2", OnMultiTap),
+            ("C1", "3", OnMultiTap),
+            ("C2", "X", OnPress),
+        ]);
+        for (count, m) in mappings.iter_mut().take(3).enumerate() {
+            m.count = count as u32 + 1;
+        }
+        KeyMappingProcessor::from_config(&mappings)
+            .with_multi_tap_window(Duration::from_millis(300))
+    }
+
+    #[test]
+    fn multi_tap_fires_highest_count_right_away() {
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+        let mut p = multi_tap_processor();
+
+        assert_eq!(p.process_at(press("C1"), at(0)), vec![]);
+        p.process_at(release("C1"), at(50));
+        assert_eq!(p.next_deadline(), Some(at(300)));
+        assert_eq!(p.process_at(press("C1"), at(250)), vec![]);
+        assert_eq!(
+            p.process_at(press("C1"), at(500)),
+            vec![KeyClick("3".into())]
+        );
+        assert_eq!(p.next_deadline(), None);
+    }
+
+    #[test]
+    fn multi_tap_fires_lower_count_when_window_closes() {
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+        let mut p = multi_tap_processor();
+
+        p.process_at(press("C1"), at(0));
+        p.process_at(press("C1"), at(100));
+        assert_eq!(p.tick_at(at(399)), vec![]);
+        assert_eq!(p.tick_at(at(400)), vec![KeyClick("2".into())]);
+
+        // another key ends the series early, its own mapping fires after the taps
+        p.process_at(press("C1"), at(1000));
+        assert_eq!(
+            p.process_at(press("C2"), at(1100)),
+            vec![KeyClick("1".into()), KeyClick("X".into())]
+        );
+
+        // a press after the window starts a new series
+        p.process_at(press("C1"), at(2000));
+        assert_eq!(
+            p.process_at(press("C1"), at(2400)),
+            vec![KeyClick("1".into())]
+        );
+        assert_eq!(p.tick_at(at(2700)), vec![KeyClick("1".into())]);
+    }
+
+    #[test]
+    fn escaped_plus_is_a_literal_key() {
+        assert_eq!(split_chord("CTRL_L+Z"), vec!["CTRL_L", "Z"]);
+        assert_eq!(split_chord("CTRL_L+\\+"), vec!["CTRL_L", "+"]);
+        assert_eq!(split_chord("\\+"), vec!["+"]);
+        assert_eq!(split_chord("SHIFT_L+\\\\"), vec!["SHIFT_L", "\\"]);
+        assert_eq!(split_chord("\\"), vec!["\\"]);
+
+        let mut p = processor(&[("TOP+\\+", "X", OnPress), ("\\+", "SHIFT_L+\\+", OnHold)]);
+        assert_eq!(p.process(press("+")), keys(KeyPress, &["SHIFT_L", "+"]));
+        p.process(release("+"));
+        p.process(press("TOP"));
+        assert_eq!(p.process(press("+")), vec![KeyClick("X".into())]);
+    }
+
+    #[test]
+    fn held_chord_releases_in_reverse_order() {
+        let mut p = processor(&[("TALL", "CTRL_L+SHIFT_L+ALT_L", OnHold)]);
+
+        assert_eq!(
+            p.process(press("TALL")),
+            keys(KeyPress, &["CTRL_L", "SHIFT_L", "ALT_L"])
+        );
+        assert_eq!(
+            p.process(release("TALL")),
+            keys(KeyRelease, &["ALT_L", "SHIFT_L", "CTRL_L"])
+        );
+    }
+
+    #[test]
+    fn locked_key_acts_as_held_modifier() {
+        let lock = |locked| TourAction::ModifierLock {
+            key: "TOP".into(),
+            locked,
+        };
+        let mut p = processor(&[
+            ("TOUR", "lock:TOP", OnPress),
+            ("C1", "X", OnPress),
+            ("TOP+C1", "Y", OnPress),
+            ("TOP+C2", "CTRL_L", OnHold),
+        ]);
+
+        assert_eq!(p.process(press("TOUR")), vec![lock(true)]);
+        p.process(release("TOUR"));
+        assert_eq!(p.process(press("C1")), vec![KeyClick("Y".into())]);
+        p.process(release("C1"));
+        assert_eq!(p.process(press("C2")), keys(KeyPress, &["CTRL_L"]));
+
+        // unlocking releases what the lock was holding
+        let mut expected = keys(KeyRelease, &["CTRL_L"]);
+        expected.push(lock(false));
+        assert_eq!(p.process(press("TOUR")), expected);
+        assert_eq!(p.process(release("C2")), vec![]);
+        assert_eq!(p.process(press("C1")), vec![KeyClick("X".into())]);
+    }
+
+    #[test]
+    fn on_release_skips_long_holds() {
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+        let mut mappings = configs(&[("C1", "X", OnRelease)]);
+        mappings[0].max_hold_ms = Some(300);
+        let mut p = KeyMappingProcessor::from_config(&mappings);
+
+        p.process_at(press("C1"), at(0));
+        assert_eq!(p.process_at(release("C1"), at(600)), vec![]);
+
+        p.process_at(press("C1"), at(1000));
+        assert_eq!(
+            p.process_at(release("C1"), at(1200)),
+            vec![KeyClick("X".into())]
+        );
+    }
+
+    #[test]
+    fn dial_dead_zone_swallows_first_ticks() {
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+        let mut p = KeyMappingProcessor::from_config(&configs(&[
+            ("DIAL_CW", "X", OnPress),
+            ("DIAL_CCW", "Y", OnPress),
+        ]))
+        .with_dial_dead_zone(
+            HashSet::from(["DIAL_CW".to_string(), "DIAL_CCW".to_string()]),
+            2,
+            Duration::from_millis(200),
+        );
+
+        assert_eq!(p.process_at(press("DIAL_CW"), at(0)), vec![]);
+        assert_eq!(p.process_at(press("DIAL_CW"), at(100)), vec![]);
+        assert_eq!(
+            p.process_at(press("DIAL_CW"), at(200)),
+            vec![KeyClick("X".into())]
+        );
+
+        // turning back starts over, as does a pause
+        assert_eq!(p.process_at(press("DIAL_CCW"), at(250)), vec![]);
+        assert_eq!(p.process_at(press("DIAL_CCW"), at(300)), vec![]);
+        assert_eq!(p.process_at(press("DIAL_CCW"), at(600)), vec![]);
+    }
+
+    #[test]
+    fn modifier_group_needs_any_of_its_keys() {
+        let mut p = processor(&[("(SIDE|TOP)+C1", "X", OnPress), ("C1", "Y", OnPress)]);
+
+        assert_eq!(p.process(press("C1")), vec![KeyClick("Y".into())]);
+        p.process(release("C1"));
+        for modifier in ["SIDE", "TOP"] {
+            p.process(press(modifier));
+            assert_eq!(p.process(press("C1")), vec![KeyClick("X".into())]);
+            p.process(release("C1"));
+            p.process(release(modifier));
+        }
+    }
+
+    #[test]
+    fn modifier_group_holds_while_any_key_is_held() {
+        let mut p = processor(&[("(SIDE|TOP)+TALL", "CTRL_L", OnHold)]);
+
+        p.process(press("SIDE"));
+        p.process(press("TOP"));
+        assert_eq!(p.process(press("TALL")), vec![KeyPress("CTRL_L".into())]);
+        assert_eq!(p.process(release("SIDE")), vec![]);
+        assert_eq!(p.process(release("TOP")), vec![KeyRelease("CTRL_L".into())]);
+    }
+
+    #[test]
+    fn state_variables_select_mappings() {
+        let mut mappings = configs(&[
+            ("C1", "toggle:eraser", OnPress),
+            ("C2", "set:size=3", OnPress),
+            ("TALL", "B", OnPress),
+            ("TALL", "E", OnPress),
+            ("TALL", "S", OnPress),
+        ]);
+        mappings[3].when = Some("eraser".into());
+        mappings[4].when = Some("size == 3".into());
+        let mut p = KeyMappingProcessor::from_config(&mappings);
+
+        assert_eq!(p.process(press("TALL")), vec![KeyClick("B".into())]);
+        assert_eq!(
+            p.process(press("C1")),
+            vec![TourAction::SetState {
+                name: "eraser".into(),
+                value: 1
+            }]
+        );
+        assert_eq!(p.process(press("TALL")), vec![KeyClick("E".into())]);
+        p.process(press("C1"));
+        p.process(press("C2"));
+        assert_eq!(p.process(press("TALL")), vec![KeyClick("S".into())]);
+    }
+
+    #[test]
+    fn conditions_parse_their_comparison() {
+        let state = HashMap::from([("mode".to_string(), 2)]);
+        let holds = |when: &str| Condition::parse(when).unwrap().holds(&state);
+
+        assert!(holds("mode==2"));
+        assert!(holds("mode != 1"));
+        assert!(holds("mode"));
+        assert!(!holds("other"));
+        assert!(holds("other==false"));
+        assert!(Condition::parse("mode=2").is_none());
+        assert!(Condition::parse("==2").is_none());
+    }
+
+    #[test]
+    fn accelerated_dial_mapping_repeats_on_fast_turns() {
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+        let mut mappings = configs(&[
+            ("SCROLL_DOWN", "DOWN", OnPress),
+            ("SCROLL_UP", "UP", OnPress),
+        ]);
+        mappings[0].accelerate = true;
+        let mut p = KeyMappingProcessor::from_config(&mappings)
+            .with_dial_dead_zone(
+                HashSet::from(["SCROLL_DOWN".to_string(), "SCROLL_UP".to_string()]),
+                0,
+                Duration::from_millis(250),
+            )
+            .with_dial_acceleration(Duration::from_millis(100), 3);
+        let downs = |n| vec![KeyClick("DOWN".to_string()); n];
+
+        assert_eq!(p.process_at(press("SCROLL_DOWN"), at(0)), downs(1));
+        assert_eq!(p.process_at(press("SCROLL_DOWN"), at(200)), downs(1));
+        assert_eq!(p.process_at(press("SCROLL_DOWN"), at(280)), downs(2));
+        assert_eq!(p.process_at(press("SCROLL_DOWN"), at(290)), downs(3));
+        // only mappings marked `accelerate` repeat
+        p.process_at(press("SCROLL_UP"), at(300));
+        assert_eq!(
+            p.process_at(press("SCROLL_UP"), at(310)),
+            vec![KeyClick("UP".into())]
+        );
+    }
+
+    #[test]
+    fn dial_ticks_at_the_same_instant_repeat_the_most() {
+        let now = Instant::now();
+        let mut mappings = configs(&[("SCROLL_DOWN", "DOWN", OnPress)]);
+        mappings[0].accelerate = true;
+        let mut p = KeyMappingProcessor::from_config(&mappings)
+            .with_dial_dead_zone(
+                HashSet::from(["SCROLL_DOWN".to_string()]),
+                0,
+                Duration::from_millis(250),
+            )
+            .with_dial_acceleration(Duration::from_millis(100), 3);
+
+        p.process_at(press("SCROLL_DOWN"), now);
+        assert_eq!(
+            p.process_at(press("SCROLL_DOWN"), now),
+            vec![KeyClick("DOWN".to_string()); 3]
+        );
+    }
+
+    #[test]
+    fn a_held_button_switches_what_the_dial_does() {
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+        let mut mappings = configs(&[
+            ("KNOB_CW", "CTRL_L+EQUAL", OnPress),
+            ("SIDE+KNOB_CW", "BRACKET_RIGHT", OnPress),
+        ]);
+        mappings[1].accelerate = true;
+        let mut p = KeyMappingProcessor::from_config(&mappings)
+            .with_dial_dead_zone(
+                HashSet::from(["KNOB_CW".to_string()]),
+                0,
+                Duration::from_millis(250),
+            )
+            .with_dial_acceleration(Duration::from_millis(100), 3);
+        let brush = |n| vec![KeyClick("BRACKET_RIGHT".to_string()); n];
+
+        assert_eq!(
+            p.process_at(press("KNOB_CW"), at(0)),
+            vec![KeyClick("CTRL_L+EQUAL".into())]
+        );
+        assert_eq!(p.process_at(press("SIDE"), at(500)), vec![]);
+        assert_eq!(p.process_at(press("KNOB_CW"), at(600)), brush(1));
+        assert_eq!(p.process_at(press("KNOB_CW"), at(680)), brush(2));
+        assert_eq!(p.process_at(release("SIDE"), at(700)), vec![]);
+        assert_eq!(
+            p.process_at(press("KNOB_CW"), at(1000)),
+            vec![KeyClick("CTRL_L+EQUAL".into())]
+        );
+    }
+
+    #[test]
+    fn snapshot_restores_variables_and_locks() {
+        let mut mappings = configs(&[
+            ("C1", "toggle:eraser", OnPress),
+            ("C2", "lock:TOP", OnPress),
+            ("TALL", "E", OnPress),
+            ("TOP+SHORT", "X", OnPress),
+        ]);
+        mappings[2].when = Some("eraser".into());
+        let mut p = KeyMappingProcessor::from_config(&mappings);
+        p.process(press("C1"));
+        p.process(press("C2"));
+        let state = p.snapshot();
+        assert_eq!(state.variables, BTreeMap::from([("eraser".to_string(), 1)]));
+        assert_eq!(state.locked, BTreeSet::from(["TOP".to_string()]));
+
+        let mut p = KeyMappingProcessor::from_config(&mappings).with_state(state);
+        assert_eq!(p.process(press("TALL")), vec![KeyClick("E".into())]);
+        assert_eq!(p.process(press("SHORT")), vec![KeyClick("X".into())]);
+    }
+
+    #[test]
+    fn down_and_up_send_raw_presses_and_releases() {
+        let mut p = processor(&[
+            ("C1", "down:CTRL_L+SHIFT_L", OnPress),
+            ("C1", "up:CTRL_L+SHIFT_L", OnRelease),
+            ("C2", "down:ALT_L", OnHold),
+        ]);
+
+        assert_eq!(
+            p.process(press("C1")),
+            keys(KeyPress, &["CTRL_L", "SHIFT_L"])
+        );
+        // verbatim, `up:` doesn't reorder the keys
+        assert_eq!(
+            p.process(release("C1")),
+            keys(KeyRelease, &["CTRL_L", "SHIFT_L"])
+        );
+        // a hold doesn't release what `down:` pressed
+        assert_eq!(p.process(press("C2")), keys(KeyPress, &["ALT_L"]));
+        assert_eq!(p.process(release("C2")), vec![]);
+    }
+
+    #[test]
+    fn release_lets_go_of_one_held_chord() {
+        let mut p = processor(&[
+            ("C1", "CTRL_L+SHIFT_L", OnHold),
+            ("C2", "ALT_L", OnHold),
+            ("TOP", "release:CTRL_L+SHIFT_L", OnPress),
+        ]);
+
+        p.process(press("C1"));
+        p.process(press("C2"));
+        assert_eq!(
+            p.process(press("TOP")),
+            keys(KeyRelease, &["SHIFT_L", "CTRL_L"])
+        );
+        // the released hold is forgotten, the other one is still held
+        assert_eq!(p.process(release("C1")), vec![]);
+        assert_eq!(p.process(release("C2")), keys(KeyRelease, &["ALT_L"]));
+    }
+
+    #[test]
+    fn host_modifiers_narrow_a_mapping_down() {
+        let mut alt = configs(&[("C1", "B", OnPress)]);
+        alt[0].host_modifiers = vec!["alt".to_string()];
+        let alt_held = std::rc::Rc::new(std::cell::Cell::new(false));
+        let held = alt_held.clone();
+        let mut p =
+            KeyMappingProcessor::from_config(&[configs(&[("C1", "A", OnPress)]), alt].concat())
+                .with_host_keys(move |k| k == "ALT" && held.get());
+
+        assert_eq!(p.process(press("C1")), keys(KeyClick, &["A"]));
+        p.process(release("C1"));
+        alt_held.set(true);
+        assert_eq!(p.process(press("C1")), keys(KeyClick, &["B"]));
+    }
+
+    #[test]
+    fn touch_codes_are_a_held_key() {
+        let key_map: crate::config::KeyMap = serde_json::from_str(
+            r#"{ "stateful": { "0x30": "KNOB_TOUCH" }, "stateless": { "0x44": "KNOB_CW" } }"#,
+        )
+        .unwrap();
+        let mut p = processor(&[
+            ("KNOB_TOUCH", "T", OnRelease),
+            ("KNOB_CW", "RIGHT", OnPress),
+            ("KNOB_TOUCH+KNOB_CW", "UP", OnPress),
+        ]);
+        let mut decoded = |code: u8| p.process(key_map.decode(code).unwrap());
+
+        // touching and letting go is a press and release of its own key
+        assert_eq!(decoded(0x30), vec![]);
+        assert_eq!(decoded(0xB0), keys(KeyClick, &["T"]));
+        assert_eq!(decoded(0x44), keys(KeyClick, &["RIGHT"]));
+        // turning while touched picks the mapping with the touch as modifier
+        decoded(0x30);
+        assert_eq!(decoded(0x44), keys(KeyClick, &["UP"]));
+    }
+
+    #[test]
+    fn mouse_to_moves_the_cursor() {
+        let mut p = processor(&[
+            ("C1", "mouse_to:1920, 10", OnPress),
+            ("C2", "mouse_to:1920", OnPress),
+        ]);
+
+        assert_eq!(
+            p.process(press("C1")),
+            vec![TourAction::MouseMoveAbs { x: 1920, y: 10 }]
+        );
+        // without a `y` it is a key name like any other
+        assert_eq!(p.process(press("C2")), keys(KeyClick, &["mouse_to:1920"]));
+    }
+
+    #[test]
+    fn tapped_sticky_keys_modify_the_next_press() {
+        let mut p = processor(&[("SIDE+C1", "Y", OnPress), ("C1", "Z", OnPress)])
+            .with_sticky_keys(&["SIDE".into()], Duration::from_millis(1000));
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+
+        p.process_at(press("SIDE"), at(0));
+        p.process_at(release("SIDE"), at(50));
+        assert_eq!(p.process_at(press("C1"), at(500)), keys(KeyClick, &["Y"]));
+        p.process_at(release("C1"), at(550));
+        // used up by that press
+        assert_eq!(p.process_at(press("C1"), at(600)), keys(KeyClick, &["Z"]));
+        p.process_at(release("C1"), at(650));
+
+        // timed out
+        p.process_at(press("SIDE"), at(1000));
+        p.process_at(release("SIDE"), at(1050));
+        assert_eq!(p.process_at(press("C1"), at(2100)), keys(KeyClick, &["Z"]));
+        p.process_at(release("C1"), at(2150));
+
+        // held as a modifier, its release doesn't arm it
+        p.process_at(press("SIDE"), at(3000));
+        assert_eq!(p.process_at(press("C1"), at(3050)), keys(KeyClick, &["Y"]));
+        p.process_at(release("C1"), at(3100));
+        p.process_at(release("SIDE"), at(3150));
+        assert_eq!(p.process_at(press("C1"), at(3200)), keys(KeyClick, &["Z"]));
+    }
+
+    #[test]
+    fn device_reinit_is_not_repeated() {
+        let mut p = processor(&[
+            ("C1", "Z", OnPress),
+            ("C2", "device:reinit", OnPress),
+            ("TOUR", "repeat_last", OnPress),
+        ]);
+
+        p.process(press("C1"));
+        assert_eq!(p.process(press("C2")), vec![TourAction::ReinitDevice]);
+        assert_eq!(p.process(press("TOUR")), keys(KeyClick, &["Z"]));
+    }
+
+    #[test]
+    fn pass_through_keys_add_their_host_keys() {
+        let mut p = processor(&[
+            ("C1", "Z", OnPress),
+            ("SIDE+C2", "Y", OnPress),
+            ("SIDE", "never", OnPress),
+        ])
+        .with_pass_through(&HashMap::from([("SIDE".to_string(), "CTRL_L".to_string())]));
+
+        assert_eq!(p.process(press("C1")), keys(KeyClick, &["Z"]));
+        assert_eq!(p.process(press("SIDE")), vec![]);
+        assert_eq!(p.process(press("C1")), keys(KeyClick, &["CTRL_L+Z"]));
+        // a mapping that names the key itself is taken as it is
+        assert_eq!(p.process(press("C2")), keys(KeyClick, &["Y"]));
+        assert_eq!(p.process(release("SIDE")), vec![]);
+        assert_eq!(p.process(press("C1")), keys(KeyClick, &["Z"]));
+    }
+}
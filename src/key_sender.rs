@@ -1,27 +1,76 @@
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
+use std::mem;
+use std::sync::{Arc, LazyLock, Mutex};
+use std::thread;
+use std::time::Duration;
 
-use enigo::{Axis, Direction, Enigo, Key, Keyboard, Mouse, Settings};
-use log::info;
+use enigo::{Axis, Coordinate, Direction, Enigo, InputError, Key, Keyboard, Mouse, Settings};
+use log::{info, warn};
+use routbox::action::{ActionSink, TourAction};
+use routbox::key_processor::{HOST_MODIFIERS, RESET_ACTION, split_chord};
 use thiserror::Error;
 
+use crate::clipboard::Clipboard;
+
+// The shortcut `clipboard:paste` sends
+const PASTE: &str = if cfg!(target_os = "macos") {
+    "COMMAND+V"
+} else {
+    "CTRL+V"
+};
+
+// What a key name stands for: a key enigo looks up itself or a hardware scan code
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Stroke {
+    Key(Key),
+    ScanCode(u16),
+}
+
+// What the keys of an action stand for, a wheel scroll in `STEP_PARTS` or the keys of a
+// chord in the order they are pressed
 #[derive(Debug, Clone)]
-pub enum TourAction {
-    KeyPress(String),
-    KeyClick(String),
-    KeyRelease(String),
-    UiAction(String)
+enum Parsed {
+    Scroll(i64),
+    Strokes(Arc<[Stroke]>),
 }
 
+// Which of `HOST_MODIFIERS` the key sender holds down itself, e.g. for a `down:` action
+pub type HeldModifiers = Arc<Mutex<HashSet<&'static str>>>;
+
 #[derive(Debug)]
 pub struct KeySender {
     enigo: Enigo,
-    active_key: HashSet<Key>,
+    active_key: HashSet<Stroke>,
+    held_modifiers: HeldModifiers,
+    // looks up the computer's keyboard, its held modifiers are let go during clicks
+    host_keys: Option<fn(&str) -> bool>,
+    // how long a clicked key stays down
+    key_press_duration: Duration,
+    // pause between the keys of a clicked chord
+    inter_key_delay: Duration,
+    // part of a scroll step not sent yet in `STEP_PARTS`, negative scrolls up
+    scroll_remainder: i64,
+    // user defined key names for raw key codes
+    key_aliases: HashMap<String, u32>,
+    clipboard: Clipboard,
+    // keys and chords of the config, parsed before anything is sent by `prepare`
+    prepared: HashMap<String, Parsed>,
 }
 
 #[derive(Error, Debug)]
 pub enum KeySenderError {
     #[error("the key `{0}` is not available")]
     UnknownKey(String),
+    #[error("could not type `{0}`: {1}")]
+    TypeText(String, InputError),
+    #[error("could not move the mouse: {0}")]
+    MoveMouse(InputError),
+    #[error("the scan code `{0}` can only be sent on Windows")]
+    ScanCodeUnsupported(String),
+    #[error("could not use the clipboard: {0}")]
+    Clipboard(arboard::Error),
+    #[error("`{0}` has to scroll a positive number of steps")]
+    WheelSteps(String),
 }
 
 impl KeySender {
@@ -30,98 +79,125 @@ impl KeySender {
         Self {
             enigo,
             active_key: HashSet::new(),
+            held_modifiers: HeldModifiers::default(),
+            host_keys: None,
+            key_press_duration: Duration::ZERO,
+            inter_key_delay: Duration::ZERO,
+            scroll_remainder: 0,
+            key_aliases: HashMap::new(),
+            clipboard: Clipboard::default(),
+            prepared: HashMap::new(),
+        }
+    }
+
+    // Slows clicks down for apps that drop keys pressed and released too fast
+    pub fn with_delays(mut self, key_press_duration: Duration, inter_key_delay: Duration) -> Self {
+        self.key_press_duration = key_press_duration;
+        self.inter_key_delay = inter_key_delay;
+        self
+    }
+
+    // Releases the modifiers held on the computer's keyboard while a chord is clicked or text
+    // is typed, and presses them again afterwards. Without it the app receives them too.
+    pub fn with_host_modifiers_released(mut self, is_held: Option<fn(&str) -> bool>) -> Self {
+        self.host_keys = is_held;
+        self
+    }
+
+    // The modifiers this sender holds, kept up to date while it sends
+    pub fn held_modifiers(&self) -> HeldModifiers {
+        self.held_modifiers.clone()
+    }
+
+    pub fn with_key_aliases(mut self, key_aliases: HashMap<String, u32>) -> Self {
+        self.key_aliases = key_aliases;
+        self
+    }
+
+    // Parses a key, chord or wheel scroll of the config before anything is sent, so
+    // sending it doesn't have to. The single keys of a chord are kept too, for holds
+    // pressing and releasing them one by one.
+    pub fn prepare(&mut self, keys: &str) -> Result<(), KeySenderError> {
+        if self.prepared.contains_key(keys) {
+            return Ok(());
+        }
+        let parsed = parse_keys(keys, &self.key_aliases)?;
+        if let Parsed::Strokes(strokes) = &parsed {
+            for (k, stroke) in split_chord(keys).into_iter().zip(strokes.iter()) {
+                self.prepared
+                    .insert(k, Parsed::Strokes(Arc::from([*stroke])));
+            }
+        }
+        self.prepared.insert(keys.to_string(), parsed);
+        Ok(())
+    }
+
+    // What `keys` stands for, parsed right away if it wasn't prepared, e.g. from a script
+    fn parsed(&self, keys: &str) -> Result<Parsed, KeySenderError> {
+        match self.prepared.get(keys) {
+            Some(parsed) => Ok(parsed.clone()),
+            None => parse_keys(keys, &self.key_aliases),
+        }
+    }
+
+    // A single key, like `parsed`
+    fn single(&self, key: &str) -> Result<Stroke, KeySenderError> {
+        match self.prepared.get(key) {
+            Some(Parsed::Strokes(strokes)) if strokes.len() == 1 => Ok(strokes[0]),
+            _ => parse_stroke(key, &self.key_aliases),
+        }
+    }
+
+    fn stroke(&mut self, stroke: Stroke, direction: Direction) {
+        match stroke {
+            Stroke::Key(key) => self.enigo.key(key, direction).unwrap(),
+            // above 0x7F enigo sends the low bits as an extended key
+            Stroke::ScanCode(code) => self.enigo.raw(code, direction).unwrap(),
         }
     }
 
     fn parse_key(key_str: &str) -> Result<Key, KeySenderError> {
         let uppercase_key = key_str.to_uppercase();
-        match uppercase_key.as_str() {
-            // Modifiers
-            "ALT" | "ALT_L" | "ALT_R" => Ok(Key::Alt),
-            "CONTROL" | "CTRL" | "CTRL_L" | "CTRL_R" => Ok(Key::Control),
-            "SHIFT" | "SHIFT_L" | "SHIFT_R" => Ok(Key::Shift),
-            "WIN" | "WIN_L" | "WIN_R" | "SUPER" | "COMMAND" => Ok(Key::Meta),
-
-            // Arrow keys
-            "DOWN" | "DOWN_ARROW" => Ok(Key::DownArrow),
-            "LEFT" | "LEFT_ARROW" => Ok(Key::LeftArrow),
-            "RIGHT" | "RIGHT_ARROW" => Ok(Key::RightArrow),
-            "UP" | "UP_ARROW" => Ok(Key::UpArrow),
-
-            // Special keys
-            "BACKSPACE" => Ok(Key::Backspace),
-            "CAPSLOCK" => Ok(Key::CapsLock),
-            "DELETE" => Ok(Key::Delete),
-            "END" => Ok(Key::End),
-            "ENTER" => Ok(Key::Return),
-            "ESCAPE" => Ok(Key::Escape),
-            "F1" => Ok(Key::F1),
-            "F2" => Ok(Key::F2),
-            "F3" => Ok(Key::F3),
-            "F4" => Ok(Key::F4),
-            "F5" => Ok(Key::F5),
-            "F6" => Ok(Key::F6),
-            "F7" => Ok(Key::F7),
-            "F8" => Ok(Key::F8),
-            "F9" => Ok(Key::F9),
-            "F10" => Ok(Key::F10),
-            "F11" => Ok(Key::F11),
-            "F12" => Ok(Key::F12),
-            "HOME" => Ok(Key::Home),
-            "PAGEDOWN" => Ok(Key::PageDown),
-            "PAGEUP" => Ok(Key::PageUp),
-            "SPACE" => Ok(Key::Space),
-            "TAB" => Ok(Key::Tab),
-
-            "A" => Ok(Key::A),
-            "B" => Ok(Key::B),
-            "C" => Ok(Key::C),
-            "D" => Ok(Key::D),
-            "E" => Ok(Key::E),
-            "F" => Ok(Key::F),
-            "G" => Ok(Key::G),
-            "H" => Ok(Key::H),
-            "I" => Ok(Key::I),
-            "J" => Ok(Key::J),
-            "K" => Ok(Key::K),
-            "L" => Ok(Key::L),
-            "M" => Ok(Key::M),
-            "N" => Ok(Key::N),
-            "O" => Ok(Key::O),
-            "P" => Ok(Key::P),
-            "Q" => Ok(Key::Q),
-            "R" => Ok(Key::R),
-            "S" => Ok(Key::S),
-            "T" => Ok(Key::T),
-            "U" => Ok(Key::U),
-            "V" => Ok(Key::V),
-            "W" => Ok(Key::W),
-            "X" => Ok(Key::X),
-            "Y" => Ok(Key::Y),
-            "Z" => Ok(Key::Z),
-
-            // Special characters that don't require a shift modifier
-            "-" => Ok(Key::Other(0xBD_u32)), // OEM_MINUS
-            "=" => Ok(Key::Other(0xBB_u32)), // OEM_PLUS
-            "[" => Ok(Key::Other(0xDB_u32)), // OEM_4
-            "]" => Ok(Key::Other(0xDD_u32)), // OEM_6
-            "" => Ok(Key::Other(0xDC_u32)),  // OEM_5
-            ";" => Ok(Key::Other(0xBA_u32)), // OEM_1
-            "'" => Ok(Key::Other(0xDE_u32)), // OEM_7
-            "," => Ok(Key::Other(0xBC_u32)), // OEM_COMMA
-            "." => Ok(Key::Other(0xBE_u32)), // OEM_PERIOD
-            "/" => Ok(Key::Other(0xBF_u32)), // OEM_2
-            "`" => Ok(Key::Other(0xC0_u32)), // OEM_3
-
+        if let Some(key) = KEYS.get(uppercase_key.as_str()) {
+            return Ok(*key);
+        }
+        match uppercase_key.strip_prefix("@") {
             // @+number for special virtual key
-            k if key_str.starts_with("@") => Ok(Key::Other(
-                k.split("@")
-                    .collect::<String>()
-                    .parse()
-                    .map_err(|_| KeySenderError::UnknownKey(k.to_string()))?,
-            )),
+            Some(code) => code
+                .parse()
+                .map(Key::Other)
+                .map_err(|_| KeySenderError::UnknownKey(uppercase_key.clone())),
+            None => Err(KeySenderError::UnknownKey(uppercase_key)),
+        }
+    }
 
-            k => Err(KeySenderError::UnknownKey(k.to_string())), // No match found
+    fn update_held_modifiers(&self) {
+        *self.held_modifiers.lock().unwrap() = modifiers_held_by(&self.active_key);
+    }
+
+    // Lets go of the keyboard's modifiers routbox doesn't hold itself, the ones released
+    // are returned for `restore_host_modifiers`
+    fn release_host_modifiers(&mut self) -> Vec<Key> {
+        let Some(is_held) = self.host_keys else {
+            return vec![];
+        };
+        let released = host_modifiers_to_release(&self.active_key, is_held);
+        for key in &released {
+            self.stroke(Stroke::Key(*key), Direction::Release);
+        }
+        released
+    }
+
+    fn restore_host_modifiers(&mut self, released: Vec<Key>) {
+        for key in released {
+            self.stroke(Stroke::Key(key), Direction::Press);
+        }
+    }
+
+    fn scroll(&mut self, parts: i64) {
+        let whole = whole_steps(&mut self.scroll_remainder, parts);
+        if whole != 0 {
+            self.enigo.scroll(whole, Axis::Vertical).unwrap();
         }
     }
 
@@ -129,35 +205,78 @@ impl KeySender {
         info!("send_key {action:?}");
         match action {
             TourAction::KeyPress(s) => {
-                let key = KeySender::parse_key(s)?;
+                let key = self.single(s)?;
                 self.active_key.insert(key);
-                self.enigo.key(key, Direction::Press).unwrap();
+                self.update_held_modifiers();
+                self.stroke(key, Direction::Press);
             }
-            TourAction::KeyClick(s) => match s.to_uppercase().as_str() {
-                "WHEEL_UP" => {
-                    self.enigo.scroll(-1, Axis::Vertical).unwrap();
-                }
-                "WHEEL_DOWN" => {
-                    self.enigo.scroll(1, Axis::Vertical).unwrap();
-                }
-                _ => {
-                    let mut to_be_release = Vec::with_capacity(10);
-                    for k in s.split("+").into_iter() {
-                        let key = KeySender::parse_key(k)?;
+            TourAction::KeyClick(s) => match self.parsed(s)? {
+                Parsed::Scroll(parts) => self.scroll(parts),
+                Parsed::Strokes(strokes) => {
+                    let released = self.release_host_modifiers();
+                    let mut to_be_release = Vec::with_capacity(strokes.len());
+                    for &key in strokes.iter() {
                         if !self.active_key.contains(&key) {
-                            self.enigo.key(key, Direction::Press).unwrap();
+                            if !to_be_release.is_empty() {
+                                thread::sleep(self.inter_key_delay);
+                            }
+                            self.stroke(key, Direction::Press);
                             to_be_release.push(key);
                         }
                     }
-                    for key in to_be_release.into_iter().rev() {
-                        self.enigo.key(key, Direction::Release).unwrap();
+                    thread::sleep(self.key_press_duration);
+                    for (i, key) in to_be_release.into_iter().rev().enumerate() {
+                        if i > 0 {
+                            thread::sleep(self.inter_key_delay);
+                        }
+                        self.stroke(key, Direction::Release);
                     }
+                    self.restore_host_modifiers(released);
                 }
             },
+            TourAction::TypeText(s) => {
+                let released = self.release_host_modifiers();
+                let typed = type_text(&mut self.enigo, s);
+                self.restore_host_modifiers(released);
+                typed.map_err(|e| KeySenderError::TypeText(s.clone(), e))?;
+            }
             TourAction::KeyRelease(s) => {
-                let key = KeySender::parse_key(s)?;
+                let key = self.single(s)?;
                 self.active_key.remove(&key);
-                self.enigo.key(key, Direction::Release).unwrap();
+                self.update_held_modifiers();
+                self.stroke(key, Direction::Release);
+            }
+            TourAction::MouseMoveAbs { x, y } => {
+                // clamped to the main display, other monitors can't be reached
+                let (x, y) = match self.enigo.main_display() {
+                    Ok((width, height)) => {
+                        (clamp_to_display(*x, width), clamp_to_display(*y, height))
+                    }
+                    Err(_) => (*x, *y),
+                };
+                self.enigo
+                    .move_mouse(x, y, Coordinate::Abs)
+                    .map_err(KeySenderError::MoveMouse)?;
+            }
+            TourAction::SetClipboard(text) => {
+                self.clipboard
+                    .set(text)
+                    .map_err(KeySenderError::Clipboard)?;
+            }
+            // after a reset nothing routbox pressed stays down, `down:` keys included
+            TourAction::UiAction(name) if name == RESET_ACTION => {
+                for key in mem::take(&mut self.active_key) {
+                    self.stroke(key, Direction::Release);
+                }
+                self.update_held_modifiers();
+            }
+            TourAction::PasteClipboard(text) => {
+                if let Some(text) = text {
+                    self.clipboard
+                        .set(text)
+                        .map_err(KeySenderError::Clipboard)?;
+                }
+                self.send_key(&TourAction::KeyClick(PASTE.to_string()))?;
             }
             _ => {
                 // ignore other action
@@ -167,3 +286,415 @@ impl KeySender {
         Ok(())
     }
 }
+
+// Scroll steps are counted in thousandths, so fractions add up exactly: ten
+// `WHEEL_DOWN:0.1` scroll one step
+const STEP_PARTS: i64 = 1000;
+
+// Adds `parts` to the carried `remainder` and takes the whole steps out of it, so small
+// amounts add up instead of being lost to rounding
+fn whole_steps(remainder: &mut i64, parts: i64) -> i32 {
+    *remainder += parts;
+    let whole = *remainder / STEP_PARTS;
+    *remainder -= whole * STEP_PARTS;
+    whole as i32
+}
+
+// A coordinate within a display `size` pixels wide or high. A display reported as 0 wide
+// or high clamps everything to 0.
+fn clamp_to_display(v: i32, size: i32) -> i32 {
+    v.clamp(0, (size - 1).max(0))
+}
+
+// Fast text entry fails on some backends, typing one character at a time works on more
+// Which of `HOST_MODIFIERS` are among the held `active` keys
+fn modifiers_held_by(active: &HashSet<Stroke>) -> HashSet<&'static str> {
+    HOST_MODIFIERS
+        .iter()
+        .copied()
+        .filter(|name| {
+            KeySender::parse_key(name).is_ok_and(|key| active.contains(&Stroke::Key(key)))
+        })
+        .collect()
+}
+
+// The modifiers held on the computer's keyboard, without the ones in `active` routbox holds
+// itself and has to keep down
+fn host_modifiers_to_release(active: &HashSet<Stroke>, is_held: impl Fn(&str) -> bool) -> Vec<Key> {
+    HOST_MODIFIERS
+        .iter()
+        .filter(|name| is_held(name))
+        .filter_map(|name| KeySender::parse_key(name).ok())
+        .filter(|key| !active.contains(&Stroke::Key(*key)))
+        .collect()
+}
+
+fn type_text(keyboard: &mut impl Keyboard, text: &str) -> Result<(), InputError> {
+    match keyboard.text(text) {
+        Err(InputError::InvalidInput(e)) => Err(InputError::InvalidInput(e)),
+        Err(e) => {
+            info!("Typing `{text}` failed ({e}), trying one character at a time");
+            for c in text.chars() {
+                keyboard.key(Key::Unicode(c), Direction::Click)?;
+            }
+            Ok(())
+        }
+        Ok(()) => Ok(()),
+    }
+}
+
+// Whether a key, chord or wheel scroll could be sent, without sending it. `prepare` parses
+// the same way, so `--validate` reports what sending would fail on.
+pub fn check_keys(keys: &str, key_aliases: &HashMap<String, u32>) -> Result<(), KeySenderError> {
+    parse_keys(keys, key_aliases).map(drop)
+}
+
+fn parse_keys(keys: &str, key_aliases: &HashMap<String, u32>) -> Result<Parsed, KeySenderError> {
+    if let Some(parts) = parse_wheel(keys) {
+        return parts.map(Parsed::Scroll);
+    }
+    split_chord(keys)
+        .iter()
+        .map(|k| parse_stroke(k, key_aliases))
+        .collect::<Result<_, _>>()
+        .map(Parsed::Strokes)
+}
+
+// Scan codes, built-in key names, then the configured aliases
+fn parse_stroke(
+    key_str: &str,
+    key_aliases: &HashMap<String, u32>,
+) -> Result<Stroke, KeySenderError> {
+    if let Some(code) = parse_scan_code(key_str) {
+        return code.map(Stroke::ScanCode);
+    }
+    KeySender::parse_key(key_str)
+        .map(Stroke::Key)
+        .or_else(|e| match key_aliases.get(key_str) {
+            Some(code) => Ok(Stroke::Key(Key::Other(*code))),
+            None => Err(e),
+        })
+}
+
+// Key names as written in actions, uppercase. Aliases are one entry per name.
+const KEY_NAMES: &[(&str, Key)] = &[
+    // Modifiers
+    ("ALT", Key::Alt),
+    ("ALT_L", Key::Alt),
+    ("ALT_R", Key::Alt),
+    ("CONTROL", Key::Control),
+    ("CTRL", Key::Control),
+    ("CTRL_L", Key::Control),
+    ("CTRL_R", Key::Control),
+    ("SHIFT", Key::Shift),
+    ("SHIFT_L", Key::Shift),
+    ("SHIFT_R", Key::Shift),
+    ("WIN", Key::Meta),
+    ("WIN_L", Key::Meta),
+    ("WIN_R", Key::Meta),
+    ("SUPER", Key::Meta),
+    ("COMMAND", Key::Meta),
+    // Arrow keys
+    ("DOWN", Key::DownArrow),
+    ("DOWN_ARROW", Key::DownArrow),
+    ("LEFT", Key::LeftArrow),
+    ("LEFT_ARROW", Key::LeftArrow),
+    ("RIGHT", Key::RightArrow),
+    ("RIGHT_ARROW", Key::RightArrow),
+    ("UP", Key::UpArrow),
+    ("UP_ARROW", Key::UpArrow),
+    // Special keys
+    ("BACKSPACE", Key::Backspace),
+    ("CAPSLOCK", Key::CapsLock),
+    ("DELETE", Key::Delete),
+    ("END", Key::End),
+    ("ENTER", Key::Return),
+    ("ESCAPE", Key::Escape),
+    ("F1", Key::F1),
+    ("F2", Key::F2),
+    ("F3", Key::F3),
+    ("F4", Key::F4),
+    ("F5", Key::F5),
+    ("F6", Key::F6),
+    ("F7", Key::F7),
+    ("F8", Key::F8),
+    ("F9", Key::F9),
+    ("F10", Key::F10),
+    ("F11", Key::F11),
+    ("F12", Key::F12),
+    ("HOME", Key::Home),
+    ("PAGEDOWN", Key::PageDown),
+    ("PAGEUP", Key::PageUp),
+    ("SPACE", Key::Space),
+    ("TAB", Key::Tab),
+    ("A", Key::A),
+    ("B", Key::B),
+    ("C", Key::C),
+    ("D", Key::D),
+    ("E", Key::E),
+    ("F", Key::F),
+    ("G", Key::G),
+    ("H", Key::H),
+    ("I", Key::I),
+    ("J", Key::J),
+    ("K", Key::K),
+    ("L", Key::L),
+    ("M", Key::M),
+    ("N", Key::N),
+    ("O", Key::O),
+    ("P", Key::P),
+    ("Q", Key::Q),
+    ("R", Key::R),
+    ("S", Key::S),
+    ("T", Key::T),
+    ("U", Key::U),
+    ("V", Key::V),
+    ("W", Key::W),
+    ("X", Key::X),
+    ("Y", Key::Y),
+    ("Z", Key::Z),
+    // Special characters that don't require a shift modifier, Windows virtual key codes
+    ("-", Key::Other(0xBD)),  // OEM_MINUS
+    ("=", Key::Other(0xBB)),  // OEM_PLUS
+    ("+", Key::Other(0xBB)),  // OEM_PLUS
+    ("[", Key::Other(0xDB)),  // OEM_4
+    ("]", Key::Other(0xDD)),  // OEM_6
+    ("\\", Key::Other(0xDC)), // OEM_5
+    (";", Key::Other(0xBA)),  // OEM_1
+    ("'", Key::Other(0xDE)),  // OEM_7
+    (",", Key::Other(0xBC)),  // OEM_COMMA
+    (".", Key::Other(0xBE)),  // OEM_PERIOD
+    ("/", Key::Other(0xBF)),  // OEM_2
+    ("`", Key::Other(0xC0)),  // OEM_3
+];
+
+static KEYS: LazyLock<HashMap<&str, Key>> = LazyLock::new(|| KEY_NAMES.iter().copied().collect());
+
+// `scancode:0x1E`, extended keys are written with their `E0` prefix like `scancode:0xE048`
+fn parse_scan_code(key_str: &str) -> Option<Result<u16, KeySenderError>> {
+    let code = key_str.strip_prefix("scancode:")?;
+    let parsed = code
+        .strip_prefix("0x")
+        .or_else(|| code.strip_prefix("0X"))
+        .and_then(|hex| u16::from_str_radix(hex, 16).ok());
+    Some(match parsed {
+        None => Err(KeySenderError::UnknownKey(key_str.to_string())),
+        Some(_) if !cfg!(target_os = "windows") => {
+            Err(KeySenderError::ScanCodeUnsupported(key_str.to_string()))
+        }
+        Some(code) => Ok(code),
+    })
+}
+
+// `WHEEL_UP` / `WHEEL_DOWN` scroll one step, `WHEEL_UP:0.25` a quarter of one, in
+// `STEP_PARTS`. `None` for anything that isn't a wheel scroll.
+fn parse_wheel(action: &str) -> Option<Result<i64, KeySenderError>> {
+    let (name, steps) = action.split_once(':').unwrap_or((action, "1"));
+    let sign = match name.to_uppercase().as_str() {
+        "WHEEL_UP" => -1,
+        "WHEEL_DOWN" => 1,
+        _ => return None,
+    };
+    // NaN and infinity parse as well, and less than a part would never scroll
+    let parts = steps
+        .parse::<f64>()
+        .ok()
+        .map(|steps| (steps * STEP_PARTS as f64).round())
+        .filter(|parts| (1.0..=i32::MAX as f64).contains(parts));
+    Some(match parts {
+        Some(parts) => Ok(sign * parts as i64),
+        None => Err(KeySenderError::WheelSteps(action.to_string())),
+    })
+}
+
+// A key thread that panicked drops its sender before it is started again, nothing it
+// pressed may stay down after that
+impl Drop for KeySender {
+    fn drop(&mut self) {
+        for stroke in mem::take(&mut self.active_key) {
+            // panicking again while unwinding would abort the process
+            let released = match stroke {
+                Stroke::Key(key) => self.enigo.key(key, Direction::Release),
+                Stroke::ScanCode(code) => self.enigo.raw(code, Direction::Release),
+            };
+            if let Err(e) = released {
+                warn!("Could not release {:?}: {}", stroke, e);
+            }
+        }
+        self.update_held_modifiers();
+    }
+}
+
+impl ActionSink for KeySender {
+    type Error = KeySenderError;
+
+    fn send(&mut self, action: &TourAction) -> Result<(), KeySenderError> {
+        self.send_key(action)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Records the keys it is asked for, its fast text entry fails with `text_error`
+    struct FakeKeyboard {
+        text_error: Option<InputError>,
+        keys: Vec<(Key, Direction)>,
+    }
+
+    impl FakeKeyboard {
+        fn failing(text_error: InputError) -> Self {
+            FakeKeyboard {
+                text_error: Some(text_error),
+                keys: Vec::new(),
+            }
+        }
+    }
+
+    impl Keyboard for FakeKeyboard {
+        fn fast_text(&mut self, _text: &str) -> enigo::InputResult<Option<()>> {
+            match self.text_error.clone() {
+                Some(e) => Err(e),
+                None => Ok(Some(())),
+            }
+        }
+
+        fn key(&mut self, key: Key, direction: Direction) -> enigo::InputResult<()> {
+            self.keys.push((key, direction));
+            Ok(())
+        }
+
+        fn raw(&mut self, _keycode: u16, _direction: Direction) -> enigo::InputResult<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn every_key_name_is_unique_and_parses_to_its_key() {
+        let mut seen = HashSet::new();
+        for (name, key) in KEY_NAMES {
+            assert!(seen.insert(*name), "`{}` is in KEY_NAMES twice", name);
+            // names are written uppercase, actions may use any case
+            assert_eq!(name.to_uppercase(), *name);
+            assert_eq!(KeySender::parse_key(name).ok(), Some(*key), "`{}`", name);
+            assert_eq!(KeySender::parse_key(&name.to_lowercase()).ok(), Some(*key));
+        }
+    }
+
+    #[test]
+    fn only_modifiers_routbox_does_not_hold_are_released() {
+        let active = HashSet::from([Stroke::Key(Key::Shift), Stroke::Key(Key::Other(65))]);
+        assert_eq!(modifiers_held_by(&active), HashSet::from(["SHIFT"]));
+        assert!(modifiers_held_by(&HashSet::new()).is_empty());
+
+        let keyboard = |k: &str| k == "ALT" || k == "SHIFT";
+        assert_eq!(host_modifiers_to_release(&active, keyboard), vec![Key::Alt]);
+        assert!(host_modifiers_to_release(&active, |_| false).is_empty());
+    }
+
+    #[test]
+    fn coordinates_stay_on_the_display() {
+        assert_eq!(clamp_to_display(-5, 1920), 0);
+        assert_eq!(clamp_to_display(500, 1920), 500);
+        assert_eq!(clamp_to_display(5000, 1920), 1919);
+        assert_eq!(clamp_to_display(500, 0), 0);
+        assert_eq!(clamp_to_display(-5, 0), 0);
+    }
+
+    #[test]
+    fn text_is_typed_at_once_when_possible() {
+        let mut keyboard = FakeKeyboard {
+            text_error: None,
+            keys: Vec::new(),
+        };
+        type_text(&mut keyboard, "hé").unwrap();
+        assert!(keyboard.keys.is_empty());
+    }
+
+    #[test]
+    fn failed_text_is_typed_one_character_at_a_time() {
+        let mut keyboard = FakeKeyboard::failing(InputError::Simulate("no fast text"));
+        type_text(&mut keyboard, "hé°").unwrap();
+        assert_eq!(
+            keyboard.keys,
+            [
+                (Key::Unicode('h'), Direction::Click),
+                (Key::Unicode('é'), Direction::Click),
+                (Key::Unicode('°'), Direction::Click),
+            ]
+        );
+    }
+
+    #[test]
+    fn invalid_text_is_not_retried() {
+        let mut keyboard = FakeKeyboard::failing(InputError::InvalidInput("contains NUL"));
+        assert_eq!(
+            type_text(&mut keyboard, "a\0b"),
+            Err(InputError::InvalidInput("contains NUL"))
+        );
+        assert!(keyboard.keys.is_empty());
+    }
+
+    #[test]
+    fn scroll_fractions_add_up_to_whole_steps() {
+        let mut remainder = 0;
+        assert_eq!(whole_steps(&mut remainder, 400), 0);
+        assert_eq!(whole_steps(&mut remainder, 400), 0);
+        assert_eq!(whole_steps(&mut remainder, 400), 1);
+        assert_eq!(remainder, 200);
+        assert_eq!(whole_steps(&mut remainder, 2500), 2);
+        assert_eq!(remainder, 700);
+
+        let tenth = parse_wheel("WHEEL_DOWN:0.1").unwrap().unwrap();
+        let mut remainder = 0;
+        let whole: i32 = (0..10).map(|_| whole_steps(&mut remainder, tenth)).sum();
+        assert_eq!(whole, 1);
+        assert_eq!(remainder, 0);
+    }
+
+    #[test]
+    fn turning_back_uses_up_the_remainder_first() {
+        let mut remainder = 0;
+        assert_eq!(whole_steps(&mut remainder, 600), 0);
+        // the way back cancels what was carried before scrolling up
+        assert_eq!(whole_steps(&mut remainder, -1000), 0);
+        assert_eq!(remainder, -400);
+        assert_eq!(whole_steps(&mut remainder, -600), -1);
+        assert_eq!(whole_steps(&mut remainder, -3000), -3);
+        assert_eq!(remainder, 0);
+    }
+
+    #[test]
+    fn keys_parse_into_strokes_or_a_scroll() {
+        let aliases = HashMap::from([("MUTE".to_string(), 0xAD)]);
+        match parse_keys("ctrl+MUTE", &aliases).unwrap() {
+            Parsed::Strokes(strokes) => assert_eq!(
+                strokes[..],
+                [Stroke::Key(Key::Control), Stroke::Key(Key::Other(0xAD))]
+            ),
+            parsed => panic!("{:?}", parsed),
+        }
+        assert!(matches!(
+            parse_keys("WHEEL_UP:2", &aliases).unwrap(),
+            Parsed::Scroll(-2000)
+        ));
+        assert!(matches!(
+            parse_keys("CTRL+NOPE", &aliases),
+            Err(KeySenderError::UnknownKey(k)) if k == "NOPE"
+        ));
+        assert!(check_keys("CTRL+NOPE", &aliases).is_err());
+    }
+
+    #[test]
+    fn wheel_steps_have_to_be_positive_numbers() {
+        assert_eq!(parse_wheel("WHEEL_UP").unwrap().unwrap(), -1000);
+        assert_eq!(parse_wheel("wheel_down:0.25").unwrap().unwrap(), 250);
+        for bad in ["nan", "inf", "-inf", "0", "-1", "0.0001", "x", ""] {
+            let action = format!("WHEEL_DOWN:{}", bad);
+            assert!(parse_wheel(&action).unwrap().is_err(), "{}", action);
+            assert!(check_keys(&action, &HashMap::new()).is_err(), "{}", action);
+        }
+        assert!(parse_wheel("CTRL+A").is_none());
+    }
+}
@@ -0,0 +1,11 @@
+//! The TourBox mapping engine: configuration, input events and the key mapping processor.
+//!
+//! Feed [`event::InputEvent`]s into a [`key_processor::KeyMappingProcessor`] and hand the
+//! resulting [`action::TourAction`]s to an [`action::ActionSink`]. [`device::run`] turns
+//! the raw key codes of a [`device::ByteStream`] into those events.
+
+pub mod action;
+pub mod config;
+pub mod device;
+pub mod event;
+pub mod key_processor;
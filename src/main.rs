@@ -1,85 +1,795 @@
 mod app;
-mod config;
-mod event;
-mod key_processor;
+mod clipboard;
+mod console;
+mod foreground;
+mod host_keys;
 mod key_sender;
+mod metrics;
+mod notify;
+mod os_actions;
+mod raw_log;
 mod serial;
+mod sound;
+mod state_file;
+mod stats;
+mod status;
+mod stdin;
+mod tui;
+mod window_mgmt;
 mod winusb;
 
-use crate::key_processor::KeyMappingProcessor;
+use crate::app::UiEvent;
 use crate::key_sender::KeySender;
+use crate::serial::SerialSource;
+use crate::stdin::StdinSource;
+use crate::winusb::WinUsbSource;
+use chrono::Timelike;
 use clap::Parser;
 use eframe::egui;
 use log::{debug, error, info, warn};
-use std::sync::{Arc, mpsc};
-use std::thread;
+use routbox::action::{ActionSink, TourAction};
+use routbox::config;
+use routbox::device::DeviceControl;
+use routbox::event::{EventSource, InputEvent, SourcedEvent};
+use routbox::key_processor::{KeyMappingProcessor, MappingSwitches, ResetRequest, action_keys};
+use std::collections::HashSet;
+use std::fs;
+use std::io;
+use std::iter;
+use std::mem;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::mpsc::{Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex, mpsc};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
 
 #[derive(Parser, Debug)]
 #[command(version, about, long_about = None)]
 struct Args {
+    /// Config file, `-` reads it from stdin. Without the file, a built-in default is used.
+    /// Given more than once, the files are merged in order, later ones overriding earlier ones
     #[arg(short, long, default_value = "config.json")]
-    config: String,
+    config: Vec<String>,
+    /// Read key events (`press <KEY>` / `release <KEY>` lines) from stdin instead of the device
+    #[arg(long)]
+    stdin: bool,
+    /// Keep key usage counts in this file across sessions
+    #[arg(long)]
+    stats_file: Option<String>,
+    /// Run without a window, e.g. as a background service
+    #[arg(long)]
+    headless: bool,
+    /// Show the actions and device status in the terminal instead of a window
+    #[arg(long, conflicts_with = "headless")]
+    tui: bool,
+    /// Serve Prometheus metrics on this address, e.g. `127.0.0.1:9898`
+    #[arg(long)]
+    metrics_addr: Option<String>,
+    /// Log which mappings each key event could trigger, their scores and which one wins
+    #[arg(long)]
+    trace_mapping: bool,
+    /// Log how long each key event took from being read from the device to its last action sent,
+    /// and how late the actions of timers and held back presses went out
+    #[arg(long)]
+    log_latency: bool,
+    /// Write every raw read from the device to this file, rolled over at 10 MB
+    #[arg(long)]
+    raw_log: Option<String>,
+    /// Print the name of every key pressed on the device and exit with Ctrl+C, to write a key map
+    #[arg(long, conflicts_with_all = ["headless", "tui", "validate", "dump_config"])]
+    probe: bool,
+    /// Write the mappings and profiles to this file, to be merged over another config, and exit
+    #[arg(long, value_name = "FILE")]
+    export: Option<String>,
+    /// Also export the `key_map`, for sharing a layout for a different device
+    #[arg(long, requires = "export")]
+    export_key_map: bool,
+    /// Merge a layout written by `--export` into the config, can be given more than once
+    #[arg(long, value_name = "FILE")]
+    import: Vec<String>,
+    /// Check the config, print its problems and exit, with a non-zero code if it has errors
+    #[arg(long)]
+    validate: bool,
+    /// Print the config with every default filled in as JSON and exit
+    #[arg(long)]
+    dump_config: bool,
+    /// Start with fresh state variables and locks instead of the ones from the last run
+    #[arg(long)]
+    no_persist_state: bool,
+    /// Keep the console window that opens with the app when started from Explorer on Windows,
+    /// and log to it instead of a file
+    #[arg(long)]
+    console: bool,
+}
+
+// how long a panicked thread waits before it is started again
+const RESTART_DELAY: Duration = Duration::from_secs(5);
+// how long closing the UI waits for the device and key threads to finish
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(2);
+
+// Runs `f` again whenever it panics, until it returns normally
+fn supervise(name: &str, status: &status::SharedStatus, mut f: impl FnMut()) {
+    while panic::catch_unwind(AssertUnwindSafe(&mut f)).is_err() {
+        error!(
+            "The {} thread panicked, restarting it in {} seconds",
+            name,
+            RESTART_DELAY.as_secs()
+        );
+        status.lock().unwrap().thread_panicked(name);
+        thread::sleep(RESTART_DELAY);
+    }
+}
+
+fn spawn_event_source<S>(
+    source: S,
+    sender: Sender<SourcedEvent>,
+    status: status::SharedStatus,
+) -> Option<JoinHandle<()>>
+where
+    S: EventSource + Clone + Send + 'static,
+{
+    let thread_status = status.clone();
+    let spawned = thread::Builder::new()
+        .name("device".to_string())
+        .spawn(move || {
+            supervise("device", &thread_status, || {
+                source.clone().run(sender.clone())
+            });
+        });
+    // the UI keeps running so the failure shows up there, not just in the log
+    spawned
+        .inspect_err(|e| {
+            error!("The device thread failed to start: {}", e);
+            status.lock().unwrap().thread_failed("device", e);
+        })
+        .ok()
+}
+
+// Waits until the threads finish, all of them within `timeout`; one that doesn't, e.g.
+// blocked reading stdin, is left behind
+fn join_threads(threads: Vec<(&str, JoinHandle<()>)>, timeout: Duration) {
+    let deadline = Instant::now() + timeout;
+    for (name, thread) in threads {
+        while !thread.is_finished() && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(10));
+        }
+        if thread.is_finished() {
+            thread.join().ok();
+        } else {
+            warn!("The {} thread did not stop within {:?}", name, timeout);
+        }
+    }
+}
+
+// The key thread's end of the device thread: its events, and requests back to it
+struct DeviceLink {
+    events: Receiver<SourcedEvent>,
+    control: DeviceControl,
+}
+
+// The key thread's end of the UI: what it shows, and mappings switched on or off there
+struct UiLink {
+    sender: Sender<UiEvent>,
+    switches: MappingSwitches,
+}
+
+// What the key thread logs besides the events and actions
+#[derive(Clone, Copy)]
+struct KeyLogging {
+    trace_mapping: bool,
+    latency: bool,
+}
+
+// Turns device events into actions until the device threads are gone
+fn process_events(
+    cfg: &config::Config,
+    device: &DeviceLink,
+    ui: &UiLink,
+    stats: &stats::SharedStats,
+    metrics: &metrics::Metrics,
+    logging: KeyLogging,
+    state_path: Option<&Path>,
+) {
+    let mut key_sender = KeySender::new()
+        .with_delays(
+            Duration::from_millis(cfg.key_press_ms),
+            Duration::from_millis(cfg.inter_key_delay_ms),
+        )
+        .with_host_modifiers_released(
+            cfg.release_host_modifiers
+                .then_some(host_keys::is_held as fn(&str) -> bool),
+        )
+        .with_key_aliases(cfg.key_aliases.clone());
+    // a modifier routbox holds down itself doesn't count as held on the keyboard
+    let sender_holds = key_sender.held_modifiers();
+    let dial_keys: HashSet<String> = cfg.key_map.stateless.values().cloned().collect();
+    let mut processor = KeyMappingProcessor::from_config(&cfg.mappings)
+        .with_profiles(&cfg.profiles)
+        .with_shift_key(cfg.shift_key.clone())
+        .with_pass_through(&cfg.pass_through)
+        .with_sticky_keys(
+            &cfg.sticky_keys,
+            Duration::from_millis(cfg.sticky_timeout_ms),
+        )
+        .with_hooks(&cfg.on_connect, &cfg.on_disconnect)
+        .with_low_battery_hook(&cfg.on_low_battery, cfg.low_battery_percent)
+        .with_timers(&cfg.timers)
+        .with_schedule(&cfg.schedule, || {
+            let now = chrono::Local::now();
+            now.hour() * 60 + now.minute()
+        })
+        .with_co_press_window(Duration::from_millis(cfg.co_press_window_ms))
+        .with_multi_tap_window(Duration::from_millis(cfg.multi_tap_window_ms))
+        .with_combo_grace(Duration::from_millis(cfg.combo_grace_ms))
+        .with_dial_dead_zone(
+            dial_keys.clone(),
+            cfg.dial_dead_zone,
+            Duration::from_millis(cfg.dial_idle_ms),
+        )
+        .with_dial_acceleration(Duration::from_millis(cfg.dial_accel_ms), cfg.dial_accel_max)
+        .with_host_keys(move |k| host_keys::is_held(k) && !sender_holds.lock().unwrap().contains(k))
+        .with_trace(logging.trace_mapping)
+        .with_switches(ui.switches.clone());
+    if !host_keys::SUPPORTED
+        && cfg
+            .all_mappings()
+            .iter()
+            .any(|(_, m)| !m.host_modifiers.is_empty())
+    {
+        warn!("Host modifiers can't be looked up on this platform, those mappings never fire");
+    }
+    // unknown keys show up now instead of at the first press
+    for (source, action) in config_actions(cfg) {
+        if let Some(keys) = action_keys(action)
+            && let Err(e) = key_sender.prepare(&keys)
+        {
+            warn!("{} action `{}`: {}", source, action, e);
+        }
+    }
+    let mut app_filter = foreground::AppFilter::new(&cfg.active_apps, &cfg.inactive_apps);
+    let mut sounds = sound::Sounds::default();
+
+    if let Some(path) = state_path {
+        match state_file::load(path) {
+            Ok(Some(saved)) => {
+                info!(
+                    "Restored state variables and locks from '{}'",
+                    path.display()
+                );
+                // the window learns about them like about any other change
+                let restored = saved
+                    .locked
+                    .iter()
+                    .map(|key| TourAction::ModifierLock {
+                        key: key.clone(),
+                        locked: true,
+                    })
+                    .chain(
+                        saved
+                            .variables
+                            .iter()
+                            .map(|(name, value)| TourAction::SetState {
+                                name: name.clone(),
+                                value: *value,
+                            }),
+                    );
+                for v in restored {
+                    if ui.sender.send(UiEvent::Action(v)).is_err() {
+                        info!("UI has been closed. Exiting key thread.");
+                        return;
+                    }
+                }
+                processor = processor.with_state(saved);
+            }
+            Ok(None) => {}
+            Err(e) => warn!("Could not load the state from '{}': {}", path.display(), e),
+        }
+    }
+
+    // the first round only ticks, which starts the timers and checks the schedule
+    let mut first = true;
+    loop {
+        // wake up for held back presses and timers even when no new event arrives
+        let first_round = mem::take(&mut first);
+        let deadline = processor.next_deadline();
+        let event = match deadline {
+            _ if first_round => Err(RecvTimeoutError::Timeout),
+            Some(deadline) => device
+                .events
+                .recv_timeout(deadline.saturating_duration_since(Instant::now())),
+            None => device.events.recv().map_err(RecvTimeoutError::from),
+        };
+
+        // latency is measured from when the device sent the key event, or from when the
+        // timer or held back press that fires on a tick was due
+        let (mut a, due) = match event {
+            Ok(SourcedEvent { event, source, at }) => {
+                if let Some(source) = source {
+                    debug!("{:?} from the `{}` device", event, source);
+                }
+                stats.lock().unwrap().record(&event);
+                metrics.record_event(&event);
+                // dial ticks are never released, the window only shows keys that are held
+                let shown = match &event {
+                    InputEvent::KeyPressed(k) | InputEvent::KeyReleased(k) => {
+                        !dial_keys.contains(k)
+                    }
+                    InputEvent::Connected
+                    | InputEvent::Disconnected
+                    | InputEvent::Battery(_)
+                    | InputEvent::LinkQuality(_) => true,
+                    InputEvent::Unknown(_) | InputEvent::ResetRequested => false,
+                };
+                if shown && ui.sender.send(UiEvent::Input(event.clone())).is_err() {
+                    info!("UI has been closed. Exiting key thread.");
+                    return;
+                }
+                (processor.process(event), Some((at, "after the event")))
+            }
+            Err(RecvTimeoutError::Timeout) => (
+                processor.tick(),
+                deadline
+                    .filter(|_| !first_round)
+                    .map(|deadline| (deadline, "after they were due")),
+            ),
+            Err(RecvTimeoutError::Disconnected) => return,
+        };
+        if !a.is_empty() && !app_filter.allows_foreground() {
+            // releases still go out so nothing stays stuck, profile changes and locks have no output
+            a.retain(|v| {
+                matches!(
+                    v,
+                    TourAction::KeyRelease(_)
+                        | TourAction::SwitchProfile(_)
+                        | TourAction::PushProfile(_)
+                        | TourAction::PopProfile
+                        | TourAction::ModifierLock { .. }
+                        | TourAction::SetState { .. }
+                        | TourAction::UiAction(_)
+                )
+            });
+        }
+        debug!("{a:?}");
+        let state_changed = a.iter().any(|v| {
+            matches!(
+                v,
+                TourAction::SetState { .. } | TourAction::ModifierLock { .. }
+            )
+        });
+        if state_changed
+            && let Some(path) = state_path
+            && let Err(e) = state_file::save(path, &processor.snapshot())
+        {
+            warn!("Could not save the state to '{}': {}", path.display(), e);
+        }
+        let actions = a.len();
+        for v in a.into_iter() {
+            metrics.record_action();
+            let failure = match &v {
+                TourAction::OsCommand(cmd) => os_actions::run(*cmd)
+                    .err()
+                    .map(|e| format!("could not run {:?}: {}", cmd, e)),
+                TourAction::WindowAction(op) => window_mgmt::run(*op)
+                    .err()
+                    .map(|e| format!("could not run {:?}: {}", op, e)),
+                TourAction::Notify { title, body } => notify::show(title, body)
+                    .err()
+                    .map(|e| format!("could not show notification '{}': {}", title, e)),
+                TourAction::ReinitDevice => {
+                    device.control.request_reinit();
+                    None
+                }
+                TourAction::PlaySound(sound) => {
+                    sounds.play(sound.as_deref());
+                    None
+                }
+                _ => key_sender.send(&v).err().map(|e| e.to_string()),
+            };
+
+            // send to ui, a failure right after the action it belongs to
+            if let Some(failure) = &failure {
+                warn!("{}", failure);
+            }
+            let events = iter::once(UiEvent::Action(v)).chain(failure.map(UiEvent::Failed));
+            for event in events {
+                if ui.sender.send(event).is_err() {
+                    info!("UI has been closed. Exiting key thread.");
+                    return;
+                }
+            }
+        }
+        if let Some((due, since)) = due
+            && actions > 0
+        {
+            let latency = due.elapsed();
+            metrics.record_latency(latency);
+            if logging.latency {
+                info!("{} action(s) sent {:?} {}", actions, latency, since);
+            }
+        }
+    }
+}
+
+// Prints what the device sends as `key_map` decodes it, until the device thread is gone
+// Keys are shown with the device's `prefix`, as mappings may name them.
+fn probe(receiver: &Receiver<SourcedEvent>, key_map: &config::KeyMap) {
+    println!("Press the keys of the device, Ctrl+C to quit");
+    for sourced in receiver {
+        let name = sourced.key_name().unwrap_or_default();
+        match sourced.event {
+            InputEvent::KeyPressed(k) => match key_map.code(&k) {
+                Some(code) => println!("Pressed: {} (0x{:02x})", name, code),
+                None => println!("Pressed: {}", name),
+            },
+            InputEvent::KeyReleased(_) => println!("Released: {}", name),
+            InputEvent::Connected => println!("Connected"),
+            InputEvent::Disconnected => println!("Disconnected"),
+            InputEvent::Unknown(code) => println!("Unknown: 0x{:02x}, not in key_map", code),
+            InputEvent::Battery(level) => println!("Battery: {}%", level),
+            InputEvent::LinkQuality(quality) => println!("Link quality: {}%", quality),
+            InputEvent::ResetRequested => {}
+        }
+    }
+}
+
+// Every action of the config, with where it is configured for messages
+fn config_actions(cfg: &config::Config) -> Vec<(String, &String)> {
+    cfg.all_mappings()
+        .into_iter()
+        .flat_map(|(_, m)| {
+            m.action
+                .iter()
+                .map(|a| (format!("mapping `{}`", m.keys), a))
+        })
+        .chain(
+            cfg.on_connect
+                .iter()
+                .map(|a| ("`on_connect`".to_string(), a)),
+        )
+        .chain(
+            cfg.on_disconnect
+                .iter()
+                .map(|a| ("`on_disconnect`".to_string(), a)),
+        )
+        .chain(
+            cfg.on_low_battery
+                .iter()
+                .map(|a| ("`on_low_battery`".to_string(), a)),
+        )
+        .chain(
+            cfg.timers
+                .iter()
+                .flat_map(|t| t.action.iter().map(|a| ("timer".to_string(), a))),
+        )
+        .chain(
+            cfg.pass_through
+                .iter()
+                .map(|(k, a)| (format!("`pass_through` key `{}`", k), a)),
+        )
+        .collect()
+}
+
+// Prints every problem of the config, returns whether any of them is an error. Warnings
+// are logged at startup too, errors are actions that would fail when sent.
+fn report_config(config_name: &str, cfg: &config::Config) -> bool {
+    let warnings = cfg.warnings();
+    let mut errors = cfg.errors();
+    let actions = config_actions(cfg);
+    for (source, action) in actions {
+        if let Some(keys) = action_keys(action)
+            && let Err(e) = key_sender::check_keys(&keys, &cfg.key_aliases)
+        {
+            errors.push(format!("{} action `{}`: {}", source, action, e));
+        }
+    }
+
+    for warning in &warnings {
+        println!("{}: warning: {}", config_name, warning);
+    }
+    for error in &errors {
+        println!("{}: error: {}", config_name, error);
+    }
+    println!(
+        "{}: {} error(s), {} warning(s)",
+        config_name,
+        errors.len(),
+        warnings.len()
+    );
+    !errors.is_empty()
 }
 
 fn main() {
-    env_logger::init();
     let args = Args::parse();
+    let mut logger = env_logger::Builder::from_default_env();
+    if args.trace_mapping {
+        // the trace is logged as info, show it even when RUST_LOG asks for less
+        logger.filter_module("routbox::key_processor", log::LevelFilter::Info);
+    }
+    // modes that print or draw in the terminal need one, the window logs to a file without
+    let prints =
+        args.tui || args.probe || args.validate || args.dump_config || args.export.is_some();
+    let has_console = args.console || prints || !console::free_own();
+    // the terminal UI would be drawn over by anything logged to the terminal
+    let log_file = match args.tui || !has_console {
+        true => fs::File::create(console::log_path())
+            .inspect_err(|e| eprintln!("Could not create the log file: {}", e))
+            .ok(),
+        false => None,
+    };
+    match log_file {
+        Some(file) => {
+            logger.target(env_logger::Target::Pipe(Box::new(file)));
+        }
+        None if args.tui => {
+            logger.target(env_logger::Target::Pipe(Box::new(io::sink())));
+        }
+        None => {}
+    }
+    logger.init();
     info!("Starting TourBox application");
 
     let (tourbox_sender, tourbox_receiver) = mpsc::channel();
     let (app_sender, app_receiver) = mpsc::channel();
 
-    let config = Arc::new(match config::Config::from_file(&args.config) {
-        Ok(cfg) => {
-            info!("Configuration loaded from '{}'", &args.config);
+    let from_stdin = args.config.iter().any(|c| c == "-");
+    if from_stdin && args.stdin {
+        error!("`--config -` and `--stdin` can't both read from stdin");
+        return;
+    }
+    if from_stdin && args.config.len() > 1 {
+        error!("`--config -` can't be merged with other config files");
+        return;
+    }
+
+    // the config file the UI can open for editing, the last one when there are several
+    let mut config_path = None;
+    if args.validate
+        && let Some(missing) = args
+            .config
+            .iter()
+            .find(|c| *c != "-" && !Path::new(c).exists())
+    {
+        println!("{}: error: the file does not exist", missing);
+        process::exit(1);
+    }
+
+    let files_name = config::Config::files_name(&args.config);
+    let (config_name, loaded) = if from_stdin {
+        (
+            "<stdin>",
+            config::Config::from_reader(io::stdin().lock(), "<stdin>"),
+        )
+    } else if let [path] = args.config.as_slice()
+        && !Path::new(path).exists()
+    {
+        warn!(
+            "Config file '{}' does not exist, using the built-in default for a TourBox over WinUSB",
+            path
+        );
+        ("built-in default", Ok(config::Config::embedded_default()))
+    } else {
+        let last = args.config.last().expect("clap always gives a config");
+        config_path = Some(std::path::absolute(last).unwrap_or(PathBuf::from(last)));
+        (
+            files_name.as_str(),
+            config::Config::from_files(&args.config),
+        )
+    };
+
+    let loaded = loaded.and_then(|mut cfg| {
+        for path in &args.import {
+            cfg.import_layout(config::Layout::from_file(path)?);
+        }
+        Ok(cfg)
+    });
+    let config = Arc::new(match loaded {
+        Ok(mut cfg) => {
+            cfg.strip_key_prefix();
+            info!("Configuration loaded from '{}'", config_name);
             cfg
         }
+        Err(e) if args.validate || args.dump_config || args.export.is_some() => {
+            // the error starts with the file name already
+            println!("error: {}", e);
+            process::exit(1);
+        }
         Err(e) => {
-            error!(
-                "Failed to read or parse config file '{}': {}",
-                &args.config, e
-            );
+            // the error names the file and where in it the problem is
+            error!("Failed to load config: {}", e);
             return;
         }
     });
 
-    match &config.device {
-        config::TourBoxDevice::WinUsb { vid: _, pid: _ } => {
-            winusb::winusb_tourbox_processor(config.clone(), tourbox_sender.clone());
-        }
-        config::TourBoxDevice::Serial {
-            serial_port: _,
-            baud_rate: _,
-        } => {
-            serial::serial_tourbox_processor(config.clone(), tourbox_sender.clone());
+    if args.dump_config {
+        println!("{}", config.to_json());
+        return;
+    }
+
+    if let Some(path) = &args.export {
+        if let Err(e) = fs::write(path, config.export_layout(args.export_key_map)) {
+            println!("error: could not write '{}': {}", path, e);
+            process::exit(1);
         }
+        println!("Exported the mappings of {} to '{}'", config_name, path);
+        return;
     }
 
-    let cfg = config.clone();
-    thread::spawn(move || {
-        let mut processor = KeyMappingProcessor::from_config(&cfg.mappings);
-        let mut key_sender = KeySender::new();
-
-        loop {
-            let event = tourbox_receiver.recv().ok();
-
-            if let Some(event) = event {
-                let a = processor.process(event);
-                debug!("{a:?}");
-                for v in a.into_iter() {
-                    if let Err(e) = key_sender.send_key(&v) {
-                        warn!("{e}");
-                    }
+    if args.validate {
+        let has_errors = report_config(config_name, &config);
+        process::exit(if has_errors { 1 } else { 0 });
+    }
 
-                    app_sender.send(v).expect("Channel to app is broken");
-                }
-                // send to ui
+    for problem in config.validate() {
+        warn!("{}: {}", config_name, problem);
+    }
+
+    let status = Arc::new(Mutex::new(status::DeviceStatus::default()));
+
+    let key_stats = match &args.stats_file {
+        Some(path) if Path::new(path).exists() => stats::KeyStats::load(path).unwrap_or_else(|e| {
+            warn!("Could not load key stats from '{}': {}", path, e);
+            Default::default()
+        }),
+        _ => Default::default(),
+    };
+    let key_stats = Arc::new(Mutex::new(key_stats));
+    if let Some(path) = &args.stats_file {
+        stats::save_periodically(key_stats.clone(), path.clone());
+    }
+
+    let device = if args.stdin {
+        &config::TourBoxDevice::Stdin { prefix: None }
+    } else {
+        &config.device
+    };
+
+    let raw_log = args
+        .raw_log
+        .as_ref()
+        .and_then(|path| match raw_log::RawLog::open(path) {
+            Ok(raw_log) => Some(raw_log),
+            Err(e) => {
+                error!("Could not open raw log '{}': {}", path, e);
+                None
+            }
+        });
+
+    // the window's reset button wakes the key thread with a message on its channel. Only the
+    // window gets a sender, with one left over the key thread wouldn't end with the device's.
+    let reset = (!args.probe && !args.headless && !args.tui)
+        .then(|| ResetRequest::new(tourbox_sender.clone()));
+    let control = DeviceControl::default();
+    let device_thread = match device {
+        config::TourBoxDevice::WinUsb(usb) => {
+            let source = WinUsbSource::new(usb.clone(), config.key_map.clone(), status.clone())
+                .with_raw_log(raw_log)
+                .with_control(control.clone());
+            spawn_event_source(source, tourbox_sender, status.clone())
+        }
+        config::TourBoxDevice::Serial(serial) => {
+            let source = SerialSource::new(serial.clone(), config.key_map.clone(), status.clone())
+                .with_raw_log(raw_log)
+                .with_control(control.clone());
+            spawn_event_source(source, tourbox_sender, status.clone())
+        }
+        config::TourBoxDevice::Stdin { .. } => {
+            if raw_log.is_some() {
+                warn!("Key events from stdin have no raw data, the raw log stays empty");
             }
+            let source = StdinSource {
+                prefix: device.prefix().map(str::to_owned),
+            };
+            spawn_event_source(source, tourbox_sender, status.clone())
         }
+    };
+
+    if args.probe {
+        probe(&tourbox_receiver, &config.key_map);
+        return;
+    }
+
+    let metrics = Arc::new(metrics::Metrics::default());
+    if let Some(addr) = &args.metrics_addr
+        && let Err(e) = metrics::serve(addr, metrics.clone())
+    {
+        error!("Could not serve metrics on '{}': {}", addr, e);
+    }
+
+    // next to the config, so every config keeps its own
+    let state_path = config_path
+        .as_ref()
+        .filter(|_| !args.no_persist_state)
+        .map(|path| path.with_extension("state.json"));
+    let cfg = config.clone();
+    let device_link = DeviceLink {
+        events: tourbox_receiver,
+        control: control.clone(),
+    };
+    let switches = MappingSwitches::from_config(&config.mappings, &config.profiles);
+    let ui_link = UiLink {
+        sender: app_sender,
+        switches: switches.clone(),
+    };
+    let stats = key_stats.clone();
+    let key_status = status.clone();
+    let key_thread = thread::spawn(move || {
+        supervise("key", &key_status, || {
+            process_events(
+                &cfg,
+                &device_link,
+                &ui_link,
+                &stats,
+                &metrics,
+                KeyLogging {
+                    trace_mapping: args.trace_mapping,
+                    latency: args.log_latency,
+                },
+                state_path.as_deref(),
+            )
+        });
     });
 
+    if args.headless {
+        info!("Running headless");
+        // nothing shows the actions, just keep the channel drained until processing stops
+        for _ in app_receiver {}
+    } else if args.tui {
+        if let Err(e) = tui::run(app_receiver, status, app::View::with_ui(config.ui.clone())) {
+            error!("Error running the terminal UI: {}", e);
+        }
+    } else if let Some(reset) = reset {
+        run_ui(
+            app_receiver,
+            status,
+            key_stats.clone(),
+            config_path,
+            &config,
+            switches,
+            reset,
+        );
+    }
+
+    // the device thread releases the device on its way out, which ends the key thread too
+    control.request_stop();
+    let threads = device_thread
+        .map(|t| ("device", t))
+        .into_iter()
+        .chain([("key", key_thread)])
+        .collect();
+    join_threads(threads, SHUTDOWN_TIMEOUT);
+
+    if let Some(path) = &args.stats_file {
+        match key_stats.lock().unwrap().save(path) {
+            Ok(()) => info!("Key stats saved to '{}'", path),
+            Err(e) => error!("Could not save key stats to '{}': {}", path, e),
+        }
+    }
+}
+
+fn run_ui(
+    app_receiver: Receiver<UiEvent>,
+    status: status::SharedStatus,
+    stats: stats::SharedStats,
+    config_path: Option<PathBuf>,
+    config: &config::Config,
+    switches: MappingSwitches,
+    reset: ResetRequest,
+) {
+    let theme = config.ui.theme;
+    let shown = app::ConfigView {
+        path: config_path,
+        mappings: app::MappingTable::new(config, switches),
+        ui: config.ui.clone(),
+    };
     let native_options = eframe::NativeOptions {
         viewport: egui::ViewportBuilder::default().with_inner_size([500.0, 400.0]),
+        follow_system_theme: theme == config::Theme::System,
+        default_theme: match theme {
+            config::Theme::Light => eframe::Theme::Light,
+            config::Theme::Dark | config::Theme::System => eframe::Theme::Dark,
+        },
         ..Default::default()
     };
 
@@ -88,7 +798,16 @@ fn main() {
     if let Err(e) = eframe::run_native(
         "TourBox Command Receiver",
         native_options,
-        Box::new(move |cc| Box::new(app::TourApp::new(app_receiver, cc.egui_ctx.clone()))),
+        Box::new(move |cc| {
+            Box::new(app::TourApp::new(
+                app_receiver,
+                status,
+                stats,
+                shown,
+                reset,
+                cc.egui_ctx.clone(),
+            ))
+        }),
     ) {
         error!("Error running eframe application: {}", e);
     }
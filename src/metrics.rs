@@ -0,0 +1,188 @@
+use log::{debug, info, warn};
+use std::fmt::Write as _;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::thread;
+use std::time::Duration;
+
+use routbox::event::InputEvent;
+
+// Counters for `/metrics`, updated by the key thread without taking a lock
+#[derive(Debug, Default)]
+pub struct Metrics {
+    events: AtomicU64,
+    actions: AtomicU64,
+    latency_micros: AtomicU64,
+    latency_max_micros: AtomicU64,
+    latencies: AtomicU64,
+    connections: AtomicU64,
+    connected: AtomicBool,
+}
+
+pub type SharedMetrics = Arc<Metrics>;
+
+impl Metrics {
+    pub fn record_event(&self, ev: &InputEvent) {
+        if *ev == InputEvent::ResetRequested {
+            // asked for by the window, not sent by the device
+            return;
+        }
+        self.events.fetch_add(1, Ordering::Relaxed);
+        match ev {
+            InputEvent::Connected => {
+                self.connections.fetch_add(1, Ordering::Relaxed);
+                self.connected.store(true, Ordering::Relaxed);
+            }
+            InputEvent::Disconnected => self.connected.store(false, Ordering::Relaxed),
+            _ => {}
+        }
+    }
+
+    pub fn record_action(&self) {
+        self.actions.fetch_add(1, Ordering::Relaxed);
+    }
+
+    // From a key event arriving to its last action sent
+    pub fn record_latency(&self, latency: Duration) {
+        let micros = latency.as_micros() as u64;
+        self.latency_micros.fetch_add(micros, Ordering::Relaxed);
+        self.latency_max_micros.fetch_max(micros, Ordering::Relaxed);
+        self.latencies.fetch_add(1, Ordering::Relaxed);
+    }
+
+    // Prometheus text exposition format
+    fn render(&self) -> String {
+        let connections = self.connections.load(Ordering::Relaxed);
+        let metrics = [
+            (
+                "routbox_events_total",
+                "counter",
+                "Device events processed.",
+                self.events.load(Ordering::Relaxed),
+            ),
+            (
+                "routbox_actions_total",
+                "counter",
+                "Actions sent.",
+                self.actions.load(Ordering::Relaxed),
+            ),
+            (
+                "routbox_action_latency_microseconds_sum",
+                "counter",
+                "Time from key events read from the device, or timers due, to their last action sent.",
+                self.latency_micros.load(Ordering::Relaxed),
+            ),
+            (
+                "routbox_action_latency_microseconds_count",
+                "counter",
+                "Key events and timers that sent actions.",
+                self.latencies.load(Ordering::Relaxed),
+            ),
+            (
+                "routbox_action_latency_microseconds_max",
+                "gauge",
+                "The longest time from a key event or timer to its last action sent.",
+                self.latency_max_micros.load(Ordering::Relaxed),
+            ),
+            (
+                "routbox_reconnects_total",
+                "counter",
+                "Times the device connected again after the first connection.",
+                connections.saturating_sub(1),
+            ),
+            (
+                "routbox_connected",
+                "gauge",
+                "Whether the device is connected.",
+                self.connected.load(Ordering::Relaxed) as u64,
+            ),
+        ];
+        let mut out = String::new();
+        for (name, kind, help, value) in metrics {
+            writeln!(out, "# HELP {} {}", name, help).unwrap();
+            writeln!(out, "# TYPE {} {}", name, kind).unwrap();
+            writeln!(out, "{} {}", name, value).unwrap();
+        }
+        out
+    }
+}
+
+// how long a client may take to send its request or read the answer
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(5);
+
+// Serves the metrics on `http://<addr>/metrics` from a background thread, and returns the
+// address it listens on
+pub fn serve(addr: &str, metrics: SharedMetrics) -> io::Result<SocketAddr> {
+    let listener = TcpListener::bind(addr)?;
+    let local_addr = listener.local_addr()?;
+    info!("Serving metrics on http://{}/metrics", local_addr);
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            // each client on its own thread, an idle one doesn't hold up the next scrape
+            let metrics = metrics.clone();
+            let result = stream.map(|s| {
+                thread::spawn(move || {
+                    if let Err(e) = respond(s, &metrics) {
+                        debug!("Metrics request failed: {}", e);
+                    }
+                })
+            });
+            if let Err(e) = result {
+                debug!("Metrics connection failed: {}", e);
+            }
+        }
+        warn!("Metrics server stopped");
+    });
+    Ok(local_addr)
+}
+
+fn respond(stream: TcpStream, metrics: &Metrics) -> io::Result<()> {
+    stream.set_read_timeout(Some(CLIENT_TIMEOUT))?;
+    stream.set_write_timeout(Some(CLIENT_TIMEOUT))?;
+    let mut reader = BufReader::new(&stream);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    // the headers are not needed, but have to be read before answering
+    let mut header = String::new();
+    while reader.read_line(&mut header)? > 2 {
+        header.clear();
+    }
+
+    let mut parts = request_line.split_whitespace();
+    let (status, body) = match (parts.next(), parts.next()) {
+        (Some("GET"), Some("/metrics")) => ("200 OK", metrics.render()),
+        _ => ("404 Not Found", "Not found, try /metrics\n".to_string()),
+    };
+    write!(
+        &stream,
+        "HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    #[test]
+    fn an_idle_client_does_not_block_a_scrape() {
+        let addr = serve("127.0.0.1:0", SharedMetrics::default()).unwrap();
+        // connects and never sends a request
+        let _idle = TcpStream::connect(addr).unwrap();
+
+        let mut scrape = TcpStream::connect(addr).unwrap();
+        scrape
+            .set_read_timeout(Some(Duration::from_secs(2)))
+            .unwrap();
+        write!(scrape, "GET /metrics HTTP/1.1\r\n\r\n").unwrap();
+        let mut answer = String::new();
+        scrape.read_to_string(&mut answer).unwrap();
+        assert!(answer.starts_with("HTTP/1.1 200 OK"));
+        assert!(answer.contains("routbox_events_total"));
+    }
+}
@@ -0,0 +1,55 @@
+use std::io;
+use std::process::Command;
+
+use crate::os_actions;
+
+// Title and body are passed through the environment or as arguments, never spliced into a script
+#[cfg(target_os = "windows")]
+fn command(title: &str, body: &str) -> Command {
+    let mut command = Command::new("powershell.exe");
+    command
+        .args([
+            "-NoProfile",
+            "-WindowStyle",
+            "Hidden",
+            "-Command",
+            "Add-Type -AssemblyName System.Windows.Forms; \
+             $n = New-Object System.Windows.Forms.NotifyIcon; \
+             $n.Icon = [System.Drawing.SystemIcons]::Information; \
+             $n.Visible = $true; \
+             $n.ShowBalloonTip(5000, $env:ROUTBOX_NOTIFY_TITLE, $env:ROUTBOX_NOTIFY_BODY, 'Info'); \
+             Start-Sleep -Seconds 6; \
+             $n.Dispose()",
+        ])
+        .env("ROUTBOX_NOTIFY_TITLE", title)
+        .env("ROUTBOX_NOTIFY_BODY", body);
+    command
+}
+
+#[cfg(target_os = "macos")]
+fn command(title: &str, body: &str) -> Command {
+    let mut command = Command::new("osascript");
+    command.args([
+        "-e",
+        "on run argv",
+        "-e",
+        "display notification (item 2 of argv) with title (item 1 of argv)",
+        "-e",
+        "end run",
+        title,
+        body,
+    ]);
+    command
+}
+
+#[cfg(not(any(target_os = "windows", target_os = "macos")))]
+fn command(title: &str, body: &str) -> Command {
+    let mut command = Command::new("notify-send");
+    command.args(["--app-name=routbox", "--", title, body]);
+    command
+}
+
+// Shows a desktop notification, returns once it is handed to the system
+pub fn show(title: &str, body: &str) -> Result<(), io::Error> {
+    os_actions::spawn(&mut command(title, body))
+}
@@ -0,0 +1,83 @@
+use log::info;
+use std::io;
+use std::path::Path;
+use std::process::Command;
+use std::thread;
+
+use routbox::action::OsCommand;
+
+// The program and arguments carrying out the command on this platform
+#[cfg(target_os = "windows")]
+fn command_line(cmd: OsCommand) -> Option<(&'static str, &'static [&'static str])> {
+    match cmd {
+        OsCommand::LockScreen => Some(("rundll32.exe", &["user32.dll,LockWorkStation"])),
+        OsCommand::Screenshot => Some(("explorer.exe", &["ms-screenclip:"])),
+        // SC_MONITORPOWER with "off" broadcast to all windows
+        OsCommand::SleepDisplay => Some((
+            "powershell.exe",
+            &[
+                "-NoProfile",
+                "-Command",
+                "(Add-Type -MemberDefinition '[DllImport(\"user32.dll\")] public static extern int PostMessage(int hWnd, int msg, int wParam, int lParam);' -Name Monitor -PassThru)::PostMessage(0xFFFF, 0x0112, 0xF170, 2)",
+            ],
+        )),
+    }
+}
+
+#[cfg(target_os = "macos")]
+fn command_line(cmd: OsCommand) -> Option<(&'static str, &'static [&'static str])> {
+    match cmd {
+        OsCommand::LockScreen => Some((
+            "osascript",
+            &[
+                "-e",
+                "tell application \"System Events\" to keystroke \"q\" using {control down, command down}",
+            ],
+        )),
+        OsCommand::Screenshot => Some(("screencapture", &["-i", "-c"])),
+        OsCommand::SleepDisplay => Some(("pmset", &["displaysleepnow"])),
+    }
+}
+
+#[cfg(not(any(target_os = "windows", target_os = "macos")))]
+fn command_line(cmd: OsCommand) -> Option<(&'static str, &'static [&'static str])> {
+    match cmd {
+        OsCommand::LockScreen => Some(("loginctl", &["lock-session"])),
+        // there is no screenshot tool every desktop ships with
+        OsCommand::Screenshot => None,
+        OsCommand::SleepDisplay => Some(("xset", &["dpms", "force", "off"])),
+    }
+}
+
+// The program that opens a file with its default app
+#[cfg(target_os = "windows")]
+const OPENER: &str = "explorer.exe";
+#[cfg(target_os = "macos")]
+const OPENER: &str = "open";
+#[cfg(not(any(target_os = "windows", target_os = "macos")))]
+const OPENER: &str = "xdg-open";
+
+// Starts the command without waiting for it to finish
+pub fn run(cmd: OsCommand) -> Result<(), io::Error> {
+    let Some((program, args)) = command_line(cmd) else {
+        return Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            format!("{:?} is not supported on this platform", cmd),
+        ));
+    };
+    info!("Running {:?}: {} {:?}", cmd, program, args);
+    spawn(Command::new(program).args(args))
+}
+
+// Starts the process and reaps it in the background once it exits
+pub fn spawn(command: &mut Command) -> Result<(), io::Error> {
+    let mut child = command.spawn()?;
+    thread::spawn(move || child.wait());
+    Ok(())
+}
+
+// Opens the file in the app the system uses for its type, e.g. the default text editor
+pub fn open_path(path: &Path) -> Result<(), io::Error> {
+    info!("Opening '{}' with {}", path.display(), OPENER);
+    spawn(Command::new(OPENER).arg(path))
+}
@@ -0,0 +1,104 @@
+use log::{error, info};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Sender};
+use std::thread;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// the log is rolled over once it grows past this size
+const MAX_SIZE: u64 = 10 * 1024 * 1024;
+// rolled over logs kept as `<path>.1` (newest) to `<path>.3`
+const KEEP: usize = 3;
+
+// Tees raw device reads to a file. Writing happens on its own thread, so recording
+// a read is just a channel send for the device loop.
+#[derive(Clone)]
+pub struct RawLog {
+    sender: Sender<(SystemTime, Vec<u8>)>,
+}
+
+impl RawLog {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, io::Error> {
+        let mut writer = RotatingWriter::open(path.as_ref().to_path_buf())?;
+        let (sender, receiver) = mpsc::channel::<(SystemTime, Vec<u8>)>();
+        thread::spawn(move || {
+            for (at, bytes) in receiver {
+                if let Err(e) = writer.write_line(at, &bytes) {
+                    error!("Could not write the raw log: {}", e);
+                    return;
+                }
+            }
+        });
+        info!("Logging raw device data to '{}'", path.as_ref().display());
+        Ok(Self { sender })
+    }
+
+    pub fn record(&self, bytes: &[u8]) {
+        // the writer thread only stops after a write error, which it already logged
+        self.sender.send((SystemTime::now(), bytes.to_vec())).ok();
+    }
+}
+
+struct RotatingWriter {
+    path: PathBuf,
+    // only `None` while rotating, Windows can't rename a file that is still open
+    file: Option<BufWriter<File>>,
+    size: u64,
+}
+
+impl RotatingWriter {
+    fn open(path: PathBuf) -> Result<Self, io::Error> {
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let size = file.metadata()?.len();
+        Ok(Self {
+            path,
+            file: Some(BufWriter::new(file)),
+            size,
+        })
+    }
+
+    // One line per read: seconds since the unix epoch, then the bytes in hex
+    fn write_line(&mut self, at: SystemTime, bytes: &[u8]) -> Result<(), io::Error> {
+        if self.size >= MAX_SIZE {
+            self.rotate()?;
+        }
+        let since_epoch = at.duration_since(UNIX_EPOCH).unwrap_or_default();
+        let mut line = format!(
+            "{}.{:03}",
+            since_epoch.as_secs(),
+            since_epoch.subsec_millis()
+        );
+        for b in bytes {
+            line.push_str(&format!(" {:02X}", b));
+        }
+        line.push('\n');
+        let file = match &mut self.file {
+            Some(file) => file,
+            None => return Err(io::Error::other("the raw log could not be reopened")),
+        };
+        file.write_all(line.as_bytes())?;
+        // flushed right away so the log is complete even if routbox crashes
+        file.flush()?;
+        self.size += line.len() as u64;
+        Ok(())
+    }
+
+    fn rotate(&mut self) -> Result<(), io::Error> {
+        let rolled = |n: usize| {
+            let mut name = self.path.clone().into_os_string();
+            name.push(format!(".{}", n));
+            PathBuf::from(name)
+        };
+        self.file = None;
+        for n in (1..KEEP).rev() {
+            if rolled(n).exists() {
+                fs::rename(rolled(n), rolled(n + 1))?;
+            }
+        }
+        fs::rename(&self.path, rolled(1))?;
+        self.file = Some(BufWriter::new(File::create(&self.path)?));
+        self.size = 0;
+        Ok(())
+    }
+}
@@ -1,18 +1,44 @@
 use log::{error, info, warn};
 use serialport::{DataBits, Parity, SerialPort, StopBits};
 use std::io::{self, Read, Write};
-use std::sync::Arc;
 use std::sync::mpsc::Sender;
-use std::thread;
 use std::time::Duration;
 
-use crate::config::Config;
-use crate::event::{self, InputEvent};
+use routbox::config::{HandshakeStep, KeyMap, SerialConfig};
+use routbox::device::{self, ByteStream, DeviceControl, HandshakePort, ReadBuffer};
+use routbox::event::{EventSource, SourcedEvent};
+
+use crate::raw_log::RawLog;
+use crate::status::SharedStatus;
+
+// The opened port and the device's response to the init command or handshake, `None` when
+// `skip_init` left it out
+type InitializedPort = (Box<dyn SerialPort>, Option<Vec<u8>>);
+
+// The port during a handshake, its short read timeout means nothing has arrived yet
+struct HandshakeSerial<'a>(&'a mut Box<dyn SerialPort>);
+
+impl HandshakePort for HandshakeSerial<'_> {
+    fn send(&mut self, bytes: &[u8]) -> io::Result<()> {
+        self.0.write_all(bytes)?;
+        self.0.flush()
+    }
+
+    fn receive(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self.0.read(buf) {
+            Err(e) if e.kind() == io::ErrorKind::TimedOut => Ok(0),
+            read => read,
+        }
+    }
+}
 
 fn initialize_serial_device(
     port_name: &str,
     baud_rate: u32,
-) -> Result<Box<dyn SerialPort>, io::Error> {
+    skip_init: bool,
+    handshake: &[HandshakeStep],
+    handshake_timeout: Duration,
+) -> Result<InitializedPort, io::Error> {
     info!(
         "Opening serial port '{}' with baud rate {}",
         port_name, baud_rate
@@ -24,8 +50,14 @@ fn initialize_serial_device(
         .timeout(Duration::from_millis(10))
         .open()
         .map_err(|e| {
+            let kind = match e.kind {
+                serialport::ErrorKind::Io(io::ErrorKind::PermissionDenied) => {
+                    io::ErrorKind::PermissionDenied
+                }
+                _ => io::ErrorKind::NotFound,
+            };
             io::Error::new(
-                io::ErrorKind::NotFound,
+                kind,
                 format!("Failed to open serial port '{}': {}", port_name, e),
             )
         })?;
@@ -34,96 +66,151 @@ fn initialize_serial_device(
     port.write_data_terminal_ready(false)?;
     port.write_request_to_send(false)?;
 
-    let init_command = [0xB5, 0x00, 0x07, 0x04, 0x00, 0x09, 0x00, 0xFE];
-    info!("Sending initialization command: {:02X?}", init_command);
-    port.write_all(&init_command)?;
-    port.flush()?;
-
-    let bytes_to_read = port.bytes_to_read().unwrap_or(0);
-    if bytes_to_read > 0 {
-        info!("Device has {} bytes to read back", bytes_to_read);
-        let mut read_buf = vec![0; bytes_to_read as usize];
-        if port.read_exact(&mut read_buf).is_ok() {
-            info!("Received data from device: {:02X?}", read_buf);
-        } else {
-            warn!("Could not read response from device");
+    let mut init_response = None;
+    if skip_init {
+        info!("Skipping the initialization command");
+    } else if !handshake.is_empty() {
+        info!("Running the handshake, {} step(s)", handshake.len());
+        let response = device::handshake(
+            handshake,
+            handshake_timeout,
+            &mut HandshakeSerial(&mut port),
+        )?;
+        init_response = Some(response);
+    } else {
+        let init_command = [0xB5, 0x00, 0x07, 0x04, 0x00, 0x09, 0x00, 0xFE];
+        info!("Sending initialization command: {:02X?}", init_command);
+        port.write_all(&init_command)?;
+        port.flush()?;
+
+        let mut response = vec![];
+        let bytes_to_read = port.bytes_to_read().unwrap_or(0);
+        if bytes_to_read > 0 {
+            info!("Device has {} bytes to read back", bytes_to_read);
+            let mut read_buf = vec![0; bytes_to_read as usize];
+            if port.read_exact(&mut read_buf).is_ok() {
+                info!("Received data from device: {:02X?}", read_buf);
+                response = read_buf;
+            } else {
+                warn!("Could not read response from device");
+            }
         }
+        init_response = Some(response);
     }
 
     info!("Clearing serial port buffers");
     port.clear(serialport::ClearBuffer::All)?;
 
     info!("Serial device initialized successfully");
-    Ok(port)
+    Ok((port, init_response))
+}
+
+// Retrying doesn't help until the user may open the port
+#[cfg(target_os = "linux")]
+const PERMISSION_HINT: &str = "The serial port can't be opened without permission. Add your user \
+     to the `dialout` group (`uucp` on Arch) with `sudo usermod -aG dialout $USER`, then log out \
+     and in again";
+#[cfg(not(target_os = "linux"))]
+const PERMISSION_HINT: &str = "The serial port can't be opened without permission, check who may \
+     access it or whether another program has it open";
+
+#[derive(Clone)]
+pub struct SerialSource {
+    cfg: SerialConfig,
+    key_map: KeyMap,
+    status: SharedStatus,
+    raw_log: Option<RawLog>,
+    control: DeviceControl,
+}
+
+impl SerialSource {
+    pub fn new(cfg: SerialConfig, key_map: KeyMap, status: SharedStatus) -> Self {
+        Self {
+            cfg,
+            key_map,
+            status,
+            raw_log: None,
+            control: DeviceControl::default(),
+        }
+    }
+
+    // Also writes every read to the raw log
+    pub fn with_raw_log(mut self, raw_log: Option<RawLog>) -> Self {
+        self.raw_log = raw_log;
+        self
+    }
+
+    // Reinit requests through `control` reconnect to the device
+    pub fn with_control(mut self, control: DeviceControl) -> Self {
+        self.control = control;
+        self
+    }
+}
+
+// An open port, recording every read for the diagnostics and the raw log
+struct SerialStream {
+    port: Box<dyn SerialPort>,
+    buffer: ReadBuffer,
+    status: SharedStatus,
+    raw_log: Option<RawLog>,
 }
 
-pub fn serial_tourbox_processor(cfg: Arc<Config>, ev_sender: Sender<InputEvent>) {
-    thread::spawn(move || {
-        if let crate::config::TourBoxDevice::Serial {
-            ref serial_port,
-            ref baud_rate,
-        } = cfg.device
-        {
-            info!(
-                "Serial thread started for port '{}' at {} baud",
-                serial_port, baud_rate
-            );
-            loop {
-                let mut port = loop {
-                    match initialize_serial_device(&serial_port, *baud_rate) {
-                        Ok(p) => break p,
-                        Err(e) => {
-                            warn!(
-                                "Could not initialize serial device: {}. Retrying in 5 seconds...",
-                                e
-                            );
-                            thread::sleep(Duration::from_secs(5));
-                        }
-                    }
-                };
-                let mut byte_buf = [0; 1];
-                loop {
-                    match port.read(&mut byte_buf) {
-                        Ok(count) => {
-                            if count > 0 {
-                                let key_code = byte_buf[0];
-                                let key_code_hex = format!("0x{:02x}", key_code);
-
-                                let ev = if let Some(key_name) =
-                                    cfg.key_map.stateless.get(&key_code_hex)
-                                {
-                                    event::InputEvent::KeyPressed(key_name.clone())
-                                } else if let Some(key_name) =
-                                    cfg.key_map.stateful.get(&key_code_hex)
-                                {
-                                    event::InputEvent::KeyPressed(key_name.clone())
-                                } else if let Some(key_name) = cfg
-                                    .key_map
-                                    .stateful
-                                    .get(&format!("0x{:02x}", key_code - 0x80))
-                                {
-                                    event::InputEvent::KeyReleased(key_name.clone())
-                                } else {
-                                    warn!("Unknown key code {key_code_hex}.");
-                                    continue;
-                                };
-
-                                if ev_sender.send(ev).is_err() {
-                                    warn!("UI thread has been closed. Exiting serial thread.");
-                                    return;
-                                }
-                            }
-                        }
-                        Err(ref e) if e.kind() == std::io::ErrorKind::TimedOut => (),
-                        Err(e) => {
-                            error!("Serial port error: {}", e);
-                            break;
-                        }
-                    }
+impl ByteStream for SerialStream {
+    fn read_byte(&mut self) -> io::Result<Option<u8>> {
+        self.buffer.next_byte(|buf| {
+            let count = match self.port.read(buf) {
+                Ok(count) => count,
+                Err(e) if e.kind() == io::ErrorKind::TimedOut => 0,
+                Err(e) => return Err(e),
+            };
+            if count > 0 {
+                self.status.lock().unwrap().record_frame(&buf[..count]);
+                if let Some(raw_log) = &self.raw_log {
+                    raw_log.record(&buf[..count]);
                 }
             }
-        } else {
-            panic!("Invalid state");
-        }
-    });
+            Ok(count)
+        })
+    }
+}
+
+impl EventSource for SerialSource {
+    fn run(self, ev_sender: Sender<SourcedEvent>) {
+        info!(
+            "Serial thread started for port '{}' at {} baud",
+            self.cfg.serial_port, self.cfg.baud_rate
+        );
+        let mut hinted = false;
+        let connect = || {
+            let (port, init_response) = initialize_serial_device(
+                &self.cfg.serial_port,
+                self.cfg.baud_rate,
+                self.cfg.skip_init,
+                &self.cfg.handshake,
+                Duration::from_millis(self.cfg.handshake_timeout_ms),
+            )
+            .inspect_err(|e| {
+                if e.kind() == io::ErrorKind::PermissionDenied && !hinted {
+                    hinted = true;
+                    error!("{}", PERMISSION_HINT);
+                }
+            })?;
+            self.status.lock().unwrap().initialized(init_response);
+            Ok(SerialStream {
+                port,
+                buffer: ReadBuffer::default(),
+                status: self.status.clone(),
+                raw_log: self.raw_log.clone(),
+            })
+        };
+        device::run(
+            "serial",
+            self.cfg.prefix.as_deref(),
+            connect,
+            &self.key_map,
+            &ev_sender,
+            Duration::from_secs(5),
+            &self.control,
+        );
+    }
 }
@@ -0,0 +1,69 @@
+use log::warn;
+use rodio::source::{SineWave, Source};
+use rodio::{Decoder, OutputStream};
+use std::fs::File;
+use std::io::BufReader;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread;
+use std::time::Duration;
+
+// how long and how loud the built-in beep plays
+const BEEP_DURATION: Duration = Duration::from_millis(120);
+const BEEP_VOLUME: f32 = 0.2;
+
+// The `feedback` sounds of mappings, played by a thread of its own that is started on first
+// use, so opening the audio device or reading a file never holds up the keys
+#[derive(Debug, Default)]
+pub struct Sounds {
+    sender: Option<Sender<Option<String>>>,
+}
+
+impl Sounds {
+    // A sound file, or the beep for `None`. Returns right away, the sound plays meanwhile.
+    pub fn play(&mut self, sound: Option<&str>) {
+        let sender = self.sender.get_or_insert_with(|| {
+            let (sender, receiver) = mpsc::channel();
+            thread::spawn(move || play_all(receiver));
+            sender
+        });
+        // a closed channel means there is no audio device, which was logged already
+        sender.send(sound.map(str::to_owned)).ok();
+    }
+}
+
+fn play_all(receiver: Receiver<Option<String>>) {
+    // the stream has to stay alive while anything plays, it stops playing when dropped
+    let (_stream, handle) = match OutputStream::try_default() {
+        Ok(output) => output,
+        Err(e) => {
+            warn!("No audio output for feedback sounds: {}", e);
+            return;
+        }
+    };
+    for sound in receiver {
+        // sounds overlap instead of waiting for each other
+        let played = match &sound {
+            None => handle
+                .play_raw(
+                    SineWave::new(880.0)
+                        .take_duration(BEEP_DURATION)
+                        .amplify(BEEP_VOLUME),
+                )
+                .map_err(|e| e.to_string()),
+            Some(path) => File::open(path)
+                .map_err(|e| e.to_string())
+                .and_then(|file| Decoder::new(BufReader::new(file)).map_err(|e| e.to_string()))
+                .and_then(|source| {
+                    handle
+                        .play_raw(source.convert_samples())
+                        .map_err(|e| e.to_string())
+                }),
+        };
+        if let Err(e) = played {
+            match sound {
+                Some(path) => warn!("Could not play '{}': {}", path, e),
+                None => warn!("Could not play the feedback beep: {}", e),
+            }
+        }
+    }
+}
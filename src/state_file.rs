@@ -0,0 +1,22 @@
+use std::fs::File;
+use std::io;
+use std::path::Path;
+
+use routbox::key_processor::ProcessorState;
+
+// The state saved by `save`, `None` if nothing was saved yet
+pub fn load(path: &Path) -> Result<Option<ProcessorState>, io::Error> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    serde_json::from_reader(io::BufReader::new(file))
+        .map(Some)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+pub fn save(path: &Path, state: &ProcessorState) -> Result<(), io::Error> {
+    let writer = io::BufWriter::new(File::create(path)?);
+    serde_json::to_writer_pretty(writer, state).map_err(io::Error::from)
+}
@@ -0,0 +1,115 @@
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use routbox::event::InputEvent;
+
+// How often each TourBox key has been pressed
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct KeyStats {
+    counts: HashMap<String, u64>,
+    // counts changed since they were loaded or last saved
+    #[serde(skip)]
+    changed: bool,
+}
+
+// how often running stats are saved, a process that is killed loses at most this much
+const SAVE_INTERVAL: Duration = Duration::from_secs(60);
+
+pub type SharedStats = Arc<Mutex<KeyStats>>;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StatsOrder {
+    ByKey,
+    ByCount,
+}
+
+impl KeyStats {
+    pub fn record(&mut self, ev: &InputEvent) {
+        if let InputEvent::KeyPressed(k) = ev {
+            *self.counts.entry(k.clone()).or_default() += 1;
+            self.changed = true;
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.counts.clear();
+        self.changed = true;
+    }
+
+    pub fn sorted(&self, order: StatsOrder) -> Vec<(&str, u64)> {
+        let mut counts: Vec<_> = self.counts.iter().map(|(k, c)| (k.as_str(), *c)).collect();
+        match order {
+            StatsOrder::ByKey => counts.sort(),
+            StatsOrder::ByCount => counts.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0))),
+        }
+        counts
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, io::Error> {
+        let reader = io::BufReader::new(File::open(path)?);
+        serde_json::from_reader(reader).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    // Written next to the file first and moved over it, so a process killed while
+    // saving keeps the old counts
+    pub fn save<P: AsRef<Path>>(&mut self, path: P) -> Result<(), io::Error> {
+        let path = path.as_ref();
+        let temp = path.with_extension("tmp");
+        let mut writer = io::BufWriter::new(File::create(&temp)?);
+        serde_json::to_writer_pretty(&mut writer, self).map_err(io::Error::from)?;
+        writer.flush()?;
+        fs::rename(&temp, path)?;
+        self.changed = false;
+        Ok(())
+    }
+
+    // Saves the counts if they changed since they were loaded or last saved
+    pub fn save_if_changed<P: AsRef<Path>>(&mut self, path: P) -> Result<bool, io::Error> {
+        if !self.changed {
+            return Ok(false);
+        }
+        self.save(path)?;
+        Ok(true)
+    }
+}
+
+// Saves the stats every `SAVE_INTERVAL` while the process runs, so they survive it
+// being killed, e.g. with Ctrl+C when running headless
+pub fn save_periodically(stats: SharedStats, path: String) {
+    thread::spawn(move || {
+        loop {
+            thread::sleep(SAVE_INTERVAL);
+            if let Err(e) = stats.lock().unwrap().save_if_changed(&path) {
+                warn!("Could not save key stats to '{}': {}", path, e);
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_changed_counts_are_saved() {
+        let path = std::env::temp_dir().join(format!("routbox-stats-{}.json", std::process::id()));
+        let mut stats = KeyStats::default();
+        assert!(!stats.save_if_changed(&path).unwrap());
+
+        stats.record(&InputEvent::KeyPressed("TOP".to_string()));
+        assert!(stats.save_if_changed(&path).unwrap());
+        assert!(!stats.save_if_changed(&path).unwrap());
+
+        let loaded = KeyStats::load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(loaded.sorted(StatsOrder::ByKey), vec![("TOP", 1)]);
+        assert!(!loaded.changed);
+    }
+}
@@ -0,0 +1,62 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::io;
+use std::sync::{Arc, Mutex};
+
+// What the device threads know about the connection, shown in the UI
+#[derive(Debug, Default)]
+pub struct DeviceStatus {
+    // response to the init command, if the transport reads one back
+    pub init_response: Option<Vec<u8>>,
+    // the first raw reads after the device was initialized
+    pub raw_frames: Vec<Vec<u8>>,
+    // how often each background thread panicked and had to be restarted
+    pub panics: BTreeMap<String, u32>,
+    // background threads that could not be started, with the reason
+    pub failed_threads: BTreeMap<String, String>,
+}
+
+pub type SharedStatus = Arc<Mutex<DeviceStatus>>;
+
+impl DeviceStatus {
+    const MAX_RAW_FRAMES: usize = 16;
+
+    pub fn initialized(&mut self, init_response: Option<Vec<u8>>) {
+        self.init_response = init_response;
+        self.raw_frames.clear();
+    }
+
+    pub fn record_frame(&mut self, frame: &[u8]) {
+        if self.raw_frames.len() < Self::MAX_RAW_FRAMES {
+            self.raw_frames.push(frame.to_vec());
+        }
+    }
+
+    pub fn thread_panicked(&mut self, thread: &str) {
+        *self.panics.entry(thread.to_string()).or_default() += 1;
+    }
+
+    pub fn thread_failed(&mut self, thread: &str, error: &io::Error) {
+        self.failed_threads
+            .insert(thread.to_string(), error.to_string());
+    }
+
+    // Plain text report of the raw device data, meant to be pasted into an issue
+    pub fn diagnostics(&self) -> String {
+        let mut report = String::new();
+        match &self.init_response {
+            Some(bytes) => writeln!(report, "init response: {:02X?}", bytes).unwrap(),
+            None => writeln!(report, "init response: (not read)").unwrap(),
+        }
+        for (i, frame) in self.raw_frames.iter().enumerate() {
+            writeln!(report, "frame {}: {:02X?}", i + 1, frame).unwrap();
+        }
+        for (thread, count) in &self.panics {
+            writeln!(report, "{} thread panics: {}", thread, count).unwrap();
+        }
+        for (thread, error) in &self.failed_threads {
+            writeln!(report, "{} thread failed to start: {}", thread, error).unwrap();
+        }
+        report
+    }
+}
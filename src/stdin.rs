@@ -0,0 +1,120 @@
+use log::{error, info, warn};
+use std::io::{self, BufRead};
+use std::sync::mpsc::Sender;
+
+use routbox::event::{EventSource, InputEvent, SourcedEvent};
+
+// Reads logical key events from stdin, one per line: `press KNOB_BTN`, `release KNOB_BTN`.
+// Blank lines and lines starting with `#` are ignored. With a `prefix`, keys may be written
+// with it, like `press left.SIDE`.
+#[derive(Clone)]
+pub struct StdinSource {
+    pub prefix: Option<String>,
+}
+
+fn parse_line(line: &str, prefix: Option<&str>) -> Result<Option<InputEvent>, String> {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') {
+        return Ok(None);
+    }
+
+    let mut words = line.split_whitespace();
+    let bare = |key: &str| {
+        prefix
+            .and_then(|prefix| key.strip_prefix(prefix))
+            .unwrap_or(key)
+            .to_owned()
+    };
+    match (words.next(), words.next().map(bare), words.next()) {
+        (Some(verb), Some(key), None) if verb.eq_ignore_ascii_case("press") => {
+            Ok(Some(InputEvent::KeyPressed(key)))
+        }
+        (Some(verb), Some(key), None) if verb.eq_ignore_ascii_case("release") => {
+            Ok(Some(InputEvent::KeyReleased(key)))
+        }
+        _ => Err(format!(
+            "expected `press <KEY>` or `release <KEY>`, got `{line}`"
+        )),
+    }
+}
+
+impl EventSource for StdinSource {
+    fn run(self, ev_sender: Sender<SourcedEvent>) {
+        info!("Stdin thread started");
+        let source = self.prefix.as_deref();
+        let send = |ev| ev_sender.send(SourcedEvent::new(ev, source));
+        if send(InputEvent::Connected).is_err() {
+            return;
+        }
+        for line in io::stdin().lock().lines() {
+            let line = match line {
+                Ok(line) => line,
+                Err(e) => {
+                    error!("Could not read from stdin: {}", e);
+                    return;
+                }
+            };
+
+            let ev = match parse_line(&line, source) {
+                Ok(Some(ev)) => ev,
+                Ok(None) => continue,
+                Err(e) => {
+                    warn!("Ignoring stdin line: {e}");
+                    continue;
+                }
+            };
+
+            if send(ev).is_err() {
+                warn!("UI thread has been closed. Exiting stdin thread.");
+                return;
+            }
+        }
+        info!("Stdin closed. Exiting stdin thread.");
+        send(InputEvent::Disconnected).ok();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lines_turn_into_presses_and_releases() {
+        assert_eq!(
+            parse_line("press SIDE", None),
+            Ok(Some(InputEvent::KeyPressed("SIDE".into())))
+        );
+        assert_eq!(
+            parse_line("  RELEASE   KNOB_BTN ", None),
+            Ok(Some(InputEvent::KeyReleased("KNOB_BTN".into())))
+        );
+        // a dial tick is the press of a stateless key
+        assert_eq!(
+            parse_line("press KNOB_CW", None),
+            Ok(Some(InputEvent::KeyPressed("KNOB_CW".into())))
+        );
+        // the device's own prefix may be written or left out
+        assert_eq!(
+            parse_line("press left.SIDE", Some("left.")),
+            Ok(Some(InputEvent::KeyPressed("SIDE".into())))
+        );
+        assert_eq!(
+            parse_line("release SIDE", Some("left.")),
+            Ok(Some(InputEvent::KeyReleased("SIDE".into())))
+        );
+    }
+
+    #[test]
+    fn blank_and_comment_lines_are_skipped() {
+        assert_eq!(parse_line("", None), Ok(None));
+        assert_eq!(parse_line("   \t", None), Ok(None));
+        assert_eq!(parse_line("# press SIDE", None), Ok(None));
+    }
+
+    #[test]
+    fn malformed_lines_are_errors() {
+        for line in ["press", "tap SIDE", "press SIDE TOP", "SIDE"] {
+            assert!(parse_line(line, None).is_err(), "`{}` parsed", line);
+        }
+    }
+}
@@ -0,0 +1,120 @@
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Style, Stylize};
+use ratatui::text::Line;
+use ratatui::widgets::{Block, List, ListItem, Paragraph};
+use ratatui::{DefaultTerminal, Frame};
+use std::io;
+use std::sync::mpsc::{Receiver, TryRecvError};
+use std::time::Duration;
+
+use crate::app::{LogEntry, UiEvent, View, describe};
+use crate::status::{DeviceStatus, SharedStatus};
+
+// Shows the same as the window, in the terminal, until `q`, Esc or Ctrl+C
+pub fn run(receiver: Receiver<UiEvent>, status: SharedStatus, view: View) -> io::Result<()> {
+    let mut terminal = ratatui::try_init()?;
+    let result = event_loop(&mut terminal, &receiver, &status, view);
+    ratatui::try_restore()?;
+    result
+}
+
+fn event_loop(
+    terminal: &mut DefaultTerminal,
+    receiver: &Receiver<UiEvent>,
+    status: &SharedStatus,
+    mut view: View,
+) -> io::Result<()> {
+    loop {
+        loop {
+            match receiver.try_recv() {
+                Ok(event) => view.apply(event),
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => return Ok(()),
+            }
+        }
+        terminal.draw(|frame| draw(frame, &view, &status.lock().unwrap()))?;
+
+        // new actions show up within a tenth of a second
+        if event::poll(Duration::from_millis(100))?
+            && let Event::Key(key) = event::read()?
+            && key.kind == KeyEventKind::Press
+        {
+            let ctrl_c =
+                key.code == KeyCode::Char('c') && key.modifiers.contains(KeyModifiers::CONTROL);
+            if ctrl_c || matches!(key.code, KeyCode::Char('q') | KeyCode::Esc) {
+                return Ok(());
+            }
+        }
+    }
+}
+
+fn draw(frame: &mut Frame, view: &View, status: &DeviceStatus) {
+    let keys = |keys: Vec<&str>| match keys.is_empty() {
+        true => "nothing".to_string(),
+        false => keys.join(", "),
+    };
+    let device = match view.connected {
+        Some(true) => "connected",
+        Some(false) => "disconnected",
+        None => "waiting",
+    };
+    let mut header = vec![
+        Line::from(format!("Device: {}   Profile: {}", device, view.profile())),
+        Line::from(format!(
+            "Held: {}",
+            keys(view.pressed_keys.iter().map(|k| view.ui.label(k)).collect())
+        )),
+        Line::from(format!(
+            "Locked: {}",
+            keys(view.locked_keys.iter().map(|k| view.ui.label(k)).collect())
+        )),
+    ];
+    if let Some(wireless) = view.wireless() {
+        header.push(Line::from(wireless));
+    }
+    for (thread, error) in &status.failed_threads {
+        header.push(Line::styled(
+            format!("⚠ The {} thread failed to start: {}", thread, error),
+            Style::new().red(),
+        ));
+    }
+    for (thread, count) in &status.panics {
+        header.push(Line::styled(
+            format!(
+                "⚠ The {} thread crashed {} time(s) and was restarted, see the log",
+                thread, count
+            ),
+            Style::new().red(),
+        ));
+    }
+
+    let [header_area, log_area] = Layout::vertical([
+        Constraint::Length(header.len() as u16 + 2),
+        Constraint::Min(0),
+    ])
+    .areas(frame.area());
+    frame.render_widget(
+        Paragraph::new(header).block(Block::bordered().title(" routbox, q to quit ")),
+        header_area,
+    );
+
+    // the newest entries at the bottom, like the window's log sticks to the bottom
+    let visible = log_area.height.saturating_sub(2) as usize;
+    let start = view.active_keys.len().saturating_sub(visible);
+    let items: Vec<ListItem> = view.active_keys[start..]
+        .iter()
+        .map(|entry| match entry {
+            LogEntry::Action(a) => {
+                let (icon, text) = describe(a, &view.ui);
+                ListItem::new(format!("{} {}", icon, text))
+            }
+            LogEntry::Failed(message) => ListItem::new(format!("⚠ failed: {}", message).red()),
+            LogEntry::Profile(name) => ListItem::new(format!("── Profile: {} ──", name).bold()),
+        })
+        .collect();
+    frame.render_widget(
+        List::new(items).block(Block::bordered().title(" Active Keys ")),
+        log_area,
+    );
+}
@@ -0,0 +1,250 @@
+use log::info;
+use std::io;
+
+use routbox::action::WindowOp;
+
+// Only the macOS and Linux backends lack some operations
+#[cfg(not(target_os = "windows"))]
+fn unsupported(op: WindowOp) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::Unsupported,
+        format!("{:?} is not supported on this platform", op),
+    )
+}
+
+// Applies the operation to the foreground window
+pub fn run(op: WindowOp) -> Result<(), io::Error> {
+    info!("Window action {:?}", op);
+    platform::run(op)
+}
+
+// Win32 calls on the foreground window, sizes follow the work area of its monitor
+#[cfg(target_os = "windows")]
+mod platform {
+    use std::ffi::c_void;
+    use std::io;
+    use std::ptr;
+
+    use routbox::action::WindowOp;
+
+    type Handle = *mut c_void;
+
+    #[repr(C)]
+    #[derive(Default, Clone, Copy)]
+    struct Rect {
+        left: i32,
+        top: i32,
+        right: i32,
+        bottom: i32,
+    }
+
+    #[repr(C)]
+    #[derive(Default)]
+    struct MonitorInfo {
+        size: u32,
+        monitor: Rect,
+        work: Rect,
+        flags: u32,
+    }
+
+    const MONITOR_DEFAULTTONEAREST: u32 = 2;
+    const SW_MAXIMIZE: i32 = 3;
+    const SW_MINIMIZE: i32 = 6;
+    const SW_RESTORE: i32 = 9;
+    const SWP_NOZORDER: u32 = 0x0004;
+    const SWP_NOACTIVATE: u32 = 0x0010;
+
+    type MonitorEnumProc = unsafe extern "system" fn(Handle, Handle, *mut Rect, isize) -> i32;
+
+    #[link(name = "user32")]
+    unsafe extern "system" {
+        fn GetForegroundWindow() -> Handle;
+        fn GetWindowRect(window: Handle, rect: *mut Rect) -> i32;
+        fn IsZoomed(window: Handle) -> i32;
+        fn ShowWindow(window: Handle, cmd: i32) -> i32;
+        fn SetWindowPos(
+            window: Handle,
+            insert_after: Handle,
+            x: i32,
+            y: i32,
+            width: i32,
+            height: i32,
+            flags: u32,
+        ) -> i32;
+        fn MonitorFromWindow(window: Handle, flags: u32) -> Handle;
+        fn GetMonitorInfoW(monitor: Handle, info: *mut MonitorInfo) -> i32;
+        fn EnumDisplayMonitors(
+            dc: Handle,
+            clip: *const Rect,
+            callback: MonitorEnumProc,
+            data: isize,
+        ) -> i32;
+    }
+
+    unsafe extern "system" fn collect_monitor(
+        monitor: Handle,
+        _dc: Handle,
+        _rect: *mut Rect,
+        data: isize,
+    ) -> i32 {
+        // SAFETY: `data` is the `Vec` passed to `EnumDisplayMonitors` in `monitors`
+        unsafe { (*(data as *mut Vec<Handle>)).push(monitor) };
+        1
+    }
+
+    fn monitors() -> Vec<Handle> {
+        let mut monitors: Vec<Handle> = vec![];
+        // SAFETY: the callback only pushes to `monitors`, which outlives the call
+        unsafe {
+            EnumDisplayMonitors(
+                ptr::null_mut(),
+                ptr::null(),
+                collect_monitor,
+                &mut monitors as *mut Vec<Handle> as isize,
+            );
+        }
+        monitors
+    }
+
+    fn work_area(monitor: Handle) -> io::Result<Rect> {
+        let mut info = MonitorInfo {
+            size: size_of::<MonitorInfo>() as u32,
+            ..Default::default()
+        };
+        // SAFETY: `info` is a valid MONITORINFO with its size set
+        if unsafe { GetMonitorInfoW(monitor, &mut info) } == 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(info.work)
+    }
+
+    // SAFETY: `window` has to be a window handle
+    unsafe fn place(window: Handle, x: i32, y: i32, width: i32, height: i32) -> io::Result<()> {
+        let flags = SWP_NOZORDER | SWP_NOACTIVATE;
+        if unsafe { SetWindowPos(window, ptr::null_mut(), x, y, width, height, flags) } == 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    pub fn run(op: WindowOp) -> io::Result<()> {
+        // SAFETY: plain Win32 calls on the foreground window, checked for null first
+        unsafe {
+            let window = GetForegroundWindow();
+            if window.is_null() {
+                return Err(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    "there is no foreground window",
+                ));
+            }
+            let monitor = MonitorFromWindow(window, MONITOR_DEFAULTTONEAREST);
+            let work = work_area(monitor)?;
+            let half = (work.right - work.left) / 2;
+            match op {
+                WindowOp::Maximize => {
+                    ShowWindow(window, SW_MAXIMIZE);
+                }
+                WindowOp::Minimize => {
+                    ShowWindow(window, SW_MINIMIZE);
+                }
+                WindowOp::SnapLeft | WindowOp::SnapRight => {
+                    // a maximized window ignores the new position
+                    ShowWindow(window, SW_RESTORE);
+                    let x = match op {
+                        WindowOp::SnapLeft => work.left,
+                        _ => work.left + half,
+                    };
+                    place(window, x, work.top, half, work.bottom - work.top)?;
+                }
+                WindowOp::NextMonitor => {
+                    let monitors = monitors();
+                    let Some(current) = monitors.iter().position(|m| *m == monitor) else {
+                        return Ok(());
+                    };
+                    let next = work_area(monitors[(current + 1) % monitors.len()])?;
+                    let maximized = IsZoomed(window) != 0;
+                    if maximized {
+                        ShowWindow(window, SW_RESTORE);
+                    }
+                    let mut rect = Rect::default();
+                    if GetWindowRect(window, &mut rect) == 0 {
+                        return Err(io::Error::last_os_error());
+                    }
+                    // same offset from the top left corner of the work area
+                    place(
+                        window,
+                        next.left + rect.left - work.left,
+                        next.top + rect.top - work.top,
+                        rect.right - rect.left,
+                        rect.bottom - rect.top,
+                    )?;
+                    if maximized {
+                        ShowWindow(window, SW_MAXIMIZE);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+// System Events scripting, needs the accessibility permission for routbox
+#[cfg(target_os = "macos")]
+mod platform {
+    use std::io;
+    use std::process::Command;
+
+    use routbox::action::WindowOp;
+
+    use crate::os_actions;
+
+    // Moves the front window, the expressions can use the desktop bounds `l`, `t`, `r`, `b`
+    fn place(x: &str, y: &str, width: &str, height: &str) -> String {
+        format!(
+            "tell application \"Finder\" to set {{l, t, r, b}} to bounds of window of desktop
+set t to t + 25 -- the menu bar
+tell application \"System Events\" to tell (first process whose frontmost is true) to tell front window
+    set position to {{{x}, {y}}}
+    set size to {{{width}, {height}}}
+end tell"
+        )
+    }
+
+    pub fn run(op: WindowOp) -> io::Result<()> {
+        let script = match op {
+            WindowOp::SnapLeft => place("l", "t", "(r - l) div 2", "b - t"),
+            WindowOp::SnapRight => place("(l + r) div 2", "t", "(r - l) div 2", "b - t"),
+            WindowOp::Maximize => place("l", "t", "r - l", "b - t"),
+            WindowOp::Minimize => "tell application \"System Events\" to tell (first process whose frontmost is true) \
+                 to set value of attribute \"AXMinimized\" of front window to true"
+                .to_string(),
+            WindowOp::NextMonitor => return Err(super::unsupported(op)),
+        };
+        os_actions::spawn(Command::new("osascript").args(["-e", &script]))
+    }
+}
+
+// xdotool on the active window, sizes follow the whole display
+#[cfg(not(any(target_os = "windows", target_os = "macos")))]
+mod platform {
+    use std::io;
+    use std::process::Command;
+
+    use routbox::action::WindowOp;
+
+    use crate::os_actions;
+
+    pub fn run(op: WindowOp) -> io::Result<()> {
+        let script = match op {
+            WindowOp::SnapLeft => "xdotool getactivewindow windowsize 50% 100% windowmove 0 0",
+            WindowOp::SnapRight => {
+                "w=$(xdotool getdisplaygeometry | cut -d' ' -f1); \
+                 xdotool getactivewindow windowsize 50% 100% windowmove $((w / 2)) 0"
+            }
+            WindowOp::Maximize => "xdotool getactivewindow windowsize 100% 100% windowmove 0 0",
+            WindowOp::Minimize => "xdotool getactivewindow windowminimize",
+            WindowOp::NextMonitor => return Err(super::unsupported(op)),
+        };
+        os_actions::spawn(Command::new("sh").args(["-c", script]))
+    }
+}
@@ -1,13 +1,16 @@
-use log::{error, info, warn};
+use log::{debug, info};
 use rusb::{Context, Device, DeviceDescriptor, DeviceHandle, Direction, TransferType, UsbContext};
 use std::io;
-use std::sync::Arc;
 use std::sync::mpsc::Sender;
 use std::thread;
 use std::time::Duration;
 
-use crate::config::{self, Config};
-use crate::event::{self, InputEvent};
+use routbox::config::{HandshakeStep, KeyMap, WinUsbConfig};
+use routbox::device::{self, ByteStream, DeviceControl, HandshakePort, ReadBuffer};
+use routbox::event::{EventSource, SourcedEvent};
+
+use crate::raw_log::RawLog;
+use crate::status::SharedStatus;
 
 struct Endpoints {
     in_address: u8,
@@ -19,19 +22,21 @@ fn find_device_and_endpoints<T: UsbContext>(
     context: &mut T,
     vid: u16,
     pid: u16,
+    config_index: u8,
+    interface_number: u8,
 ) -> Result<(Device<T>, DeviceDescriptor, Endpoints), rusb::Error> {
     for device in context.devices()?.iter() {
         let device_desc = device.device_descriptor()?;
         if device_desc.vendor_id() == vid && device_desc.product_id() == pid {
             info!("Found device with VID={:04x}, PID={:04x}", vid, pid);
-            let config_desc = device.config_descriptor(0)?; // Assuming first configuration
+            let config_desc = device.config_descriptor(config_index)?;
 
             let mut in_address = None;
             let mut out_address = None;
 
             for interface in config_desc.interfaces() {
                 for interface_desc in interface.descriptors() {
-                    if interface_desc.interface_number() == 1 {
+                    if interface_desc.interface_number() == interface_number {
                         for endpoint_desc in interface_desc.endpoint_descriptors() {
                             if endpoint_desc.transfer_type() == TransferType::Bulk {
                                 if endpoint_desc.direction() == Direction::In {
@@ -64,18 +69,63 @@ fn find_device_and_endpoints<T: UsbContext>(
     Err(rusb::Error::NoDevice)
 }
 
+// The claimed interface during a handshake, reads time out after `read_timeout`
+struct HandshakeUsb<'a> {
+    handle: &'a DeviceHandle<Context>,
+    endpoints: &'a Endpoints,
+    read_timeout: Duration,
+}
+
+impl HandshakePort for HandshakeUsb<'_> {
+    fn send(&mut self, bytes: &[u8]) -> io::Result<()> {
+        self.handle
+            .write_bulk(self.endpoints.out_address, bytes, Duration::from_secs(1))
+            .map(|_| ())
+            .map_err(io::Error::other)
+    }
+
+    fn receive(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self
+            .handle
+            .read_bulk(self.endpoints.in_address, buf, self.read_timeout)
+        {
+            Ok(count) => Ok(count),
+            Err(rusb::Error::Timeout) => Ok(0),
+            Err(e) => Err(io::Error::other(e)),
+        }
+    }
+}
+
+// How the device is initialized once its interface is claimed
+struct Init<'a> {
+    retries: u32,
+    retry_delay: Duration,
+    skip: bool,
+    handshake: &'a [HandshakeStep],
+    handshake_timeout: Duration,
+    read_timeout: Duration,
+}
+
+// The claimed device and what its handshake read, `None` without a handshake
+type InitializedDevice = (DeviceHandle<Context>, Endpoints, Option<Vec<u8>>);
+
 fn initialize_winusb_device(
     vid: u16,
     pid: u16,
-) -> Result<(DeviceHandle<Context>, Endpoints), io::Error> {
+    config_index: u8,
+    interface: u8,
+    init: &Init,
+) -> Result<InitializedDevice, io::Error> {
     let mut context = Context::new().map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
     let (device, _, endpoints) =
-        find_device_and_endpoints(&mut context, vid, pid).map_err(|e| {
-            io::Error::new(
-                io::ErrorKind::NotFound,
-                format!("Failed to find USB device {:04x}:{:04x}: {}", vid, pid, e),
-            )
-        })?;
+        find_device_and_endpoints(&mut context, vid, pid, config_index, interface).map_err(
+            |e| {
+                io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("Failed to find USB device {:04x}:{:04x}: {}", vid, pid, e),
+                )
+            },
+        )?;
 
     let handle = device.open().map_err(|e| {
         io::Error::new(
@@ -87,9 +137,9 @@ fn initialize_winusb_device(
     // The python script does device.set_configuration() which rusb does automatically on open.
     // We may need to detach kernel driver if necessary, especially on Linux.
     // On Windows, this is often not needed if the correct driver (e.g., WinUSB) is installed.
-    if handle.kernel_driver_active(1).unwrap_or(false) {
-        info!("Detaching kernel driver from interface 1");
-        handle.detach_kernel_driver(1).map_err(|e| {
+    if handle.kernel_driver_active(interface).unwrap_or(false) {
+        info!("Detaching kernel driver from interface {}", interface);
+        handle.detach_kernel_driver(interface).map_err(|e| {
             io::Error::new(
                 io::ErrorKind::Other,
                 format!("Could not detach kernel driver: {}", e),
@@ -97,104 +147,189 @@ fn initialize_winusb_device(
         })?;
     }
 
-    info!("Claiming interface 1");
-    handle.claim_interface(1).map_err(|e| {
+    info!("Claiming interface {}", interface);
+    handle.claim_interface(interface).map_err(|e| {
         io::Error::new(
             io::ErrorKind::Other,
-            format!("Could not claim interface 1: {}", e),
+            format!("Could not claim interface {}: {}", interface, e),
         )
     })?;
 
-    let init_command = [0xB5, 0x00, 0x07, 0x04, 0x00, 0x09, 0x00, 0xFE];
-    info!("Sending initialization command: {:02X?}", init_command);
-    handle
-        .write_bulk(endpoints.out_address, &init_command, Duration::from_secs(1))
-        .map_err(|e| {
-            io::Error::new(
-                io::ErrorKind::Other,
-                format!("Could not send init command: {}", e),
-            )
-        })?;
+    let mut response = None;
+    if init.skip {
+        info!("Skipping the initialization command");
+    } else if !init.handshake.is_empty() {
+        info!("Running the handshake, {} step(s)", init.handshake.len());
+        let mut port = HandshakeUsb {
+            handle: &handle,
+            endpoints: &endpoints,
+            read_timeout: init.read_timeout,
+        };
+        response = Some(device::handshake(
+            init.handshake,
+            init.handshake_timeout,
+            &mut port,
+        )?);
+    } else {
+        let init_command = [0xB5, 0x00, 0x07, 0x04, 0x00, 0x09, 0x00, 0xFE];
+        info!("Sending initialization command: {:02X?}", init_command);
+        // some devices reject the first init command but accept the next one
+        let mut attempt = 0;
+        while let Err(e) =
+            handle.write_bulk(endpoints.out_address, &init_command, Duration::from_secs(1))
+        {
+            if attempt == init.retries {
+                return Err(io::Error::new(
+                    io::ErrorKind::Other,
+                    format!("Could not send init command: {}", e),
+                ));
+            }
+            attempt += 1;
+            debug!(
+                "Init command failed: {}, retry {} of {} in {} ms",
+                e,
+                attempt,
+                init.retries,
+                init.retry_delay.as_millis()
+            );
+            thread::sleep(init.retry_delay);
+        }
+    }
 
     info!("WinUSB device initialized successfully");
-    Ok((handle, endpoints))
+    Ok((handle, endpoints, response))
 }
 
-pub fn winusb_tourbox_processor(cfg: Arc<Config>, ev_sender: Sender<InputEvent>) {
-    // These should come from config, but for now, let's use the example values from the python script.
-    // The user's TODO list indicates they will update the config later.
-
-    thread::spawn(move || {
-        if let config::TourBoxDevice::WinUsb { vid, pid } = cfg.device {
-            info!("WinUSB thread started for device {:04x}:{:04x}", vid, pid);
-            loop {
-                let (handle, endpoints) = loop {
-                    match initialize_winusb_device(vid, pid) {
-                        Ok(p) => break p,
-                        Err(e) => {
-                            warn!(
-                                "Could not initialize WinUSB device: {}. Retrying in 5 seconds...",
-                                e
-                            );
-                            thread::sleep(Duration::from_secs(5));
-                        }
-                    }
-                };
+#[derive(Clone)]
+pub struct WinUsbSource {
+    cfg: WinUsbConfig,
+    key_map: KeyMap,
+    status: SharedStatus,
+    raw_log: Option<RawLog>,
+    control: DeviceControl,
+}
 
-                let mut read_buf = [0u8; 64];
-                loop {
-                    match handle.read_bulk(
-                        endpoints.in_address,
-                        &mut read_buf,
-                        Duration::from_secs(1),
-                    ) {
-                        Ok(count) => {
-                            if count > 0 {
-                                // The python script just prints the hex values.
-                                // The logic here is copied from serial.rs to process the bytes.
-                                // This assumes the data format is the same.
-                                let key_code = read_buf[0];
-                                let key_code_hex = format!("0x{:02x}", key_code);
-
-                                let ev = if let Some(key_name) =
-                                    cfg.key_map.stateless.get(&key_code_hex)
-                                {
-                                    event::InputEvent::KeyPressed(key_name.clone())
-                                } else if let Some(key_name) =
-                                    cfg.key_map.stateful.get(&key_code_hex)
-                                {
-                                    event::InputEvent::KeyPressed(key_name.clone())
-                                } else if let Some(key_name) = cfg
-                                    .key_map
-                                    .stateful
-                                    .get(&format!("0x{:02x}", key_code - 0x80))
-                                {
-                                    event::InputEvent::KeyReleased(key_name.clone())
-                                } else {
-                                    warn!("Unknown key code {key_code_hex}.");
-                                    continue;
-                                };
-
-                                if ev_sender.send(ev).is_err() {
-                                    warn!("UI thread has been closed. Exiting WinUSB thread.");
-                                    // Before returning, it's good practice to release the interface.
-                                    handle.release_interface(1).ok();
-                                    return;
-                                }
-                            }
-                        }
-                        Err(rusb::Error::Timeout) => (), // Timeouts are expected, just continue.
-                        Err(e) => {
-                            error!("WinUSB read error: {}", e);
-                            // On error, release the interface and break the inner loop to re-initialize.
-                            handle.release_interface(1).ok();
-                            break;
-                        }
+impl WinUsbSource {
+    pub fn new(cfg: WinUsbConfig, key_map: KeyMap, status: SharedStatus) -> Self {
+        Self {
+            cfg,
+            key_map,
+            status,
+            raw_log: None,
+            control: DeviceControl::default(),
+        }
+    }
+
+    // Also writes every read to the raw log
+    pub fn with_raw_log(mut self, raw_log: Option<RawLog>) -> Self {
+        self.raw_log = raw_log;
+        self
+    }
+
+    // Reinit requests through `control` reconnect to the device
+    pub fn with_control(mut self, control: DeviceControl) -> Self {
+        self.control = control;
+        self
+    }
+}
+
+// A claimed interface, recording every read for the diagnostics and the raw log
+struct UsbStream {
+    handle: DeviceHandle<Context>,
+    endpoints: Endpoints,
+    interface: u8,
+    read_timeout: Duration,
+    buffer: ReadBuffer,
+    status: SharedStatus,
+    raw_log: Option<RawLog>,
+}
+
+impl ByteStream for UsbStream {
+    fn read_byte(&mut self) -> io::Result<Option<u8>> {
+        self.buffer.next_byte(|buf| {
+            let count =
+                match self
+                    .handle
+                    .read_bulk(self.endpoints.in_address, buf, self.read_timeout)
+                {
+                    Ok(count) => count,
+                    // Timeouts are expected, just continue.
+                    Err(rusb::Error::Timeout) => 0,
+                    // a garbled transfer, the next one is read as usual
+                    Err(e @ rusb::Error::Overflow) => {
+                        return Err(io::Error::new(io::ErrorKind::InvalidData, e));
+                    }
+                    Err(e @ rusb::Error::Interrupted) => {
+                        return Err(io::Error::new(io::ErrorKind::Interrupted, e));
                     }
+                    Err(e) => return Err(io::Error::other(e)),
+                };
+            if count > 0 {
+                self.status.lock().unwrap().record_frame(&buf[..count]);
+                if let Some(raw_log) = &self.raw_log {
+                    raw_log.record(&buf[..count]);
                 }
             }
-        } else {
-            panic!("Invalid state");
-        }
-    });
+            Ok(count)
+        })
+    }
+}
+
+impl Drop for UsbStream {
+    // the interface is free again for the next connection
+    fn drop(&mut self) {
+        self.handle.release_interface(self.interface).ok();
+    }
+}
+
+impl EventSource for WinUsbSource {
+    fn run(self, ev_sender: Sender<SourcedEvent>) {
+        let WinUsbConfig {
+            vid,
+            pid,
+            interface,
+            config_index,
+            read_timeout_ms,
+            init_retries,
+            init_retry_delay_ms,
+            skip_init,
+            handshake,
+            handshake_timeout_ms,
+            prefix,
+            ..
+        } = self.cfg;
+        info!("WinUSB thread started for device {:04x}:{:04x}", vid, pid);
+        let init = Init {
+            retries: init_retries,
+            retry_delay: Duration::from_millis(init_retry_delay_ms),
+            skip: skip_init,
+            handshake: &handshake,
+            handshake_timeout: Duration::from_millis(handshake_timeout_ms),
+            read_timeout: Duration::from_millis(read_timeout_ms),
+        };
+        let connect = || {
+            let (handle, endpoints, response) =
+                initialize_winusb_device(vid, pid, config_index, interface, &init)?;
+            // without a handshake, any response to the init command shows up as the first frame
+            self.status.lock().unwrap().initialized(response);
+            Ok(UsbStream {
+                handle,
+                endpoints,
+                interface,
+                read_timeout: Duration::from_millis(read_timeout_ms),
+                buffer: ReadBuffer::default(),
+                status: self.status.clone(),
+                raw_log: self.raw_log.clone(),
+            })
+        };
+        device::run(
+            "WinUSB",
+            prefix.as_deref(),
+            connect,
+            &self.key_map,
+            &ev_sender,
+            Duration::from_secs(5),
+            &self.control,
+        );
+    }
 }